@@ -0,0 +1,165 @@
+//! Benchmarks of rotation estimation on a synthetic motion field.
+//!
+//! The previous implementation, which takes numeric derivatives by reprojecting every point, is
+//! kept here for comparison. It is adapted to the body frame conventions, but otherwise unchanged.
+//! Note that its ransac always tests every hypothesis, while the estimator stops once confident.
+
+use almeida_estimator::AlmeidaEstimator;
+use criterion::*;
+use nalgebra as na;
+use ofps::frames;
+use ofps::prelude::v1::*;
+use rand::seq::SliceRandom;
+
+const EPS: f32 = 0.001 * std::f32::consts::PI / 180.0;
+const ALPHA: f32 = 0.5;
+
+/// Motion of a 50x50 grid of points, as seen by a rotating camera.
+fn rotation_field(camera: &StandardCamera) -> Vec<MotionEntry> {
+    let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+    let rot = frames::from_roll_pitch_yaw(0.5f32.to_radians(), 1f32.to_radians(), 0.2);
+    let rot_view = frames::view_matrix(na::Point3::origin(), rot);
+
+    (0..50)
+        .flat_map(|x| (0..50).map(move |y| (x, y)))
+        .map(|(x, y)| na::Point2::new(x as f32 / 50.0, y as f32 / 50.0))
+        .map(|p| {
+            let world = camera.unproject(p, view.transpose());
+            (p, camera.project(world, rot_view) - p)
+        })
+        .filter(|(p, _)| (p - na::Point2::new(0.5, 0.5)).magnitude() <= 0.71)
+        .collect()
+}
+
+/// Build the estimator through its properties, like plugin users do.
+fn estimator(use_ransac: bool) -> AlmeidaEstimator {
+    let mut estimator = AlmeidaEstimator::default();
+
+    for (name, prop) in estimator.props_mut() {
+        match (name, prop) {
+            ("Use ransac", PropertyMut::Bool(v)) => *v = use_ransac,
+            ("Ransac iters", PropertyMut::Usize(mut v)) => *v = 100,
+            _ => {}
+        }
+    }
+
+    estimator
+}
+
+/// Previous implementation of least squares rotation fitting.
+fn legacy_solve_given(input: &[MotionEntry], camera: &StandardCamera) -> na::UnitQuaternion<f32> {
+    let roll = |pos| camera.delta(pos, frames::from_roll_pitch_yaw(EPS, 0.0, 0.0).into());
+    let pitch = |pos| camera.delta(pos, frames::from_roll_pitch_yaw(0.0, EPS, 0.0).into());
+    let yaw = |pos| camera.delta(pos, frames::from_roll_pitch_yaw(0.0, 0.0, EPS).into());
+
+    let limit = (15.0 / ALPHA).ceil() as usize;
+
+    let mut rotation = na::UnitQuaternion::identity();
+
+    for i in 0..limit {
+        let alpha = if i == limit - 1 { 1.0 } else { ALPHA };
+
+        let rotm = rotation.to_homogeneous();
+
+        let motion = input
+            .iter()
+            .map(|&(pos, motion)| {
+                let delta = camera.delta(pos, rotm);
+                [motion - delta, roll(pos), pitch(pos), yaw(pos)]
+            })
+            .collect::<Vec<_>>();
+
+        let dot = |a: usize, b: usize| motion.iter().map(|v| v[a].dot(&v[b])).sum::<f32>();
+
+        let a = na::Matrix3::from_fn(|r, c| dot(r + 1, c + 1));
+        let b = na::Vector3::from_fn(|r, _| dot(r + 1, 0));
+
+        let model = a.lu().solve(&b).unwrap_or_default() * EPS * alpha;
+
+        let roll = frames::from_roll_pitch_yaw(model.x, 0.0, 0.0);
+        let pitch = frames::from_roll_pitch_yaw(0.0, model.y, 0.0);
+        let yaw = frames::from_roll_pitch_yaw(0.0, 0.0, model.z);
+
+        rotation *= pitch * roll * yaw;
+    }
+
+    rotation.inverse()
+}
+
+/// Previous, single threaded implementation of RANSAC rotation fitting.
+fn legacy_solve_ransac(
+    field: &[MotionEntry],
+    camera: &StandardCamera,
+    num_iters: usize,
+    target_delta: f32,
+    num_samples: usize,
+) -> na::UnitQuaternion<f32> {
+    let mut best_inliers = vec![];
+    let target_delta = target_delta.to_radians();
+
+    let rng = &mut rand::thread_rng();
+
+    for _ in 0..num_iters {
+        let samples = field.choose_multiple(rng, 3).copied().collect::<Vec<_>>();
+
+        let fit = legacy_solve_given(&samples, camera);
+
+        let mat = fit.inverse().to_homogeneous();
+
+        let inliers = field
+            .choose_multiple(rng, num_samples)
+            .copied()
+            .filter(|&(pos, vec)| {
+                let delta = camera.delta(pos, mat);
+                let angle = camera.point_angle(pos + delta);
+                let cosang = na::matrix![angle.x.cos(); angle.y.cos()];
+                (vec - delta).component_mul(&cosang).magnitude_squared()
+                    <= target_delta * target_delta
+            })
+            .collect::<Vec<_>>();
+
+        if inliers.len() > best_inliers.len() {
+            best_inliers = inliers;
+        }
+    }
+
+    if best_inliers.len() >= 3 {
+        legacy_solve_given(&best_inliers, camera)
+    } else {
+        Default::default()
+    }
+}
+
+fn solve(c: &mut Criterion) {
+    let camera = StandardCamera::new(1.0, 90.0);
+    let entries = rotation_field(&camera);
+    let vectors = entries.iter().copied().collect::<MotionVectors>();
+
+    let mut group = c.benchmark_group("almeida_rotation");
+    group.sample_size(10);
+
+    for use_ransac in [false, true] {
+        let name = if use_ransac { "ransac" } else { "given" };
+
+        let mut est = estimator(use_ransac);
+
+        group.bench_function(BenchmarkId::new("closed_form", name), |b| {
+            b.iter(|| est.estimate(&vectors, &camera, None).unwrap())
+        });
+
+        group.bench_function(BenchmarkId::new("legacy", name), |b| {
+            b.iter(|| {
+                if use_ransac {
+                    legacy_solve_ransac(&entries, &camera, 100, 0.05, 1000)
+                } else {
+                    legacy_solve_given(&entries, &camera)
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, solve);
+criterion_main!(benches);
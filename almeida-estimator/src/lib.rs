@@ -1,49 +1,302 @@
 //! # Implementation of "Robust Estimation of Camera Motion Using Optical Flow Models".
 //!
-//! The following estimator produces relatively accurate rotation estimates, with no translational
-//! output.
+//! The following estimator produces relatively accurate rotation estimates and, optionally, the
+//! direction of translation and camera zoom.
 //!
 //! This is not a blind reimplementation of the paper - it contains several improvements to the
 //! methods used.
 
 use nalgebra as na;
+use ofps::frames;
 use ofps::prelude::v1::*;
 use rand::seq::SliceRandom;
+use rayon::prelude::*;
+
+mod self_calibration;
+
+pub use self_calibration::{FovCalibration, FovCalibrator};
 
 ofps::define_descriptor!(almeida, Estimator, |_| Ok(Box::new(
     AlmeidaEstimator::default()
 )));
 
-const EPS: f32 = 0.001 * std::f32::consts::PI / 180.0;
 const ALPHA: f32 = 0.5;
 
-pub trait MotionModel {
+/// Probability of having drawn an outlier free sample, after which ransac stops early.
+const RANSAC_CONFIDENCE: f32 = 0.999;
+
+/// Motion entry along with its weight.
+type WeightedEntry = (MotionEntry, f32);
+
+/// Derivatives of screen-space motion of a point with respect to the model parameters.
+///
+/// Columns correspond to roll, pitch, yaw, zoom and translation along x, y and z axes.
+pub type MotionJacobian = na::SMatrix<f32, 2, 7>;
+
+/// Screen-space motion of points caused by infinitesimal camera rotation, translation and zoom.
+///
+/// This is implemented for every [`CameraModel`]. The derivatives are computed in closed form
+/// through [`CameraModel::project_jacobian`], thus they stay small across the seam of screens
+/// that wrap around. Use [`CameraModel::motion_scale`] to compare them across the screen.
+///
+/// Translational motion depends on the distance of the points, which is unknown. Thus, the
+/// points are assumed to be at unit distance, and only the direction of translation, that is, the
+/// focus of expansion, is meaningful.
+pub trait MotionModel: CameraModel {
     fn roll(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
 
     fn pitch(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
 
     fn yaw(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
 
-    fn point_angle(&self, p: na::Point2<f32>) -> na::Vector2<f32>;
+    fn right(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
+
+    fn forward(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
+
+    fn up(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
+
+    /// Calculate screen-space motion of a point caused by scaling the focal length by `1 + eps`.
+    fn zoom(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32>;
+
+    /// Calculate derivatives of screen-space motion of a point for all model parameters at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen space coordinates of the point.
+    fn jacobian(&self, coords: na::Point2<f32>) -> MotionJacobian;
+
+    /// Calculate screen-space motion of a point at unit distance being rotated and then moved.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen space coordinates of the point.
+    /// * `rotation` - rotation of the point, in the [body frame](frames::Frame::Body).
+    /// * `translation` - translation of the point, in the [body frame](frames::Frame::Body).
+    fn motion(
+        &self,
+        coords: na::Point2<f32>,
+        rotation: na::UnitQuaternion<f32>,
+        translation: na::Vector3<f32>,
+    ) -> na::Vector2<f32>;
 }
 
-impl MotionModel for StandardCamera {
+impl<T: CameraModel + ?Sized> MotionModel for T {
     fn roll(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
-        // We use different camera axis compared to what nalgebra considers as RPY
-        self.delta(coords, na::Matrix4::from_euler_angles(0.0, eps, 0.0))
+        self.jacobian(coords).column(0) * eps
     }
 
     fn pitch(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
-        self.delta(coords, na::Matrix4::from_euler_angles(eps, 0.0, 0.0))
+        self.jacobian(coords).column(1) * eps
     }
 
     fn yaw(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
-        self.delta(coords, na::Matrix4::from_euler_angles(0.0, 0.0, -eps))
+        self.jacobian(coords).column(2) * eps
+    }
+
+    fn zoom(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
+        self.jacobian(coords).column(3) * eps
+    }
+
+    fn right(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
+        self.jacobian(coords).column(4) * eps
     }
 
-    fn point_angle(&self, p: na::Point2<f32>) -> na::Vector2<f32> {
-        StandardCamera::point_angle(self, p)
+    fn forward(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
+        self.jacobian(coords).column(5) * eps
     }
+
+    fn up(&self, coords: na::Point2<f32>, eps: f32) -> na::Vector2<f32> {
+        self.jacobian(coords).column(6) * eps
+    }
+
+    fn jacobian(&self, coords: na::Point2<f32>) -> MotionJacobian {
+        let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+
+        let world = self.unproject(coords, view.transpose());
+        let projection = self.project_jacobian(world, view);
+
+        // Infinitesimal rotation around an axis moves the point along `axis x point`. Roll,
+        // pitch and yaw rotate around y, x and z axes of the body frame respectively.
+        let rotation = [na::Vector3::y(), na::Vector3::x(), na::Vector3::z()]
+            .map(|axis| projection * axis.cross(&world.coords));
+
+        // Zoom scales normalised coordinates of the point.
+        let p = self.screen_to_normalised(coords);
+        let zoom = self.intrinsics().fixed_slice::<2, 2>(0, 0) * p.coords;
+
+        // Translation is scaled by the distance of the point, as if it was at unit distance.
+        let dist = world.coords.norm();
+        let translation = [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()]
+            .map(|axis| projection * axis * dist);
+
+        MotionJacobian::from_columns(&[
+            rotation[0],
+            rotation[1],
+            rotation[2],
+            zoom,
+            translation[0],
+            translation[1],
+            translation[2],
+        ])
+    }
+
+    fn motion(
+        &self,
+        coords: na::Point2<f32>,
+        rotation: na::UnitQuaternion<f32>,
+        translation: na::Vector3<f32>,
+    ) -> na::Vector2<f32> {
+        let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+
+        let world = self.unproject(coords, view.transpose());
+
+        // Scale translation by the distance of the point, as if it was at unit distance. This
+        // keeps the point at the distance it was unprojected to, where projection is accurate.
+        let world = rotation * world + translation * world.coords.norm();
+
+        self.wrap_delta(self.project(world, view) - coords)
+    }
+}
+
+/// Terms of the optical flow model that are fitted along with rotation.
+#[derive(Clone, Copy, Default)]
+struct ModelTerms {
+    translation: bool,
+    zoom: bool,
+}
+
+impl ModelTerms {
+    /// Get the indices of fitted parameters within the full parameter vector.
+    ///
+    /// The full vector consists of roll, pitch, yaw, zoom and translation along x, y and z.
+    fn params(&self) -> Vec<usize> {
+        let zoom = Some(3).filter(|_| self.zoom);
+        let translation = [4, 5, 6].into_iter().filter(|_| self.translation);
+        [0, 1, 2]
+            .into_iter()
+            .chain(zoom)
+            .chain(translation)
+            .collect()
+    }
+
+    /// Get the mask of fitted parameters within the full parameter vector.
+    fn mask(&self) -> na::SVector<f32, 7> {
+        let mut mask = na::SVector::zeros();
+
+        for p in self.params() {
+            mask[p] = 1.0;
+        }
+
+        mask
+    }
+
+    /// Get the minimum number of motion vectors that constrain the model.
+    fn min_samples(&self) -> usize {
+        self.params().len().div_ceil(2)
+    }
+}
+
+/// Motion of points relative to the camera, in the [body frame](frames::Frame::Body).
+///
+/// Translation is that of points at unit distance, while zoom is the ratio of the new focal
+/// length to the old one.
+#[derive(Clone, Copy)]
+struct PointMotion {
+    rotation: na::UnitQuaternion<f32>,
+    translation: na::Vector3<f32>,
+    zoom: f32,
+}
+
+impl Default for PointMotion {
+    fn default() -> Self {
+        Self {
+            rotation: Default::default(),
+            translation: Default::default(),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl PointMotion {
+    /// Get the view matrix that moves points at unit distance by this model.
+    ///
+    /// # Arguments
+    ///
+    /// * `view` - view matrix of the camera at the origin of the body frame.
+    fn view(&self, view: &na::Matrix4<f32>) -> na::Matrix4<f32> {
+        view * na::Isometry3::from_parts(self.translation.into(), self.rotation).to_homogeneous()
+    }
+
+    /// Calculate screen-space motion of a sample under this model.
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - camera model.
+    /// * `sample` - sample to move.
+    /// * `view` - view matrix of the model, as returned by [`PointMotion::view`].
+    fn delta<M: MotionModel + ?Sized>(
+        &self,
+        camera: &M,
+        sample: &Sample,
+        view: &na::Matrix4<f32>,
+    ) -> na::Vector2<f32> {
+        // Same as `MotionModel::motion`, but without unprojecting the point again.
+        let mut p = camera.project(sample.world, *view);
+
+        if self.zoom != 1.0 {
+            // Zoom applies to the image of the new camera, after it has moved.
+            p = camera.normalised_to_screen(camera.screen_to_normalised(p) * self.zoom);
+        }
+
+        camera.wrap_delta(p - sample.pos)
+    }
+}
+
+/// Motion vector prepared for fitting.
+#[derive(Clone, Copy)]
+struct Sample {
+    pos: na::Point2<f32>,
+    motion: na::Vector2<f32>,
+    weight: f32,
+    /// Point unprojected into the [body frame](frames::Frame::Body), at unit distance.
+    world: na::Point3<f32>,
+    /// Cosines of the [point angle](CameraModel::point_angle), which scale the inlier error.
+    cos_angle: na::Vector2<f32>,
+    /// Scale that brings motion at the point to the units of the screen centre.
+    scale: na::Matrix2<f32>,
+    /// Derivatives of scaled motion of the point.
+    jacobian: MotionJacobian,
+}
+
+/// Precompute everything about motion vectors that does not depend on the model.
+///
+/// # Arguments
+///
+/// * `input` - weighted motion vectors.
+/// * `camera` - camera model.
+fn prepare<M: MotionModel + ?Sized>(input: &[WeightedEntry], camera: &M) -> Vec<Sample> {
+    let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+
+    input
+        .iter()
+        .map(|&((pos, motion), weight)| {
+            // Measure motion in the same units across the screen.
+            let scale = na::Matrix2::from_diagonal(&camera.motion_scale(pos));
+
+            let world = camera.unproject(pos, view.transpose());
+            let angle = camera.point_angle(pos);
+
+            Sample {
+                pos,
+                motion,
+                weight,
+                world: world.coords.normalize().into(),
+                cos_angle: angle.map(f32::cos),
+                scale,
+                jacobian: scale * camera.jacobian(pos),
+            }
+        })
+        .collect()
 }
 
 /// Motion estimator built on a research paper titled "Robust Estimation
@@ -53,7 +306,17 @@ impl MotionModel for StandardCamera {
 ///
 /// Jurandy Almeida, Rodrigo Minetto, Tiago A. Almeida, Ricardo da S. Torres, and Neucimar J. Leite.
 ///
-/// This estimator only produces rotational output, and no translation.
+/// By default, only rotation is estimated, like in the original paper. Translation needs to be
+/// enabled explicitly with the "Estimate translation" property. It can only be estimated up to
+/// scale, thus it is scaled to the `move_magnitude` hint, or unit length, if no hint is given. If
+/// the translational motion is below the inlier threshold, zero translation is returned.
+///
+/// Zoom is not estimated by default, because it is hard to tell apart from forward translation.
+/// When enabled, the focal length change of the last frame is reported through
+/// [`Estimator::get_zoom`]. It may also be tracked across frames, in which case later frames are
+/// estimated with the focal length of the given camera scaled by
+/// [`Estimator::get_focal_scale`]. The given camera itself is left unchanged - callers may use the
+/// scale to adjust its field of view.
 pub struct AlmeidaEstimator {
     /// True if ransac is used. False to perform least
     /// squares minimisation solution.
@@ -64,6 +327,16 @@ pub struct AlmeidaEstimator {
     inlier_angle: f32,
     /// Number of samples per each ransac iteration.
     ransac_samples: usize,
+    /// True if translation is fitted along with rotation.
+    translation: bool,
+    /// True if zoom is fitted along with rotation.
+    zoom: bool,
+    /// True if focal length of the camera is adjusted by estimated zoom.
+    track_zoom: bool,
+    /// Zoom estimated on the last frame.
+    last_zoom: f32,
+    /// Zoom accumulated over all frames.
+    focal_scale: f32,
 }
 
 impl Default for AlmeidaEstimator {
@@ -73,6 +346,20 @@ impl Default for AlmeidaEstimator {
             num_iters: 200,
             inlier_angle: 0.05,
             ransac_samples: 1000,
+            translation: false,
+            zoom: false,
+            track_zoom: false,
+            last_zoom: 1.0,
+            focal_scale: 1.0,
+        }
+    }
+}
+
+impl AlmeidaEstimator {
+    fn terms(&self) -> ModelTerms {
+        ModelTerms {
+            translation: self.translation,
+            zoom: self.zoom,
         }
     }
 }
@@ -93,6 +380,12 @@ impl Properties for AlmeidaEstimator {
                 "Ransac samples",
                 PropertyMut::usize(&mut self.ransac_samples, 100, 16000),
             ),
+            (
+                "Estimate translation",
+                PropertyMut::bool(&mut self.translation),
+            ),
+            ("Estimate zoom", PropertyMut::bool(&mut self.zoom)),
+            ("Track zoom", PropertyMut::bool(&mut self.track_zoom)),
         ]
     }
 }
@@ -100,151 +393,292 @@ impl Properties for AlmeidaEstimator {
 impl Estimator for AlmeidaEstimator {
     fn estimate(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
-        _move_magnitude: Option<f32>,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
     ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
-        let rot = if self.use_ransac {
-            solve_ypr_ransac(
-                motion_vectors,
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+        let motion_vectors = motion_vectors
+            .entries()
+            .zip(motion_vectors.weights())
+            .collect::<Vec<_>>();
+
+        let zoomed;
+
+        // Screens that wrap around have no focal length to scale.
+        let camera = if self.zoom
+            && self.track_zoom
+            && self.focal_scale != 1.0
+            && !camera.wraps_horizontally()
+        {
+            zoomed = zoom_camera(camera, self.focal_scale);
+            &zoomed
+        } else {
+            camera
+        };
+
+        let samples = prepare(&motion_vectors, camera);
+
+        let model = if self.use_ransac {
+            solve_ransac(
+                &samples,
                 camera,
+                self.terms(),
                 self.num_iters,
                 self.inlier_angle,
                 self.ransac_samples,
             )
         } else {
-            solve_ypr_given(motion_vectors, camera)
+            solve_given(&samples, camera, self.terms())
+        };
+
+        self.last_zoom = model.zoom;
+        self.focal_scale *= model.zoom;
+
+        // Without enough translational motion, its direction is just noise.
+        let translation = if !self.translation
+            || translational_angle(&motion_vectors, camera, model.translation)
+                < self.inlier_angle.to_radians()
+        {
+            na::Vector3::zeros()
+        } else {
+            model.translation.normalize() * move_magnitude.unwrap_or(1.0)
         };
 
-        Ok((rot, na::Vector3::default()))
+        // We estimated how points move, not how the camera moves - take inverse.
+        let rotation = model.rotation.inverse();
+
+        Ok((rotation, -(rotation * translation)))
+    }
+
+    fn get_zoom(&self) -> Option<f32> {
+        Some(self.last_zoom).filter(|_| self.zoom)
+    }
+
+    fn get_focal_scale(&self) -> Option<f32> {
+        Some(self.focal_scale).filter(|_| self.zoom)
+    }
+}
+
+/// Build a pinhole camera with focal length of the given camera scaled.
+///
+/// # Arguments
+///
+/// * `camera` - camera to base the new one on.
+/// * `scale` - focal length scale.
+fn zoom_camera(camera: &dyn CameraModel, scale: f32) -> CalibratedCamera {
+    let mut intrinsics = camera.intrinsics();
+
+    for (r, c) in [(0, 0), (0, 1), (1, 1)] {
+        intrinsics[(r, c)] *= scale;
     }
+
+    CalibratedCamera::new(intrinsics).distortion(camera.get_distortion())
 }
 
-fn solve_ypr_given(input: &[MotionEntry], camera: &StandardCamera) -> na::UnitQuaternion<f32> {
-    let dot = |a: usize, b: usize| move |vecs: &[na::Vector2<f32>]| vecs[a].dot(&vecs[b]);
+/// Compute the weighted mean angle points move by due to translation.
+///
+/// # Arguments
+///
+/// * `input` - weighted motion vectors.
+/// * `camera` - camera model.
+/// * `translation` - translation of points at unit distance.
+fn translational_angle<M: MotionModel + ?Sized>(
+    input: &[WeightedEntry],
+    camera: &M,
+    translation: na::Vector3<f32>,
+) -> f32 {
+    let inv_view = na::Matrix4::identity();
+
+    let (angle, weight) = input
+        .iter()
+        .map(|&((pos, _), weight)| {
+            let motion = camera.motion(pos, Default::default(), translation);
+            let a = camera.unproject(pos, inv_view).coords;
+            let b = camera.unproject(pos + motion, inv_view).coords;
+            (a.cross(&b).norm().atan2(a.dot(&b)) * weight, weight)
+        })
+        .fold((0.0, 0.0), |(a, w), (a2, w2)| (a + a2, w + w2));
 
-    fn dot_map<T: Fn(&[na::Vector2<f32>]) -> f32>(
-        motion: &[(na::Point2<f32>, [na::Vector2<f32>; 4])],
-    ) -> (impl Fn(T) -> f32 + '_) {
-        move |dot| motion.iter().map(|(_, v)| dot(v)).sum::<f32>()
+    if weight > 0.0 {
+        angle / weight
+    } else {
+        0.0
     }
+}
+
+/// Fit point motion to the motion vectors using iterative least squares.
+///
+/// # Arguments
+///
+/// * `input` - prepared motion vectors.
+/// * `camera` - camera model.
+/// * `terms` - terms fitted along with rotation.
+fn solve_given<M: MotionModel + ?Sized>(
+    input: &[Sample],
+    camera: &M,
+    terms: ModelTerms,
+) -> PointMotion {
+    let mask = terms.mask();
 
     let limit = (15.0 / ALPHA).ceil() as usize;
 
-    let mut rotation = na::UnitQuaternion::identity();
+    // Derivatives are taken at the initial position of the points, thus the left hand side of
+    // normal equations stays constant throughout the optimisation.
+    let a = input
+        .iter()
+        .fold(na::SMatrix::<f32, 7, 7>::zeros(), |a, s| {
+            a + s.jacobian.tr_mul(&s.jacobian) * s.weight
+        });
+
+    // Parameters that are not fitted are decoupled from the rest, and stay at zero.
+    let a = a.component_mul(&(mask * mask.transpose()))
+        + na::SMatrix::from_diagonal(&mask.map(|m| 1.0 - m));
+
+    let decomp = a.lu();
+
+    let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+
+    let mut model = PointMotion::default();
 
     // Iterative optimisation loop.
     for i in 0..limit {
         let alpha = if i == limit - 1 { 1.0 } else { ALPHA };
 
-        let rotm = rotation.to_homogeneous();
+        let model_view = model.view(&view);
 
-        let motion = input
+        let b = input
             .iter()
-            .copied()
-            .map(|(pos, motion)| {
-                let delta = camera.delta(pos, rotm);
-                (
-                    pos + delta,
-                    [
-                        motion - delta,
-                        camera.roll(pos, EPS),
-                        camera.pitch(pos, EPS),
-                        camera.yaw(pos, EPS),
-                    ],
-                )
+            .fold(na::SVector::<f32, 7>::zeros(), |b, s| {
+                let residual = camera.wrap_delta(s.motion - model.delta(camera, s, &model_view));
+                b + s.jacobian.tr_mul(&(s.scale * residual)) * s.weight
             })
-            .collect::<Vec<_>>();
-
-        let a = na::Matrix3::from_iterator(
-            [
-                dot(1, 1),
-                dot(1, 2),
-                dot(1, 3),
-                dot(2, 1),
-                dot(2, 2),
-                dot(2, 3),
-                dot(3, 1),
-                dot(3, 2),
-                dot(3, 3),
-            ]
-            .iter()
-            .map(dot_map(&motion)),
-        );
-
-        let b = na::Vector3::from_iterator(
-            [dot(1, 0), dot(2, 0), dot(3, 0)]
-                .iter()
-                .map(dot_map(&motion)),
-        );
-
-        let decomp = a.lu();
+            .component_mul(&mask);
 
-        let model = decomp.solve(&b).unwrap_or_default();
-
-        let model = model * EPS * alpha;
+        let step = decomp.solve(&b).unwrap_or_default() * alpha;
 
         // Apply rotation in YRP order, as it is more correct.
 
-        let roll = na::UnitQuaternion::from_euler_angles(0.0, model.x, 0.0);
-        let pitch = na::UnitQuaternion::from_euler_angles(model.y, 0.0, 0.0);
-        let yaw = na::UnitQuaternion::from_euler_angles(0.0, 0.0, -model.z);
+        let roll = frames::from_roll_pitch_yaw(step[0], 0.0, 0.0);
+        let pitch = frames::from_roll_pitch_yaw(0.0, step[1], 0.0);
+        let yaw = frames::from_roll_pitch_yaw(0.0, 0.0, step[2]);
 
         let rot = pitch * roll * yaw;
 
-        rotation *= rot;
+        model.rotation *= rot;
+        model.zoom *= 1.0 + step[3];
+        model.translation += step.fixed_rows::<3>(4);
+
+        // Further steps would not change the model at single precision.
+        if step.amax() < f32::EPSILON {
+            break;
+        }
     }
 
-    // We estimated how points rotate, not how the camera rotates - take inverse.
-    rotation.inverse()
+    model
 }
 
-fn solve_ypr_ransac(
-    field: &[MotionEntry],
-    camera: &StandardCamera,
+/// Fit point motion to the motion vectors, ignoring outliers.
+///
+/// Hypotheses are evaluated in parallel. Fewer than `num_iters` of them are tested, if the best one
+/// is supported by enough samples to be confident in it.
+///
+/// # Arguments
+///
+/// * `field` - prepared motion vectors.
+/// * `camera` - camera model.
+/// * `terms` - terms fitted along with rotation.
+/// * `num_iters` - maximum number of random models to test.
+/// * `target_delta` - maximum error of inliers, in degrees.
+/// * `num_samples` - number of motion vectors to test each model against.
+fn solve_ransac<M: MotionModel + Sync + ?Sized>(
+    field: &[Sample],
+    camera: &M,
+    terms: ModelTerms,
     num_iters: usize,
     target_delta: f32,
     num_samples: usize,
-) -> na::UnitQuaternion<f32> {
-    let mut best_inliers = vec![];
+) -> PointMotion {
     let target_delta = target_delta.to_radians();
 
-    let rng = &mut rand::thread_rng();
+    let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
 
-    for _ in 0..num_iters {
-        let samples = field.choose_multiple(rng, 3).copied().collect::<Vec<_>>();
+    // The error is scaled at the start of the motion vector, which is close enough for inliers.
+    let is_inlier = |fit: &PointMotion, fit_view: &na::Matrix4<f32>, sample: &Sample| {
+        let delta = fit.delta(camera, sample, fit_view);
+        let vec = camera.wrap_delta(sample.motion - delta);
+        vec.component_mul(&sample.cos_angle).magnitude_squared() <= target_delta * target_delta
+    };
 
-        let fit = solve_ypr_given(&samples, camera);
+    // Evaluate a random hypothesis, returning its weight of inliers and of all tested samples.
+    let hypothesis = |_| {
+        let rng = &mut rand::thread_rng();
 
-        let motion = field
-            .choose_multiple(rng, num_samples)
+        let samples = field
+            .choose_multiple(rng, terms.min_samples())
             .copied()
             .collect::<Vec<_>>();
 
-        let mat = fit.inverse().to_homogeneous();
+        let fit = solve_given(&samples, camera, terms);
+        let fit_view = fit.view(&view);
 
-        let inliers = motion
-            .iter()
-            .copied()
-            .map(|(pos, vec)| {
-                let delta = camera.delta(pos, mat);
-                ((pos, vec), (pos + delta, vec - delta))
-            })
-            .filter(|(_, (sample, vec))| {
-                let angle = camera.point_angle(*sample);
-                let cosang = na::matrix![angle.x.cos(); angle.y.cos()];
-                vec.component_mul(&cosang).magnitude_squared() <= target_delta * target_delta
-            })
-            .map(|(a, _)| a)
-            .collect::<Vec<_>>();
+        // Prefer the fit supported by the most block area and confidence.
+        let (inliers, total) =
+            field
+                .choose_multiple(rng, num_samples)
+                .fold((0.0, 0.0), |(inliers, total), s| {
+                    if is_inlier(&fit, &fit_view, s) {
+                        (inliers + s.weight, total + s.weight)
+                    } else {
+                        (inliers, total + s.weight)
+                    }
+                });
+
+        (Some(fit), inliers, total)
+    };
+
+    let mut best = (None, 0.0, 0.0);
+    let mut needed = num_iters;
+    let mut done = 0;
+
+    // Only the weight of inliers is kept per hypothesis, the winning one is refitted afterwards.
+    // Hypotheses are tested in batches, until the inlier ratio of the best one shows that an
+    // outlier free sample has been drawn with enough confidence.
+    while done < needed {
+        let count = rayon::current_num_threads().min(needed - done);
+
+        best = (0..count)
+            .into_par_iter()
+            .map(hypothesis)
+            .chain([best])
+            .reduce(|| (None, 0.0, 0.0), |a, b| if b.1 > a.1 { b } else { a });
+
+        done += count;
+
+        let ratio = if best.2 > 0.0 { best.1 / best.2 } else { 0.0 };
+        let iters =
+            (1.0 - RANSAC_CONFIDENCE).ln() / (1.0 - ratio.powi(terms.min_samples() as i32)).ln();
 
-        if inliers.len() > best_inliers.len() {
-            best_inliers = inliers;
+        if iters.is_finite() {
+            needed = needed.min(iters.ceil() as usize);
         }
     }
 
-    if best_inliers.len() >= 3 {
-        solve_ypr_given(&best_inliers, camera)
+    let (best_fit, _, _) = best;
+
+    let best_inliers = best_fit
+        .map(|fit| {
+            let fit_view = fit.view(&view);
+            field
+                .choose_multiple(&mut rand::thread_rng(), num_samples)
+                .filter(|s| is_inlier(&fit, &fit_view, s))
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if best_inliers.len() >= terms.min_samples() {
+        solve_given(&best_inliers, camera, terms)
     } else {
         Default::default()
     }
@@ -257,7 +691,7 @@ pub mod tests {
     fn get_grid(
         grid_cnt_x: usize,
         grid_cnt_y: usize,
-        camera: &StandardCamera,
+        camera: &dyn CameraModel,
     ) -> Vec<na::Point3<f32>> {
         let mut new_points = vec![];
 
@@ -278,16 +712,12 @@ pub mod tests {
     }
 
     fn calc_view(rot: na::UnitQuaternion<f32>, pos: na::Point3<f32>) -> na::Matrix4<f32> {
-        na::Matrix4::look_at_rh(
-            &pos,
-            &(pos + rot.transform_vector(&na::Vector3::new(0.0, -1.0, 0.0))),
-            &rot.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0)),
-        )
+        frames::view_matrix(pos, rot)
     }
 
     fn project_grid<'a>(
         grid: impl IntoIterator<Item = &'a na::Point3<f32>>,
-        camera: &StandardCamera,
+        camera: &dyn CameraModel,
         view: na::Matrix4<f32>,
     ) -> Vec<na::Point2<f32>> {
         grid.into_iter().map(|&p| camera.project(p, view)).collect()
@@ -296,7 +726,7 @@ pub mod tests {
     fn calc_field(
         p1: impl IntoIterator<Item = na::Point2<f32>>,
         p2: impl IntoIterator<Item = na::Point2<f32>>,
-    ) -> Vec<MotionEntry> {
+    ) -> MotionVectors {
         let mid = na::Point2::new(0.5, 0.5);
         p1.into_iter()
             .zip(p2.into_iter())
@@ -305,11 +735,176 @@ pub mod tests {
             .collect()
     }
 
-    fn test_rot(mut estimator: AlmeidaEstimator) {
+    fn test_rot(mut estimator: AlmeidaEstimator, camera: &dyn CameraModel) {
+        let grid = get_grid(50, 50, camera);
+
+        for rot in [0.01f32, 0.1, 1.0, 10.0] {
+            let angles = [
+                (0.0, 0.0, 0.0),
+                (rot, 0.0, 0.0),
+                (0.0, rot, 0.0),
+                (0.0, 0.0, rot),
+                (rot, rot, 0.0),
+                (rot, 0.0, rot),
+                (0.0, rot, rot),
+                (rot, rot, rot),
+            ];
+
+            for q in angles.iter().map(|&(r, p, y)| {
+                frames::from_roll_pitch_yaw(r.to_radians(), p.to_radians(), y.to_radians())
+            }) {
+                let p1 = project_grid(
+                    &grid,
+                    camera,
+                    calc_view(Default::default(), Default::default()),
+                );
+                let p2 = project_grid(&grid, camera, calc_view(q, Default::default()));
+
+                let field = calc_field(p1, p2);
+
+                let (r, tr) = estimator.estimate(&field, camera, None).unwrap();
+
+                let delta = q.angle_to(&r).to_degrees();
+
+                assert_eq!(tr, na::Vector3::zeros());
+                assert_eq!(estimator.get_zoom(), None);
+
+                println!("E: {}", delta / rot);
+
+                assert!(
+                    delta < 0.1 * rot,
+                    "{:?} vs {:?}: {} > {}",
+                    frames::roll_pitch_yaw(&q),
+                    frames::roll_pitch_yaw(&r),
+                    delta,
+                    0.1 * rot
+                );
+            }
+        }
+    }
+
+    fn test_translation(mut estimator: AlmeidaEstimator, camera: &dyn CameraModel) {
+        let inv_view = calc_view(Default::default(), Default::default()).transpose();
+
+        // Points in front of the camera, at equal distance from it.
+        let grid = (0..50)
+            .flat_map(|x| (0..50).map(move |y| (x, y)))
+            .map(|(x, y)| na::Point2::new(x as f32 / 50.0, y as f32 / 50.0))
+            .map(|p| (camera.unproject(p, inv_view).coords.normalize() * 5.0).into())
+            .collect::<Vec<_>>();
+
+        let motions: [((f32, f32, f32), [f32; 3]); 4] = [
+            ((0.0, 0.0, 0.0), [0.0, 0.05, 0.0]),
+            ((0.0, 0.0, 0.5), [0.0, 0.05, 0.0]),
+            ((0.2, -0.3, 0.1), [0.01, 0.05, -0.01]),
+            ((-0.5, 0.2, 0.3), [-0.02, 0.1, 0.02]),
+        ];
+
+        for ((r, p, y), pos) in motions {
+            let q = frames::from_roll_pitch_yaw(r.to_radians(), p.to_radians(), y.to_radians());
+            let pos = na::Point3::from(pos);
+
+            let p1 = project_grid(
+                &grid,
+                camera,
+                calc_view(Default::default(), Default::default()),
+            );
+            let p2 = project_grid(&grid, camera, calc_view(q, pos));
+
+            let field = calc_field(p1, p2);
+
+            let (r, tr) = estimator.estimate(&field, camera, Some(2.0)).unwrap();
+
+            let delta = q.angle_to(&r).to_degrees();
+            let expected = pos.coords.normalize() * 2.0;
+
+            println!("E: {} {} {}", delta, tr, expected);
+
+            assert!(delta < 0.05, "{:?} vs {:?}", q, r);
+            assert!((tr - expected).norm() < 0.1, "{} vs {}", tr, expected);
+        }
+    }
+
+    fn test_zoom(mut estimator: AlmeidaEstimator) {
+        let base = StandardCamera::new(16.0 / 9.0, 60.0);
+        let grid = get_grid(50, 50, &base);
+
+        let step = frames::from_roll_pitch_yaw(0.0, 0.2f32.to_radians(), 0.5f32.to_radians());
+
+        let mut rot = na::UnitQuaternion::identity();
+        let mut focal_scale = 1.0;
+
+        for zoom in [1.02, 1.0, 0.97, 1.05] {
+            let p1 = project_grid(
+                &grid,
+                &zoom_camera(&base, focal_scale),
+                calc_view(rot, Default::default()),
+            );
+
+            rot = step * rot;
+            focal_scale *= zoom;
+
+            let p2 = project_grid(
+                &grid,
+                &zoom_camera(&base, focal_scale),
+                calc_view(rot, Default::default()),
+            );
+
+            let field = calc_field(p1, p2);
+
+            let (r, _) = estimator.estimate(&field, &base, None).unwrap();
+
+            let delta = step.angle_to(&r).to_degrees();
+
+            let (est_zoom, est_scale) = estimator
+                .get_zoom()
+                .zip(estimator.get_focal_scale())
+                .unwrap();
+
+            println!("E: {} {} {}", delta, est_zoom, zoom);
+
+            assert!(delta < 0.01, "{:?} vs {:?}", step, r);
+            assert!((est_zoom - zoom).abs() < 1e-3);
+            assert!((est_scale - focal_scale).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_rotation_default() {
+        let mut estimator = AlmeidaEstimator::default();
+        estimator.use_ransac = false;
+        test_rot(estimator, &StandardCamera::new(1.0, 90.0));
+    }
+
+    #[test]
+    fn test_rotation_ransac() {
+        let mut estimator = AlmeidaEstimator::default();
+        estimator.use_ransac = true;
+        estimator.num_iters = 100;
+        test_rot(estimator, &StandardCamera::new(1.0, 90.0));
+    }
+
+    /// Rotation estimates on the inputs of the original test suite - a camera looking towards `-y`
+    /// and rotated by `nalgebra` euler angles, rather than the body frame conventions.
+    ///
+    /// Estimates are in the body frame of the camera, which is turned around from the world, thus
+    /// roll and pitch come out negated.
+    fn test_rot_baseline(mut estimator: AlmeidaEstimator) {
         let camera = StandardCamera::new(1.0, 90.0);
 
+        let calc_view = |rot: na::UnitQuaternion<f32>, pos: na::Point3<f32>| {
+            na::Matrix4::look_at_rh(
+                &pos,
+                &(pos + rot.transform_vector(&na::Vector3::new(0.0, -1.0, 0.0))),
+                &rot.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0)),
+            )
+        };
+
         let grid = get_grid(50, 50, &camera);
 
+        let turn =
+            na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), std::f32::consts::PI);
+
         for rot in [0.01f32, 0.1, 1.0, 10.0] {
             let angles = [
                 (0.0, 0.0, 0.0),
@@ -340,14 +935,16 @@ pub mod tests {
 
                 let (r, _) = estimator.estimate(&field, &camera, None).unwrap();
 
-                let delta = q.angle_to(&r).to_degrees();
+                let expected = turn.inverse() * q * turn;
+
+                let delta = expected.angle_to(&r).to_degrees();
 
                 println!("E: {}", delta / rot);
 
                 assert!(
                     delta < 0.1 * rot,
                     "{:?} vs {:?}: {} > {}",
-                    q.euler_angles(),
+                    expected.euler_angles(),
                     r.euler_angles(),
                     delta,
                     0.1 * rot
@@ -357,17 +954,91 @@ pub mod tests {
     }
 
     #[test]
-    fn test_rotation_default() {
-        let mut estimator = AlmeidaEstimator::default();
-        estimator.use_ransac = false;
-        test_rot(estimator);
+    fn test_rotation_baseline_default() {
+        test_rot_baseline(AlmeidaEstimator {
+            use_ransac: false,
+            ..Default::default()
+        });
     }
 
     #[test]
-    fn test_rotation_ransac() {
-        let mut estimator = AlmeidaEstimator::default();
-        estimator.use_ransac = true;
-        estimator.num_iters = 100;
-        test_rot(estimator);
+    fn test_rotation_baseline_ransac() {
+        test_rot_baseline(AlmeidaEstimator {
+            num_iters: 100,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_rotation_calibrated() {
+        let estimator = AlmeidaEstimator {
+            use_ransac: false,
+            ..Default::default()
+        };
+        let camera = CalibratedCamera::new(na::matrix![
+            0.45, 0.0, 0.55;
+            0.0, 0.6, 0.42;
+            0.0, 0.0, 1.0
+        ]);
+        test_rot(estimator, &camera);
+    }
+
+    #[test]
+    fn test_rotation_equirectangular() {
+        let estimator = AlmeidaEstimator {
+            use_ransac: false,
+            ..Default::default()
+        };
+        test_rot(estimator, &EquirectangularCamera::new());
+    }
+
+    #[test]
+    fn test_translation_default() {
+        let estimator = AlmeidaEstimator {
+            use_ransac: false,
+            translation: true,
+            ..Default::default()
+        };
+        test_translation(estimator, &StandardCamera::new(1.0, 90.0));
+    }
+
+    #[test]
+    fn test_translation_ransac() {
+        let estimator = AlmeidaEstimator {
+            num_iters: 100,
+            translation: true,
+            ..Default::default()
+        };
+        test_translation(estimator, &StandardCamera::new(1.0, 90.0));
+    }
+
+    #[test]
+    fn test_translation_equirectangular() {
+        let estimator = AlmeidaEstimator {
+            use_ransac: false,
+            translation: true,
+            ..Default::default()
+        };
+        test_translation(estimator, &EquirectangularCamera::new());
+    }
+
+    #[test]
+    fn test_zoom_default() {
+        test_zoom(AlmeidaEstimator {
+            use_ransac: false,
+            zoom: true,
+            track_zoom: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_zoom_ransac() {
+        test_zoom(AlmeidaEstimator {
+            num_iters: 100,
+            zoom: true,
+            track_zoom: true,
+            ..Default::default()
+        });
     }
 }
@@ -0,0 +1,312 @@
+//! # Field-of-view self-calibration
+//!
+//! Camera rotation moves points across the screen differently depending on camera intrinsics -
+//! the wider the field of view, the more curved the motion. Given a sequence of rotation-dominant
+//! motion vectors, this module finds intrinsics under which the rotational model of the estimator
+//! explains the motion best.
+//!
+//! Translation, or motion of objects in the scene does not fit the model, thus calibration
+//! footage should primarily consist of the camera panning and tilting.
+
+use super::*;
+
+/// Estimates camera field of view from motion.
+#[derive(Clone, Copy, Debug)]
+pub struct FovCalibrator {
+    aspect: f32,
+    fov_range: (f32, f32),
+    principal_point: bool,
+    max_samples: usize,
+}
+
+/// Result of field-of-view self-calibration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FovCalibration {
+    /// Screen aspect ratio.
+    pub aspect: f32,
+    /// Estimated vertical field of view, in degrees.
+    pub fov_y: f32,
+    /// Estimated principal point, in screen-space coordinates.
+    pub principal_point: na::Point2<f32>,
+    /// Average squared residual of the rotational model, in screen-space units.
+    pub residual: f32,
+}
+
+impl FovCalibration {
+    /// Build a camera with the calibrated intrinsics.
+    pub fn camera(&self) -> CalibratedCamera {
+        camera(self.aspect, self.fov_y, self.principal_point)
+    }
+}
+
+/// Build a camera from its vertical field of view and principal point.
+fn camera(aspect: f32, fov_y: f32, principal_point: na::Point2<f32>) -> CalibratedCamera {
+    let fy = 0.5 / (fov_y.to_radians() / 2.0).tan();
+
+    CalibratedCamera::new(na::matrix![
+        fy / aspect, 0.0, principal_point.x;
+        0.0, fy, principal_point.y;
+        0.0, 0.0, 1.0
+    ])
+}
+
+/// Find the minimum of a unimodal function using golden-section search.
+fn golden_section(mut lo: f32, mut hi: f32, iters: usize, f: impl Fn(f32) -> f32) -> f32 {
+    const INV_PHI: f32 = 0.618_034;
+
+    let mut a = hi - (hi - lo) * INV_PHI;
+    let mut b = lo + (hi - lo) * INV_PHI;
+    let (mut fa, mut fb) = (f(a), f(b));
+
+    for _ in 0..iters {
+        if fa < fb {
+            hi = b;
+            b = a;
+            fb = fa;
+            a = hi - (hi - lo) * INV_PHI;
+            fa = f(a);
+        } else {
+            lo = a;
+            a = b;
+            fa = fb;
+            b = lo + (hi - lo) * INV_PHI;
+            fb = f(b);
+        }
+    }
+
+    (lo + hi) * 0.5
+}
+
+impl FovCalibrator {
+    /// Number of candidates evaluated in the initial field-of-view search.
+    const FOV_STEPS: usize = 28;
+
+    /// Number of golden-section iterations per refined parameter.
+    const REFINE_ITERS: usize = 12;
+
+    /// Maximum offset of the principal point from the centre of the screen.
+    const MAX_PRINCIPAL_OFFSET: f32 = 0.2;
+
+    /// Create a new calibrator
+    ///
+    /// # Arguments
+    ///
+    /// * `aspect` - screen aspect ratio.
+    pub fn new(aspect: f32) -> Self {
+        Self {
+            aspect,
+            fov_range: (10.0, 150.0),
+            principal_point: false,
+            max_samples: 256,
+        }
+    }
+
+    /// Set the range of vertical field of view to search in, in degrees.
+    pub fn fov_range(self, min: f32, max: f32) -> Self {
+        Self {
+            fov_range: (min, max),
+            ..self
+        }
+    }
+
+    /// Set whether the principal point should be estimated.
+    ///
+    /// If disabled, principal point is assumed to be at the centre of the screen.
+    pub fn principal_point(self, principal_point: bool) -> Self {
+        Self {
+            principal_point,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of motion vectors used per frame.
+    ///
+    /// Larger values improve accuracy at the cost of speed.
+    pub fn max_samples(self, max_samples: usize) -> Self {
+        Self {
+            max_samples,
+            ..self
+        }
+    }
+
+    /// Estimate camera intrinsics from a sequence of motion vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `frames` - motion vectors of consecutive frames, captured by a rotating camera.
+    pub fn calibrate<'a>(
+        &self,
+        frames: impl IntoIterator<Item = &'a MotionVectors>,
+    ) -> Result<FovCalibration> {
+        let frames = frames
+            .into_iter()
+            .map(|mv| self.samples(mv))
+            .filter(|s| s.len() >= 3 && s.iter().any(|((_, m), _)| *m != na::Vector2::zeros()))
+            .collect::<Vec<_>>();
+
+        if frames.is_empty() {
+            return Err(anyhow!("Not enough motion for calibration"));
+        }
+
+        let (min, max) = self.fov_range;
+
+        if !(min > 0.0 && min < max && max < 180.0) {
+            return Err(anyhow!("Invalid field of view range {min}-{max}"));
+        }
+
+        let mut pp = na::Point2::new(0.5, 0.5);
+        let residual = |fov_y, pp| self.residual(&frames, fov_y, pp);
+
+        // Coarse search over the whole range, then refine around the best candidate.
+        let step = (max - min) / (Self::FOV_STEPS - 1) as f32;
+
+        let mut fov_y = (0..Self::FOV_STEPS)
+            .map(|i| min + step * i as f32)
+            .map(|fov| (fov, residual(fov, pp)))
+            .fold((min, f32::INFINITY), |a, b| if b.1 < a.1 { b } else { a })
+            .0;
+
+        let refine_fov = |fov_y: f32, pp| {
+            golden_section(
+                (fov_y - step).max(min),
+                (fov_y + step).min(max),
+                Self::REFINE_ITERS,
+                |fov| residual(fov, pp),
+            )
+        };
+
+        fov_y = refine_fov(fov_y, pp);
+
+        if self.principal_point {
+            let (lo, hi) = (
+                0.5 - Self::MAX_PRINCIPAL_OFFSET,
+                0.5 + Self::MAX_PRINCIPAL_OFFSET,
+            );
+
+            // Coordinate descent, since the parameters are only loosely coupled.
+            for _ in 0..2 {
+                pp.x = golden_section(lo, hi, Self::REFINE_ITERS, |x| {
+                    residual(fov_y, na::Point2::new(x, pp.y))
+                });
+                pp.y = golden_section(lo, hi, Self::REFINE_ITERS, |y| {
+                    residual(fov_y, na::Point2::new(pp.x, y))
+                });
+                fov_y = refine_fov(fov_y, pp);
+            }
+        }
+
+        Ok(FovCalibration {
+            aspect: self.aspect,
+            fov_y,
+            principal_point: pp,
+            residual: residual(fov_y, pp),
+        })
+    }
+
+    /// Pick evenly spaced weighted samples of a frame.
+    fn samples(&self, motion_vectors: &MotionVectors) -> Vec<WeightedEntry> {
+        let stride = (motion_vectors.len() / self.max_samples.max(1)).max(1);
+
+        motion_vectors
+            .entries()
+            .zip(motion_vectors.weights())
+            .step_by(stride)
+            .filter(|(_, w)| *w > 0.0)
+            .collect()
+    }
+
+    /// Compute the residual of the rotational model under given intrinsics.
+    fn residual(&self, frames: &[Vec<WeightedEntry>], fov_y: f32, pp: na::Point2<f32>) -> f32 {
+        let camera = camera(self.aspect, fov_y, pp);
+
+        let (error, weight) = frames
+            .iter()
+            .flat_map(|frame| {
+                let rot = solve_given(&prepare(frame, &camera), &camera, Default::default())
+                    .rotation
+                    .to_homogeneous();
+                let camera = &camera;
+
+                frame.iter().map(move |&((pos, motion), weight)| {
+                    let residual = camera.wrap_delta(motion - camera.delta(pos, rot));
+                    (residual.norm_squared() * weight, weight)
+                })
+            })
+            .fold((0.0, 0.0), |(e, w), (e2, w2)| (e + e2, w + w2));
+
+        error / weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generate motion of a rotating camera.
+    fn rotating_camera(camera: &dyn CameraModel) -> Vec<MotionVectors> {
+        let rotations = [
+            (0.0, 0.0, 2.0),
+            (1.0, 0.0, 1.0),
+            (0.0, 2.0, 0.0),
+            (-1.5, 0.5, -1.0),
+        ];
+
+        rotations
+            .iter()
+            .map(|&(r, p, y)| {
+                let rot = na::Matrix4::from_euler_angles(
+                    f32::to_radians(r),
+                    f32::to_radians(p),
+                    f32::to_radians(y),
+                );
+
+                (0..8)
+                    .flat_map(|y| (0..8).map(move |x| (x, y)))
+                    .map(|(x, y)| na::Point2::new((x as f32 + 0.5) / 8.0, (y as f32 + 0.5) / 8.0))
+                    .map(|pos| (pos, camera.delta(pos, rot)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calibrate_fov() {
+        let frames = rotating_camera(&StandardCamera::new(16.0 / 9.0, 60.0));
+
+        let calibration = FovCalibrator::new(16.0 / 9.0).calibrate(&frames).unwrap();
+
+        assert!((calibration.fov_y - 60.0).abs() < 1.0, "{calibration:?}");
+        assert_eq!(calibration.principal_point, na::Point2::new(0.5, 0.5));
+
+        // A wrong guess explains the motion worse.
+        let wrong = FovCalibrator::new(16.0 / 9.0)
+            .fov_range(80.0, 120.0)
+            .calibrate(&frames)
+            .unwrap();
+        assert!(wrong.residual > calibration.residual);
+    }
+
+    #[test]
+    fn calibrate_principal_point() {
+        let truth = camera(1.0, 45.0, na::Point2::new(0.56, 0.46));
+        let frames = rotating_camera(&truth);
+
+        let calibration = FovCalibrator::new(1.0)
+            .principal_point(true)
+            .calibrate(&frames)
+            .unwrap();
+
+        assert!((calibration.fov_y - 45.0).abs() < 1.0, "{calibration:?}");
+        assert!(
+            (calibration.principal_point - na::Point2::new(0.56, 0.46)).norm() < 0.02,
+            "{calibration:?}"
+        );
+        assert!((calibration.camera().intrinsics() - truth.intrinsics()).norm() < 0.05);
+    }
+
+    #[test]
+    fn calibrate_no_motion() {
+        let frames = vec![MotionVectors::new()];
+        assert!(FovCalibrator::new(1.0).calibrate(&frames).is_err());
+    }
+}
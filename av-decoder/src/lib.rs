@@ -5,7 +5,7 @@ use ::core::ops::{Deref, DerefMut};
 use ::core::{ptr, slice};
 use c_str_macro::c_str;
 use ffmpeg_sys_next::*;
-use libc::c_int;
+use libc::{c_int, SEEK_CUR, SEEK_END, SEEK_SET};
 use log::*;
 use nalgebra as na;
 use ofps::prelude::v1::{ptrplus::*, Result, *};
@@ -71,14 +71,22 @@ impl<T: ?Sized> Drop for AvContext<T> {
     }
 }
 
-impl<T: Read + ?Sized> AvContext<T> {
+impl<T: InputStream + ?Sized> AvContext<T> {
     pub fn try_new(stream: Box<T>) -> Result<Self> {
         let mut buf = AvBuf::try_new(8196)?;
 
         let mut stream: Box<Box<T>> = stream.into();
 
+        // Only expose seeking to libav if the stream supports it. Otherwise libav would attempt
+        // to seek around the input while probing it.
+        let seek_callback = if stream.is_seekable() {
+            Some(Self::seek_callback as _)
+        } else {
+            None
+        };
+
         // SAFETY: Box<T> stream is being passed, which is the expected stream type in the
-        // read_callback function.
+        // read_callback and seek_callback functions.
         let avio_ctx = unsafe {
             avio_alloc_context(
                 buf.as_mut_ptr(),
@@ -87,7 +95,7 @@ impl<T: Read + ?Sized> AvContext<T> {
                 (&mut *stream) as *mut Box<T> as *mut _,
                 Some(Self::read_callback),
                 None,
-                None,
+                seek_callback,
             )
             .as_mut()
         }
@@ -151,6 +159,44 @@ impl<T: Read + ?Sized> AvContext<T> {
         }
     }
 
+    unsafe extern "C" fn seek_callback(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+        let stream = &mut *(opaque as *mut Box<T>);
+
+        let stream = match stream.as_seek() {
+            Some(stream) => stream,
+            None => return -1,
+        };
+
+        let whence = whence & !(AVSEEK_FORCE as c_int);
+
+        // libav may request the size of the stream without moving the cursor.
+        if whence & AVSEEK_SIZE as c_int != 0 {
+            let size = stream.stream_position().and_then(|pos| {
+                let size = stream.seek(SeekFrom::End(0))?;
+                stream.seek(SeekFrom::Start(pos))?;
+                Ok(size)
+            });
+
+            return size.map(|s| s as i64).unwrap_or(-1);
+        }
+
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(offset as u64),
+            SEEK_CUR => SeekFrom::Current(offset),
+            SEEK_END => SeekFrom::End(offset),
+            _ => return -1,
+        };
+
+        stream
+            .seek(pos)
+            .map(|r| r as i64)
+            .map_err(|e| {
+                error!("{}", e);
+                e
+            })
+            .unwrap_or(-1)
+    }
+
     pub fn dump_format(&mut self) {
         unsafe { av_dump_format(self.fmt_ctx, 0, std::ptr::null(), 0) };
     }
@@ -163,6 +209,15 @@ pub struct AvDecoder<T: ?Sized> {
     stream_idx: i32,
     framerate: f64,
     aspect_ratio: (usize, usize),
+    seekable: bool,
+    time_base: f64,
+    start_time: i64,
+    last_pts: Option<i64>,
+    /// Index of the next decoded frame. After seeking, this is an estimate.
+    decode_index: usize,
+    frame_info: FrameInfo,
+    frame_count: Option<usize>,
+    duration: Option<f64>,
     sws_av_frame: &'static mut AVFrame,
     sws_ctx: Option<&'static mut SwsContext>,
 }
@@ -222,8 +277,9 @@ impl<'a> DerefMut for RefFrame<'a> {
     }
 }
 
-impl<T: Read + ?Sized> AvDecoder<T> {
+impl<T: InputStream + ?Sized> AvDecoder<T> {
     pub fn try_new(stream: Box<T>) -> Result<Self> {
+        let seekable = stream.is_seekable();
         let av_ctx = AvContext::try_new(stream)?;
 
         let mut decoder: Option<&mut AVCodec> = None;
@@ -256,6 +312,28 @@ impl<T: Read + ?Sized> AvDecoder<T> {
             stream.time_base.den as f64 / stream.time_base.num as f64
         };
 
+        let time_base = stream.time_base.num as f64 / stream.time_base.den as f64;
+
+        let start_time = if stream.start_time != AV_NOPTS_VALUE {
+            stream.start_time
+        } else {
+            0
+        };
+
+        let duration = if stream.duration != AV_NOPTS_VALUE && stream.duration > 0 {
+            Some(stream.duration as f64 * time_base)
+        } else if av_ctx.fmt_ctx.duration != AV_NOPTS_VALUE && av_ctx.fmt_ctx.duration > 0 {
+            Some(av_ctx.fmt_ctx.duration as f64 / AV_TIME_BASE as f64)
+        } else {
+            None
+        };
+
+        let frame_count = if stream.nb_frames > 0 {
+            Some(stream.nb_frames as usize)
+        } else {
+            duration.map(|d| (d * framerate).round() as usize)
+        };
+
         match unsafe { avcodec_parameters_to_context(codec_ctx, stream.codecpar) } {
             e if e < 0 => {
                 unsafe { avcodec_free_context(codec_ctx.as_mut_ptr()) };
@@ -308,6 +386,14 @@ impl<T: Read + ?Sized> AvDecoder<T> {
             stream_idx,
             framerate,
             aspect_ratio: (0, 0),
+            seekable,
+            time_base,
+            start_time,
+            last_pts: None,
+            decode_index: 0,
+            frame_info: Default::default(),
+            frame_count,
+            duration,
             sws_av_frame,
             sws_ctx: None,
         })
@@ -332,6 +418,26 @@ impl<T: Read + ?Sized> AvDecoder<T> {
         if let Some(frame) = RefFrame::new(self.codec_ctx, self.av_frame)? {
             self.aspect_ratio = (frame.width as usize, frame.height as usize);
 
+            self.last_pts = Some(frame.best_effort_timestamp).filter(|&p| p != AV_NOPTS_VALUE);
+
+            self.frame_info = FrameInfo {
+                pts: self
+                    .last_pts
+                    .map(|pts| (pts - self.start_time) as f64 * self.time_base),
+                decode_index: self.decode_index,
+                picture_type: match frame.pict_type {
+                    AVPictureType::AV_PICTURE_TYPE_I => PictureType::I,
+                    AVPictureType::AV_PICTURE_TYPE_P => PictureType::P,
+                    AVPictureType::AV_PICTURE_TYPE_B => PictureType::B,
+                    _ => PictureType::Unknown,
+                },
+                keyframe: frame.key_frame != 0,
+                forward: false,
+                backward: false,
+            };
+
+            self.decode_index += 1;
+
             if let Some((out_frame, out_height)) = out_frame {
                 out_frame.clear();
 
@@ -406,6 +512,19 @@ impl<T: Read + ?Sized> AvDecoder<T> {
 
                 // TODO: use dst or src?
                 for mv in motion_vectors {
+                    // Negative source means the vector references a past frame.
+                    let source = match mv.source {
+                        s if s < 0 => {
+                            self.frame_info.forward = true;
+                            VectorSource::Forward
+                        }
+                        s if s > 0 => {
+                            self.frame_info.backward = true;
+                            VectorSource::Backward
+                        }
+                        _ => VectorSource::Unknown,
+                    };
+
                     let pos = na::Vector2::new(mv.src_x as f32, mv.src_y as f32)
                         .component_mul(&frame_norm)
                         .into();
@@ -415,8 +534,15 @@ impl<T: Read + ?Sized> AvDecoder<T> {
                             mv.motion_scale as f32,
                         ))
                         .component_mul(&-frame_norm);
-
-                    mf.push((pos, motion));
+                    let footprint =
+                        na::Vector2::new(mv.w as f32, mv.h as f32).component_mul(&frame_norm);
+
+                    mf.push(
+                        MotionVector::new(pos, motion)
+                            .footprint(footprint)
+                            .source(source)
+                            .flags(mv.flags as u64),
+                    );
                 }
 
                 Ok(true)
@@ -427,16 +553,57 @@ impl<T: Read + ?Sized> AvDecoder<T> {
             Ok(false)
         }
     }
+
+    /// Convert a presentation timestamp to a frame index.
+    fn pts_to_frame(&self, pts: i64) -> i64 {
+        ((pts - self.start_time) as f64 * self.time_base * self.framerate).round() as i64
+    }
+
+    /// Decode packets until the frame before `frame` has been output.
+    ///
+    /// The decoder must have just been flushed after seeking to a keyframe before `frame`.
+    fn decode_until(&mut self, frame: usize) -> Result<()> {
+        let mut packet = MaybeUninit::uninit();
+        let mut mf = MotionVectors::new();
+
+        loop {
+            match unsafe { av_read_frame(self.av_ctx.fmt_ctx, packet.as_mut_ptr()) } {
+                e if e < 0 => return Err(anyhow!("Failed to read frame ({})", e)),
+                _ => {
+                    let packet = unsafe { packet.assume_init_mut() };
+
+                    let ret = if packet.stream_index == self.stream_idx {
+                        self.last_pts = None;
+                        mf.clear();
+                        self.extract_mvs(packet, &mut mf, None)
+                            .map(|_| self.last_pts)
+                    } else {
+                        Ok(None)
+                    };
+
+                    unsafe { av_packet_unref(packet) };
+
+                    if let Some(pts) = ret? {
+                        if self.pts_to_frame(pts) + 1 >= frame as i64 {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
-impl<T: Read + ?Sized> Decoder for AvDecoder<T> {
+impl<T: InputStream + ?Sized> Decoder for AvDecoder<T> {
     fn process_frame(
         &mut self,
         mf: &mut MotionVectors,
         mut out_frame: Option<(&mut Vec<RGBA>, &mut usize)>,
+        frame_info: Option<&mut FrameInfo>,
         mut skip: usize,
     ) -> Result<bool> {
         let mut packet = MaybeUninit::uninit();
+        self.frame_info = Default::default();
         let mut reached_stream = false;
         let mut ret = false;
 
@@ -466,6 +633,10 @@ impl<T: Read + ?Sized> Decoder for AvDecoder<T> {
             }
         }
 
+        if let Some(frame_info) = frame_info {
+            *frame_info = self.frame_info;
+        }
+
         Ok(ret)
     }
 
@@ -476,4 +647,49 @@ impl<T: Read + ?Sized> Decoder for AvDecoder<T> {
     fn get_aspect(&self) -> Option<(usize, usize)> {
         Some(self.aspect_ratio)
     }
+
+    fn can_seek(&self) -> bool {
+        self.seekable
+    }
+
+    fn seek_to_frame(&mut self, frame: usize) -> Result<()> {
+        if !self.seekable {
+            return Err(anyhow!("Input stream is not seekable"));
+        }
+
+        // Seek to the keyframe before the frame preceding the target, so that the next
+        // decoded frame is the target one, with its motion vectors intact.
+        let target = frame.saturating_sub(1) as f64 / (self.framerate * self.time_base);
+        let ts = self.start_time + target.round() as i64;
+
+        match unsafe {
+            av_seek_frame(
+                self.av_ctx.fmt_ctx,
+                self.stream_idx,
+                ts,
+                AVSEEK_FLAG_BACKWARD as _,
+            )
+        } {
+            e if e < 0 => return Err(anyhow!("Failed to seek ({})", e)),
+            _ => {}
+        }
+
+        unsafe { avcodec_flush_buffers(self.codec_ctx) };
+
+        if frame > 0 {
+            self.decode_until(frame)?;
+        }
+
+        self.decode_index = frame;
+
+        Ok(())
+    }
+
+    fn frame_count(&self) -> Option<usize> {
+        self.frame_count
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.duration
+    }
 }
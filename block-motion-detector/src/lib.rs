@@ -10,10 +10,15 @@ ofps::define_descriptor!(block_motion, Detector, |_| Ok(Box::new(
 ///
 /// This detector splits up each frame to blocks that are of area `min_size / (subdivide ^ 2)` and
 /// checks average motion within each block to be at least of `target_motion` magnitude.
+///
+/// Motion vectors are accumulated into blocks using the kernel selected by `kernel_mode`, see
+/// [`DensifyKernel::NAMES`]. `kernel_radius` is only used by the gaussian kernel.
 pub struct BlockMotionDetection {
     pub min_size: f32,
     pub subdivide: usize,
     pub target_motion: f32,
+    pub kernel_mode: usize,
+    pub kernel_radius: f32,
 }
 
 impl Default for BlockMotionDetection {
@@ -22,6 +27,8 @@ impl Default for BlockMotionDetection {
             min_size: 0.05,
             subdivide: 3,
             target_motion: 0.003,
+            kernel_mode: 0,
+            kernel_radius: 1.0,
         }
     }
 }
@@ -41,29 +48,35 @@ impl Properties for BlockMotionDetection {
                 "Target motion",
                 PropertyMut::float(&mut self.target_motion, 0.0001, 0.1),
             ),
+            (
+                "Kernel",
+                PropertyMut::usize(&mut self.kernel_mode, 0, DensifyKernel::NAMES.len() - 1),
+            ),
+            (
+                "Kernel radius",
+                PropertyMut::float(&mut self.kernel_radius, 0.5, 8.0),
+            ),
         ]
     }
 }
 
 impl Detector for BlockMotionDetection {
-    fn detect_motion(&self, motion: &[MotionEntry]) -> Option<(usize, MotionField)> {
-        let motion = motion.iter().copied();
-
+    fn detect_motion(&self, motion: &MotionVectors) -> Option<(usize, MotionField)> {
         // Calculate the motion field size, rounded up.
         let block_width = self.min_size.sqrt() / self.subdivide as f32;
         let block_dim = (1.0 / block_width).ceil() as usize;
 
-        // Add all the motion to the field densifier.
-        let mut mf = MotionFieldDensifier::new(block_dim, block_dim);
-        motion.for_each(|(pos, motion)| {
-            mf.add_vector(pos, motion);
-        });
+        // Add all the motion to the field densifier, weighted by block area and confidence.
+        let kernel =
+            DensifyKernel::from_mode(self.kernel_mode, self.kernel_radius).unwrap_or_default();
+        let mut mf = MotionFieldDensifier::new(block_dim, block_dim).kernel(kernel);
+        mf.add_vectors(motion);
         let mf = MotionField::from(mf);
 
         let mut map = vec![vec![false; block_dim]; block_dim];
 
-        // Compute which blocks have motion
-        mf.iter()
+        // Compute which blocks have motion. Blocks without any vectors are skipped.
+        mf.iter_valid()
             .filter(|(_, _, motion)| motion.magnitude() >= self.target_motion)
             .for_each(|(x, y, _)| map[y][x] = true);
 
@@ -75,7 +88,7 @@ impl Detector for BlockMotionDetection {
             for x in 0..block_dim {
                 if map[y][x] {
                     let mut area = 0;
-                    let mut mf2 = MotionField::new(block_dim, block_dim);
+                    let mut mf2 = MotionField::new_empty(block_dim, block_dim);
 
                     map[y][x] = false;
                     let mut to_fill = vec![(x, y); 1];
@@ -96,7 +109,12 @@ impl Detector for BlockMotionDetection {
                             .map(|(x, y)| (x as usize, y as usize))
                         {
                             if map[y][x] {
-                                mf2.set_motion(x, y, mf.get_motion(x, y));
+                                mf2.set_motion_weighted(
+                                    x,
+                                    y,
+                                    mf.get_motion(x, y),
+                                    mf.get_weight(x, y),
+                                );
                                 to_fill.push((x, y));
                                 map[y][x] = false;
                             }
@@ -14,6 +14,9 @@ ofps::define_descriptor!(cv, Decoder, |args| CvDecoder::try_new(
 )
 .map(|d| Box::new(d) as _));
 
+/// Minimum Sobel response for a pixel to be part of the Gunnar-Farneback mask.
+const SOBEL_THRESHOLD: f32 = 20.0;
+
 pub struct CvDecoder {
     capture: VideoCapture,
     frame: Mat,
@@ -83,6 +86,7 @@ impl Decoder for CvDecoder {
         &mut self,
         mf: &mut MotionVectors,
         out_frame: Option<(&mut Vec<RGBA>, &mut usize)>,
+        frame_info: Option<&mut FrameInfo>,
         skip: usize,
     ) -> Result<bool> {
         let mut cnt = 0;
@@ -141,6 +145,17 @@ impl Decoder for CvDecoder {
             }
         }
 
+        if let Some(frame_info) = frame_info {
+            // Flow is computed against the previous frame, if there is one.
+            *frame_info = FrameInfo {
+                pts: self.capture.get(CAP_PROP_POS_MSEC).ok().map(|t| t / 1000.0),
+                decode_index: (self.capture.get(CAP_PROP_POS_FRAMES)? as usize).saturating_sub(1),
+                forward: self.gray.rows() == self.old_gray.rows()
+                    && self.gray.cols() == self.old_gray.cols(),
+                ..Default::default()
+            };
+        }
+
         if let Some((out_frame, out_height)) = out_frame {
             *out_height = self.frame.rows() as _;
             out_frame.clear();
@@ -216,7 +231,7 @@ impl Decoder for CvDecoder {
             opencv::imgproc::threshold(
                 &self.sobel,
                 &mut self.thresh,
-                20.0,
+                SOBEL_THRESHOLD as f64,
                 255.0,
                 opencv::imgproc::THRESH_BINARY,
             )?;
@@ -241,7 +256,8 @@ impl Decoder for CvDecoder {
             1f32 / self.gray.rows() as f32,
         );
 
-        let mut points = std::collections::BTreeSet::new();
+        // Confidence sum and vector count of each downsampled cell.
+        let mut points = std::collections::BTreeMap::new();
 
         let mut downsample = if self.process_fullres {
             MotionFieldDensifier::new(dxy.0, dxy.1)
@@ -251,13 +267,21 @@ impl Decoder for CvDecoder {
 
         for y in 0..self.gray.rows() {
             for x in 0..self.gray.cols() {
-                if !self.use_rlof {
+                let confidence = if self.use_rlof {
+                    1.0
+                } else {
                     let mask: &f32 = self.mask.at_2d(y, x).unwrap();
 
                     if *mask < 0.1 {
                         continue;
                     }
-                }
+
+                    // The mask is binary, take the confidence from the edge response of the
+                    // pixel itself, but never go below the mask threshold.
+                    let sobel: &f32 = self.sobel.at_2d(y, x)?;
+
+                    (sobel.abs().max(SOBEL_THRESHOLD) / 255.0).min(1.0)
+                };
 
                 let dir: &Point2f = self.flow.at_2d(y, x)?;
 
@@ -269,9 +293,17 @@ impl Decoder for CvDecoder {
                     na::Vector2::new(dir.x as f32, dir.y as f32).component_mul(&frame_norm);
 
                 if self.process_fullres {
-                    points.insert(downsample.add_vector(pos, motion));
+                    let cell = downsample.add_vector_weighted(pos, motion, confidence);
+                    let (sum, cnt) = points.entry(cell).or_insert((0.0, 0));
+                    *sum += confidence;
+                    *cnt += 1;
                 } else {
-                    mf.push((pos, motion));
+                    mf.push(
+                        MotionVector::new(pos, motion)
+                            .confidence(confidence)
+                            .footprint(frame_norm)
+                            .source(VectorSource::Forward),
+                    );
                 }
             }
         }
@@ -280,14 +312,19 @@ impl Decoder for CvDecoder {
 
         let frame_norm = na::Vector2::new(1f32 / dxy.0 as f32, 1f32 / dxy.1 as f32);
 
-        for (x, y) in points {
+        for ((x, y), (sum, cnt)) in points {
             let motion = downsampled.get_motion(x, y);
 
             let pos = na::Vector2::new(x as f32 + 0.5, y as f32 + 0.5)
                 .component_mul(&frame_norm)
                 .into();
 
-            mf.push((pos, motion));
+            mf.push(
+                MotionVector::new(pos, motion)
+                    .confidence(sum / cnt as f32)
+                    .footprint(frame_norm)
+                    .source(VectorSource::Forward),
+            );
         }
 
         Ok(true)
@@ -300,4 +337,41 @@ impl Decoder for CvDecoder {
     fn get_aspect(&self) -> Option<(usize, usize)> {
         Some((self.gray.cols() as _, self.gray.rows() as _))
     }
+
+    fn can_seek(&self) -> bool {
+        self.frame_count().is_some()
+    }
+
+    fn seek_to_frame(&mut self, frame: usize) -> Result<()> {
+        if !self.can_seek() {
+            return Err(anyhow!("Input stream is not seekable"));
+        }
+
+        // Flow is computed between consecutive frames, so position at the frame before the
+        // target one, and load it as the previous frame.
+        if !self
+            .capture
+            .set(CAP_PROP_POS_FRAMES, frame.saturating_sub(1) as f64)?
+        {
+            return Err(anyhow!("Failed to seek to frame {}", frame));
+        }
+
+        self.frame = Default::default();
+        self.gray = Default::default();
+        self.flow = Default::default();
+
+        if frame > 0 {
+            self.process_frame(&mut MotionVectors::new(), None, None, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn frame_count(&self) -> Option<usize> {
+        self.capture
+            .get(CAP_PROP_FRAME_COUNT)
+            .ok()
+            .filter(|&c| c > 0.0)
+            .map(|c| c as usize)
+    }
 }
@@ -0,0 +1,552 @@
+//! # Essential matrix estimation
+//!
+//! Implementation of Nistér's 5-point relative pose solver, using the Gröbner basis formulation
+//! by Stewénius et al. The solver works on bearing vectors rather than image coordinates, thus it
+//! is not restricted to pinhole cameras.
+//!
+//! Given rays `f1` and `f2` of the same point seen from two cameras, the essential matrix `E`
+//! satisfies `f2^T * E * f1 = 0`, where `E = [t]x * R`, and points transform from the first
+//! camera to the second as `X2 = R * X1 + t`.
+
+use nalgebra as na;
+
+/// Monomials of `x`, `y` and `z` up to degree 3, as exponent triplets.
+///
+/// Cubic monomials go first, so that eliminating them leaves the basis of the quotient ring -
+/// all monomials of degree 2 and less.
+const MONOMIALS: [(u8, u8, u8); 20] = [
+    (3, 0, 0),
+    (2, 1, 0),
+    (2, 0, 1),
+    (1, 2, 0),
+    (1, 1, 1),
+    (1, 0, 2),
+    (0, 3, 0),
+    (0, 2, 1),
+    (0, 1, 2),
+    (0, 0, 3),
+    (2, 0, 0),
+    (1, 1, 0),
+    (1, 0, 1),
+    (0, 2, 0),
+    (0, 1, 1),
+    (0, 0, 2),
+    (1, 0, 0),
+    (0, 1, 0),
+    (0, 0, 1),
+    (0, 0, 0),
+];
+
+/// Number of cubic monomials, and the size of the quotient ring basis.
+const CUBIC: usize = 10;
+
+/// Get the index of a monomial in [`MONOMIALS`].
+fn monomial((x, y, z): (u8, u8, u8)) -> usize {
+    MONOMIALS
+        .iter()
+        .position(|&m| m == (x, y, z))
+        .expect("monomial degree over 3")
+}
+
+/// Polynomial in `x`, `y` and `z` of degree up to 3.
+#[derive(Clone, Copy)]
+struct Poly([f64; 20]);
+
+impl Poly {
+    fn linear(x: f64, y: f64, z: f64, w: f64) -> Self {
+        let mut p = [0.0; 20];
+        p[16..].copy_from_slice(&[x, y, z, w]);
+        Self(p)
+    }
+}
+
+impl std::ops::Add for Poly {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a += b);
+        self
+    }
+}
+
+impl std::ops::Sub for Poly {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + rhs * -1.0
+    }
+}
+
+impl std::ops::Mul<f64> for Poly {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f64) -> Self {
+        self.0.iter_mut().for_each(|a| *a *= rhs);
+        self
+    }
+}
+
+impl std::ops::Mul for Poly {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mut out = [0.0; 20];
+
+        let terms = |p: Self| p.0.into_iter().zip(MONOMIALS).filter(|(c, _)| *c != 0.0);
+
+        for (a, (ax, ay, az)) in terms(self) {
+            for (b, (bx, by, bz)) in terms(rhs) {
+                out[monomial((ax + bx, ay + by, az + bz))] += a * b;
+            }
+        }
+
+        Self(out)
+    }
+}
+
+/// Compute essential matrices from 5 point correspondences.
+///
+/// Returns up to 10 candidate solutions, normalised to unit norm.
+///
+/// # Arguments
+///
+/// * `f1` - rays of the points in the first camera.
+/// * `f2` - rays of the same points in the second camera.
+pub fn five_point(f1: &[na::Vector3<f64>; 5], f2: &[na::Vector3<f64>; 5]) -> Vec<na::Matrix3<f64>> {
+    // Each correspondence gives a linear constraint on the row-major entries of E. Pad the
+    // system to a square matrix, so that the full right null space gets computed.
+    let mut q = na::SMatrix::<f64, 9, 9>::zeros();
+
+    for (i, (a, b)) in f1.iter().zip(f2).enumerate() {
+        let row = b * a.transpose();
+        q.row_mut(i)
+            .iter_mut()
+            .zip(row.transpose().iter())
+            .for_each(|(q, r)| *q = *r);
+    }
+
+    let v_t = match q.svd(false, true).v_t {
+        Some(v_t) => v_t,
+        None => return vec![],
+    };
+
+    // E = x * X + y * Y + z * Z + W, where X, Y, Z and W span the null space.
+    let basis =
+        [5, 6, 7, 8].map(|r| na::Matrix3::from_iterator(v_t.row(r).iter().copied()).transpose());
+
+    let e = [0, 1, 2].map(|r| {
+        [0, 1, 2].map(|c| {
+            let [x, y, z, w] = basis.map(|m| m[(r, c)]);
+            Poly::linear(x, y, z, w)
+        })
+    });
+
+    // Essential matrices have zero determinant, and satisfy 2 * E * E^T * E - tr(E * E^T) * E = 0.
+    let det = e[0][0] * (e[1][1] * e[2][2] - e[1][2] * e[2][1])
+        - e[0][1] * (e[1][0] * e[2][2] - e[1][2] * e[2][0])
+        + e[0][2] * (e[1][0] * e[2][1] - e[1][1] * e[2][0]);
+
+    let eet = [0, 1, 2]
+        .map(|r| [0, 1, 2].map(|c| e[r][0] * e[c][0] + e[r][1] * e[c][1] + e[r][2] * e[c][2]));
+    let trace = eet[0][0] + eet[1][1] + eet[2][2];
+
+    let constraints = (0..9).map(|i| {
+        let (r, c) = (i / 3, i % 3);
+        (eet[r][0] * e[0][c] + eet[r][1] * e[1][c] + eet[r][2] * e[2][c]) * 2.0 - trace * e[r][c]
+    });
+
+    let mut a = na::SMatrix::<f64, 10, 20>::zeros();
+
+    for (i, p) in std::iter::once(det).chain(constraints).enumerate() {
+        a.row_mut(i).iter_mut().zip(p.0).for_each(|(a, c)| *a = c);
+    }
+
+    // Express cubic monomials in terms of the quotient ring basis.
+    let reduced = match a
+        .fixed_columns::<CUBIC>(0)
+        .into_owned()
+        .lu()
+        .solve(&a.fixed_columns::<CUBIC>(CUBIC).into_owned())
+    {
+        Some(reduced) => reduced,
+        None => return vec![],
+    };
+
+    // Action matrix of multiplication by x. Its eigenvectors are the basis monomials evaluated
+    // at the solutions.
+    let mut action = na::SMatrix::<f64, CUBIC, CUBIC>::zeros();
+
+    for (i, &(x, y, z)) in MONOMIALS[CUBIC..].iter().enumerate() {
+        match monomial((x + 1, y, z)) {
+            m if m >= CUBIC => action[(i, m - CUBIC)] = 1.0,
+            m => action.set_row(i, &-reduced.row(m)),
+        }
+    }
+
+    let eigenvalues = match na::linalg::Schur::try_new(action, 1e-12, 10000) {
+        Some(schur) => schur.complex_eigenvalues(),
+        None => return vec![],
+    };
+
+    eigenvalues
+        .iter()
+        .filter(|l| l.im.abs() <= 1e-8 * (1.0 + l.re.abs()))
+        .filter_map(|l| {
+            let m = action - na::SMatrix::<f64, CUBIC, CUBIC>::identity() * l.re;
+            let b = m.svd(false, true).v_t?.row(CUBIC - 1).transpose();
+
+            if b[9].abs() < 1e-12 {
+                return None;
+            }
+
+            let (x, y, z) = (b[6] / b[9], b[7] / b[9], b[8] / b[9]);
+            let e = basis[0] * x + basis[1] * y + basis[2] * z + basis[3];
+            Some(e.normalize())
+        })
+        .collect()
+}
+
+/// Compute the signed Sampson residual of a correspondence.
+///
+/// This is the first-order approximation of the angular distance, in radians, rays need to be
+/// moved by to satisfy the epipolar constraint.
+///
+/// # Arguments
+///
+/// * `e` - essential matrix.
+/// * `f1` - unit ray of the point in the first camera.
+/// * `f2` - unit ray of the point in the second camera.
+pub fn sampson_residual(e: &na::Matrix3<f64>, f1: &na::Vector3<f64>, f2: &na::Vector3<f64>) -> f64 {
+    let ef1 = e * f1;
+    let etf2 = e.tr_mul(f2);
+    let err = f2.dot(&ef1);
+
+    // Gradients restricted to the tangent planes of the unit sphere.
+    let g1 = etf2 - f1 * f1.dot(&etf2);
+    let g2 = ef1 - f2 * f2.dot(&ef1);
+
+    err / (g1.norm_squared() + g2.norm_squared())
+        .sqrt()
+        .max(f64::MIN_POSITIVE)
+}
+
+/// Compute the Sampson error of a correspondence.
+///
+/// This is the square of [`sampson_residual`].
+pub fn sampson_error(e: &na::Matrix3<f64>, f1: &na::Vector3<f64>, f2: &na::Vector3<f64>) -> f64 {
+    sampson_residual(e, f1, f2).powi(2)
+}
+
+/// Build an essential matrix from relative camera pose.
+///
+/// # Arguments
+///
+/// * `r` - rotation from the first camera to the second.
+/// * `t` - translation from the first camera to the second.
+pub fn essential_from_pose(r: &na::Rotation3<f64>, t: &na::Vector3<f64>) -> na::Matrix3<f64> {
+    t.cross_matrix() * r.matrix()
+}
+
+/// Decompose an essential matrix into 4 possible rotation and translation pairs.
+///
+/// Translation is of unit length. Only one of the pairs places points in front of both cameras,
+/// see [`recover_pose`].
+///
+/// # Arguments
+///
+/// * `e` - essential matrix to decompose.
+pub fn decompose_essential(e: &na::Matrix3<f64>) -> [(na::Rotation3<f64>, na::Vector3<f64>); 4] {
+    let svd = e.svd(true, true);
+    let mut u = svd.u.unwrap();
+    let mut v_t = svd.v_t.unwrap();
+
+    if u.determinant() < 0.0 {
+        u = -u;
+    }
+
+    if v_t.determinant() < 0.0 {
+        v_t = -v_t;
+    }
+
+    let w = na::matrix![
+        0.0, -1.0, 0.0;
+        1.0, 0.0, 0.0;
+        0.0, 0.0, 1.0
+    ];
+
+    let r1 = na::Rotation3::from_matrix_unchecked(u * w * v_t);
+    let r2 = na::Rotation3::from_matrix_unchecked(u * w.transpose() * v_t);
+    let t = u.column(2).into_owned();
+
+    [(r1, t), (r1, -t), (r2, t), (r2, -t)]
+}
+
+/// Compute depths of a point along its rays in both cameras.
+///
+/// # Arguments
+///
+/// * `r` - rotation from the first camera to the second.
+/// * `t` - translation from the first camera to the second.
+/// * `f1` - unit ray of the point in the first camera.
+/// * `f2` - unit ray of the point in the second camera.
+pub fn depths(
+    r: &na::Rotation3<f64>,
+    t: &na::Vector3<f64>,
+    f1: &na::Vector3<f64>,
+    f2: &na::Vector3<f64>,
+) -> (f64, f64) {
+    let rf1 = r * f1;
+    let n = f2.cross(&rf1);
+    let d1 = -f2.cross(t).dot(&n) / n.norm_squared();
+    let d2 = f2.dot(&(rf1 * d1 + t));
+    (d1, d2)
+}
+
+/// Recover relative camera pose from an essential matrix.
+///
+/// Picks the decomposition that places the most points in front of both cameras. Returns
+/// rotation, unit translation, and whether each of the correspondences is in front of the
+/// cameras.
+///
+/// # Arguments
+///
+/// * `e` - essential matrix.
+/// * `rays` - unit ray pairs of the correspondences.
+pub fn recover_pose(
+    e: &na::Matrix3<f64>,
+    rays: &[(na::Vector3<f64>, na::Vector3<f64>)],
+) -> (na::Rotation3<f64>, na::Vector3<f64>, Vec<bool>) {
+    decompose_essential(e)
+        .into_iter()
+        .map(|(r, t)| {
+            let in_front = rays
+                .iter()
+                .map(|(f1, f2)| depths(&r, &t, f1, f2))
+                .map(|(d1, d2)| d1 > 0.0 && d2 > 0.0 && d1.is_finite())
+                .collect::<Vec<_>>();
+            (r, t, in_front)
+        })
+        .max_by_key(|(_, _, in_front)| in_front.iter().filter(|v| **v).count())
+        .unwrap()
+}
+
+/// Apply a pose update of 3 rotation and 2 translation parameters.
+///
+/// Translation is kept at unit length, thus it is updated along the tangent plane of the sphere.
+fn update_pose(
+    r: &na::Rotation3<f64>,
+    t: &na::Vector3<f64>,
+    delta: &na::Vector5<f64>,
+) -> (na::Rotation3<f64>, na::Vector3<f64>) {
+    let axis = if t.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::y()
+    };
+
+    let b1 = t.cross(&axis).normalize();
+    let b2 = t.cross(&b1);
+
+    (
+        r * na::Rotation3::new(delta.fixed_rows::<3>(0).into_owned()),
+        (t + b1 * delta[3] + b2 * delta[4]).normalize(),
+    )
+}
+
+/// Refine relative camera pose by minimising Sampson error.
+///
+/// Minimal solutions fit their samples exactly, which makes them sensitive to noise. This
+/// performs Levenberg-Marquardt optimisation of the pose over all given correspondences.
+///
+/// # Arguments
+///
+/// * `r` - initial rotation from the first camera to the second.
+/// * `t` - initial unit translation from the first camera to the second.
+/// * `rays` - unit ray pairs of the inlier correspondences.
+/// * `iters` - maximum number of iterations.
+pub fn refine_pose(
+    mut r: na::Rotation3<f64>,
+    mut t: na::Vector3<f64>,
+    rays: &[(na::Vector3<f64>, na::Vector3<f64>)],
+    iters: usize,
+) -> (na::Rotation3<f64>, na::Vector3<f64>) {
+    const STEP: f64 = 1e-6;
+
+    let cost = |r: &na::Rotation3<f64>, t: &na::Vector3<f64>| {
+        let e = essential_from_pose(r, t);
+        rays.iter()
+            .map(|(f1, f2)| sampson_error(&e, f1, f2))
+            .sum::<f64>()
+    };
+
+    let mut current = cost(&r, &t);
+    let mut lambda = 1e-3;
+
+    for _ in 0..iters {
+        // Central difference Jacobians of the residuals.
+        let perturbed = (0..5)
+            .map(|i| {
+                let delta = na::Vector5::from_fn(|j, _| if i == j { STEP } else { 0.0 });
+                let (r1, t1) = update_pose(&r, &t, &delta);
+                let (r2, t2) = update_pose(&r, &t, &-delta);
+                (essential_from_pose(&r1, &t1), essential_from_pose(&r2, &t2))
+            })
+            .collect::<Vec<_>>();
+
+        let e = essential_from_pose(&r, &t);
+        let mut jtj = na::Matrix5::zeros();
+        let mut jtr = na::Vector5::zeros();
+
+        for (f1, f2) in rays {
+            let res = sampson_residual(&e, f1, f2);
+            let j = na::Vector5::from_fn(|i, _| {
+                let (e1, e2) = &perturbed[i];
+                (sampson_residual(e1, f1, f2) - sampson_residual(e2, f1, f2)) / (2.0 * STEP)
+            });
+            jtj += j * j.transpose();
+            jtr += j * res;
+        }
+
+        // Damping also keeps translation in place when it is not observable.
+        let improved = loop {
+            let damped = jtj + na::Matrix5::from_diagonal(&jtj.diagonal()) * lambda;
+            let delta = match (damped + na::Matrix5::identity() * 1e-12).cholesky() {
+                Some(c) => -c.solve(&jtr),
+                None => break false,
+            };
+
+            let (r2, t2) = update_pose(&r, &t, &delta);
+            let new = cost(&r2, &t2);
+
+            if new < current {
+                let converged = current - new < current * 1e-10;
+                r = r2;
+                t = t2;
+                current = new;
+                lambda = (lambda * 0.1).max(1e-9);
+                break !converged;
+            }
+
+            lambda *= 10.0;
+
+            if lambda > 1e6 {
+                break false;
+            }
+        };
+
+        if !improved {
+            break;
+        }
+    }
+
+    (r, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> (na::Rotation3<f64>, na::Vector3<f64>, Vec<na::Point3<f64>>) {
+        let r = na::Rotation3::from_euler_angles(0.05, -0.1, 0.02);
+        let t = na::Vector3::new(0.3, -0.1, 0.2).normalize();
+
+        let points = [
+            (0.1, 0.2, -3.0),
+            (-0.5, 0.3, -4.0),
+            (0.7, -0.6, -2.5),
+            (-0.2, -0.4, -5.0),
+            (0.4, 0.5, -3.5),
+            (-0.8, 0.1, -6.0),
+            (0.0, -0.9, -4.5),
+        ]
+        .map(|(x, y, z)| na::Point3::new(x, y, z))
+        .to_vec();
+
+        (r, t, points)
+    }
+
+    fn rays(
+        r: &na::Rotation3<f64>,
+        t: &na::Vector3<f64>,
+        points: &[na::Point3<f64>],
+    ) -> Vec<(na::Vector3<f64>, na::Vector3<f64>)> {
+        points
+            .iter()
+            .map(|p| (p.coords.normalize(), (r * p.coords + t).normalize()))
+            .collect()
+    }
+
+    #[test]
+    fn five_point_solutions() {
+        let (r, t, points) = scene();
+        let rays = rays(&r, &t, &points);
+
+        let f1 = [0, 1, 2, 3, 4].map(|i| rays[i].0);
+        let f2 = [0, 1, 2, 3, 4].map(|i| rays[i].1);
+
+        let solutions = five_point(&f1, &f2);
+        assert!(!solutions.is_empty() && solutions.len() <= 10);
+
+        for e in &solutions {
+            for (f1, f2) in &rays[..5] {
+                assert!(f2.dot(&(e * f1)).abs() < 1e-9);
+            }
+        }
+
+        // One of the solutions is the true matrix, up to sign.
+        let expected = essential_from_pose(&r, &t).normalize();
+        assert!(solutions
+            .iter()
+            .any(|e| (e - expected).norm() < 1e-6 || (e + expected).norm() < 1e-6));
+    }
+
+    #[test]
+    fn pose_recovery() {
+        let (r, t, points) = scene();
+        let rays = rays(&r, &t, &points);
+        let e = essential_from_pose(&r, &t);
+
+        let (rot, tr, in_front) = recover_pose(&e, &rays);
+
+        assert!(rot.angle_to(&r) < 1e-9);
+        assert!((tr - t).norm() < 1e-9);
+        assert!(in_front.iter().all(|v| *v));
+
+        let (d1, d2) = depths(&r, &t, &rays[0].0, &rays[0].1);
+        assert!((d1 - points[0].coords.norm()).abs() < 1e-9);
+        assert!((d2 - (r * points[0].coords + t).norm()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pose_refinement() {
+        let (r, t, points) = scene();
+        let rays = rays(&r, &t, &points);
+
+        let initial = r * na::Rotation3::from_euler_angles(0.01, -0.005, 0.002);
+        let (rot, tr) = refine_pose(
+            initial,
+            (t + na::Vector3::x() * 0.05).normalize(),
+            &rays,
+            20,
+        );
+
+        assert!(rot.angle_to(&r) < 1e-6, "{}", rot.angle_to(&r));
+        assert!((tr - t).norm() < 1e-6);
+    }
+
+    #[test]
+    fn sampson() {
+        let (r, t, points) = scene();
+        let rays = rays(&r, &t, &points);
+        let e = essential_from_pose(&r, &t);
+
+        let (f1, f2) = rays[0];
+        assert!(sampson_error(&e, &f1, &f2) < 1e-20);
+
+        // Error is bounded by the angle the ray was moved by, since both rays may be corrected.
+        let offset = e * f1;
+        let offset = (offset - f2 * f2.dot(&offset)).normalize() * 0.001;
+        let err = sampson_error(&e, &f1, &(f2 + offset).normalize()).sqrt();
+        assert!(err > 0.0002 && err < 0.001 + 1e-6, "{err}");
+    }
+}
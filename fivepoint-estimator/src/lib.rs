@@ -0,0 +1,372 @@
+//! # Motion estimator built on the 5-point algorithm.
+//!
+//! This estimator is a pure Rust alternative to the OpenCV based `multiview` estimator. It finds
+//! the essential matrix with Nistér's 5-point solver inside a RANSAC loop, and recovers camera
+//! rotation and translation direction from it.
+//!
+//! Motion vectors are converted to rays through the camera model, thus this estimator is not
+//! limited to pinhole cameras.
+
+use nalgebra as na;
+use ofps::frames::Frame;
+use ofps::prelude::v1::*;
+
+pub mod essential;
+
+use essential::{
+    depths, essential_from_pose, five_point, recover_pose, refine_pose, sampson_error,
+};
+
+ofps::define_descriptor!(fivepoint, Estimator, |_| Ok(Box::new(
+    FivePointEstimator::default()
+)));
+
+/// Pair of unit rays of a point, seen from the previous and the current frame.
+type RayPair = (na::Vector3<f64>, na::Vector3<f64>);
+
+/// Pure Rust 5-point based camera estimator.
+///
+/// Translation can only be estimated up to scale, thus it is scaled to the `move_magnitude`
+/// hint, or unit length, if no hint is given. If the camera does not move enough for translation
+/// to be observable, zero translation is returned.
+pub struct FivePointEstimator {
+    desired_confidence: f32,
+    max_error: f32,
+    max_iters: usize,
+    refine_iters: usize,
+    inliers: Vec<usize>,
+}
+
+impl Properties for FivePointEstimator {
+    fn props_mut(&mut self) -> Vec<(&str, PropertyMut)> {
+        vec![
+            (
+                "Desired confidence",
+                PropertyMut::float(&mut self.desired_confidence, 0.0, 1.0),
+            ),
+            (
+                "Max error",
+                PropertyMut::float(&mut self.max_error, 0.00001, 0.1),
+            ),
+            (
+                "Max iters",
+                PropertyMut::usize(&mut self.max_iters, 1, 5000),
+            ),
+            (
+                "Refine iters",
+                PropertyMut::usize(&mut self.refine_iters, 0, 100),
+            ),
+        ]
+    }
+}
+
+impl FivePointEstimator {
+    pub fn desired_confidence(self, desired_confidence: f32) -> Self {
+        Self {
+            desired_confidence,
+            ..self
+        }
+    }
+
+    pub fn max_error(self, max_error: f32) -> Self {
+        Self { max_error, ..self }
+    }
+
+    pub fn max_iters(self, max_iters: usize) -> Self {
+        Self { max_iters, ..self }
+    }
+
+    pub fn refine_iters(self, refine_iters: usize) -> Self {
+        Self {
+            refine_iters,
+            ..self
+        }
+    }
+
+    /// Get the inliers of the last estimate.
+    ///
+    /// Returns indices of motion vectors that agree with the estimated motion.
+    pub fn get_inliers(&self) -> &[usize] {
+        &self.inliers
+    }
+}
+
+impl Default for FivePointEstimator {
+    fn default() -> Self {
+        Self {
+            desired_confidence: 0.999,
+            max_error: 0.001,
+            max_iters: 1000,
+            refine_iters: 20,
+            inliers: vec![],
+        }
+    }
+}
+
+impl FivePointEstimator {
+    /// Find the essential matrix that agrees with the most rays.
+    ///
+    /// # Arguments
+    ///
+    /// * `rays` - ray pairs of the motion vectors.
+    /// * `max_error` - maximum angular error of inliers, in radians.
+    fn essential(&self, rays: &[RayPair], max_error: f64) -> Option<na::Matrix3<f64>> {
+        let rng = &mut rand::thread_rng();
+        let threshold = max_error * max_error;
+        let log_confidence = (1.0 - self.desired_confidence as f64).ln();
+
+        let mut best = None;
+        let mut best_score = f64::INFINITY;
+        let mut iters = self.max_iters;
+        let mut i = 0;
+
+        while i < iters {
+            i += 1;
+
+            let sample = rand::seq::index::sample(rng, rays.len(), 5);
+            let f1 = [0, 1, 2, 3, 4].map(|i| rays[sample.index(i)].0);
+            let f2 = [0, 1, 2, 3, 4].map(|i| rays[sample.index(i)].1);
+
+            for e in five_point(&f1, &f2) {
+                // Score with truncated errors, which ranks models with equal inlier count.
+                let (score, inliers) = rays.iter().map(|(f1, f2)| sampson_error(&e, f1, f2)).fold(
+                    (0.0, 0),
+                    |(score, inliers), err| {
+                        if err < threshold {
+                            (score + err, inliers + 1)
+                        } else {
+                            (score + threshold, inliers)
+                        }
+                    },
+                );
+
+                if score < best_score {
+                    best_score = score;
+                    best = Some(e);
+
+                    // Stop early once an all-inlier sample has been drawn with desired confidence.
+                    let ratio = inliers as f64 / rays.len() as f64;
+                    let needed = log_confidence / (1.0 - ratio.powi(5)).ln();
+
+                    if needed.is_finite() && needed >= 0.0 {
+                        iters = iters.min(needed.ceil() as usize);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+impl Estimator for FivePointEstimator {
+    fn estimate(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        self.inliers.clear();
+
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+
+        let inv_view = na::Matrix4::identity();
+        let ray = |p| {
+            camera
+                .unproject(p, inv_view)
+                .coords
+                .cast::<f64>()
+                .normalize()
+        };
+
+        let rays = motion_vectors
+            .entries()
+            .map(|(pos, motion)| (ray(pos), ray(pos + motion)))
+            .collect::<Vec<_>>();
+
+        if rays.len() < 5 {
+            return Err(anyhow!("not enough motion vectors"));
+        }
+
+        // Convert screen-space error to angular one, as seen at the centre of the screen.
+        let max_error = self.max_error as f64 / camera.intrinsics()[(1, 1)] as f64;
+
+        let e = self
+            .essential(&rays, max_error)
+            .ok_or_else(|| anyhow!("failed to compute essential matrix"))?;
+
+        let threshold = max_error * max_error;
+        let is_inlier = |e, (f1, f2): &RayPair| sampson_error(e, f1, f2) < threshold;
+
+        let (r, t, in_front) = recover_pose(&e, &rays);
+
+        let inliers = rays
+            .iter()
+            .zip(in_front)
+            .filter(|(rays, in_front)| *in_front && is_inlier(&e, rays))
+            .map(|(rays, _)| *rays)
+            .collect::<Vec<_>>();
+
+        let (r, t) = refine_pose(r, t, &inliers, self.refine_iters);
+        let e = essential_from_pose(&r, &t);
+
+        self.inliers.extend(
+            rays.iter()
+                .enumerate()
+                .filter(|(_, rays)| is_inlier(&e, rays))
+                .filter(|(_, (f1, f2))| {
+                    let (d1, d2) = depths(&r, &t, f1, f2);
+                    d1 > 0.0 && d2 > 0.0
+                })
+                .map(|(i, _)| i),
+        );
+
+        // Without enough parallax left after derotation, translation direction is just noise.
+        let parallax = self
+            .inliers
+            .iter()
+            .map(|&i| rays[i].1.cross(&(r * rays[i].0)).norm())
+            .sum::<f64>()
+            / self.inliers.len().max(1) as f64;
+
+        let t = if parallax < max_error {
+            na::Vector3::zeros()
+        } else {
+            t * move_magnitude.unwrap_or(1.0) as f64
+        };
+
+        // Points transform from the first camera to the second, while we need the motion of the
+        // camera itself. Rays are in OpenGL camera space.
+        let rot = na::UnitQuaternion::from_rotation_matrix(&r.inverse());
+        let tr = -(r.inverse() * t);
+
+        Ok((
+            Frame::OpenGl.convert_rotation(Frame::Body, rot),
+            Frame::OpenGl.convert_translation(Frame::Body, tr),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofps::frames;
+
+    /// Generate motion vectors of a camera moving through a cloud of points.
+    fn calc_field(
+        camera: &dyn CameraModel,
+        rot: na::UnitQuaternion<f32>,
+        pos: na::Point3<f32>,
+    ) -> MotionVectors {
+        let view = frames::view_matrix(Default::default(), Default::default());
+        let inv_view = view.try_inverse().unwrap();
+        let next_view = frames::view_matrix(pos, rot);
+
+        (1..20)
+            .flat_map(|x| (1..20).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let p = na::Point2::new(x as f32 / 20.0, y as f32 / 20.0);
+                let dir = camera.unproject(p, inv_view).coords.normalize();
+                let depth = 2.0 + ((x * 7 + y * 3) % 5) as f32;
+                let next = camera.project((dir * depth).into(), next_view);
+                (p, next - p)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_motion() {
+        let camera = StandardCamera::new(16.0 / 9.0, 60.0);
+
+        let motions = [
+            (frames::from_roll_pitch_yaw(0.0, 0.0, 0.0), [0.0, 0.2, 0.0]),
+            (frames::from_roll_pitch_yaw(0.0, 0.0, 0.05), [0.1, 0.0, 0.0]),
+            (
+                frames::from_roll_pitch_yaw(0.02, -0.03, 0.01),
+                [0.1, 0.1, 0.05],
+            ),
+            (
+                frames::from_roll_pitch_yaw(-0.04, 0.02, 0.03),
+                [0.0, -0.1, 0.1],
+            ),
+        ];
+
+        for (rot, pos) in motions {
+            let pos = na::Point3::from(pos);
+            let field = calc_field(&camera, rot, pos);
+
+            let mut estimator = FivePointEstimator::default();
+            let (r, tr) = estimator.estimate(&field, &camera, Some(2.0)).unwrap();
+
+            assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+            assert!(
+                (tr - pos.coords.normalize() * 2.0).norm() < 0.05,
+                "{tr} {pos}"
+            );
+            assert_eq!(estimator.get_inliers().len(), field.len());
+        }
+    }
+
+    #[test]
+    fn estimate_rotation() {
+        let camera = StandardCamera::new(1.0, 90.0);
+        let rot = frames::from_roll_pitch_yaw(0.01, 0.02, -0.03);
+        let field = calc_field(&camera, rot, Default::default());
+
+        let (r, tr) = FivePointEstimator::default()
+            .estimate(&field, &camera, None)
+            .unwrap();
+
+        assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+        assert_eq!(tr, na::Vector3::zeros());
+    }
+
+    #[test]
+    fn reject_outliers() {
+        let camera = StandardCamera::new(1.0, 75.0);
+        let rot = frames::from_roll_pitch_yaw(0.01, 0.0, 0.02);
+        let pos = na::Point3::new(0.05, 0.1, 0.0);
+
+        let mut entries = calc_field(&camera, rot, pos).entries().collect::<Vec<_>>();
+        let outliers = (0..entries.len()).step_by(5).collect::<Vec<_>>();
+
+        for &i in &outliers {
+            entries[i].1 += na::Vector2::new(0.02, -0.03);
+        }
+
+        let field = MotionVectors::from(entries);
+
+        let mut estimator = FivePointEstimator::default();
+        let (r, tr) = estimator.estimate(&field, &camera, None).unwrap();
+
+        assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+        assert!((tr - pos.coords.normalize()).norm() < 0.05, "{tr}");
+
+        let inliers = estimator.get_inliers();
+        // Outliers moved along their epipolar lines can not be told apart.
+        let accepted = outliers.iter().filter(|i| inliers.contains(i)).count();
+        assert!(accepted <= 2, "{accepted}");
+        assert!(inliers.len() >= field.len() - outliers.len() - 2);
+    }
+
+    #[test]
+    fn not_enough_vectors() {
+        let camera = StandardCamera::new(1.0, 90.0);
+        let field = calc_field(&camera, Default::default(), na::Point3::new(0.0, 0.1, 0.0));
+        let field = field.entries().take(4).collect::<MotionVectors>();
+
+        assert!(FivePointEstimator::default()
+            .estimate(&field, &camera, None)
+            .is_err());
+    }
+}
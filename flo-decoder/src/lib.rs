@@ -0,0 +1,301 @@
+//! Optical flow file sequence decoder
+//!
+//! Plays back a directory of flow files, such as Sintel ground truth, or output of a neural
+//! network, as a stream of motion vectors. Files are played back in lexicographical order.
+//!
+//! Middlebury `.flo` files are played back by the `flo` plugin, while KITTI flow images are
+//! played back by the `kitti` plugin.
+//!
+//! Both formats are expected to store motion in pixels, as done by Sintel, KITTI and
+//! flow-extract. Motion is converted to normalised units upon playback.
+
+use nalgebra as na;
+use ofps::prelude::v1::{Result, *};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+ofps::define_descriptor!(flo, Decoder, |args| FloDecoder::try_new(
+    &args,
+    FlowFormat::Flo
+)
+.map(|d| Box::new(d) as _));
+
+mod kitti {
+    use super::*;
+
+    ofps::define_descriptor!(kitti, Decoder, |args| FloDecoder::try_new(
+        &args,
+        FlowFormat::Kitti
+    )
+    .map(|d| Box::new(d) as _));
+}
+
+/// Format of flow files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowFormat {
+    /// Middlebury `.flo` files.
+    Flo,
+    /// KITTI 16-bit `.png` images.
+    Kitti,
+}
+
+impl FlowFormat {
+    /// Get the file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Flo => "flo",
+            Self::Kitti => "png",
+        }
+    }
+
+    /// Get the typical framerate of sequences in this format.
+    fn framerate(self) -> f32 {
+        match self {
+            Self::Flo => 24.0,
+            Self::Kitti => 10.0,
+        }
+    }
+
+    /// Read dimensions of a flow file.
+    fn read_header(self, path: &Path) -> Result<(usize, usize)> {
+        let file = BufReader::new(File::open(path)?);
+        match self {
+            Self::Flo => MotionField::read_flo_header(file),
+            Self::Kitti => MotionField::read_kitti_png_header(file),
+        }
+    }
+
+    /// Read a flow file.
+    fn read(self, path: &Path) -> Result<MotionField> {
+        let file = BufReader::new(File::open(path)?);
+        match self {
+            Self::Flo => MotionField::read_flo(file),
+            Self::Kitti => MotionField::read_kitti_png(file),
+        }
+    }
+}
+
+pub struct FloDecoder {
+    format: FlowFormat,
+    files: Vec<PathBuf>,
+    frame: usize,
+    aspect: (usize, usize),
+    max_mfield_size: (usize, usize),
+    framerate: f32,
+}
+
+impl Properties for FloDecoder {
+    fn props_mut(&mut self) -> Vec<(&str, PropertyMut)> {
+        vec![
+            (
+                "Width",
+                PropertyMut::usize(&mut self.max_mfield_size.0, 1, 2000),
+            ),
+            (
+                "Height",
+                PropertyMut::usize(&mut self.max_mfield_size.1, 1, 2000),
+            ),
+            (
+                "Framerate",
+                PropertyMut::float(&mut self.framerate, 1.0, 240.0),
+            ),
+        ]
+    }
+}
+
+impl FloDecoder {
+    /// Create a decoder for all flow files of given format in a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - directory containing the flow files.
+    /// * `format` - format of the flow files.
+    pub fn try_new(dir: impl AsRef<Path>, format: FlowFormat) -> Result<Self> {
+        let mut files = std::fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        files.retain(|p| p.is_file() && p.extension().is_some_and(|e| e == format.extension()));
+        files.sort();
+
+        let first = files
+            .first()
+            .ok_or_else(|| anyhow!("Directory contains no .{} files", format.extension()))?;
+
+        let aspect = format.read_header(first)?;
+
+        Ok(Self {
+            format,
+            files,
+            frame: 0,
+            aspect,
+            max_mfield_size: (150, 150),
+            framerate: format.framerate(),
+        })
+    }
+
+    /// Compute the output field size, keeping the aspect ratio of the flow.
+    fn field_size(&self) -> (usize, usize) {
+        let (w, h) = self.aspect;
+        let (mw, mh) = self.max_mfield_size;
+        let scale = (mw as f32 / w as f32).min(mh as f32 / h as f32).min(1.0);
+        (
+            ((w as f32 * scale).round() as usize).max(1),
+            ((h as f32 * scale).round() as usize).max(1),
+        )
+    }
+}
+
+impl Decoder for FloDecoder {
+    fn process_frame(
+        &mut self,
+        mf: &mut MotionVectors,
+        _: Option<(&mut Vec<RGBA>, &mut usize)>,
+        frame_info: Option<&mut FrameInfo>,
+        skip: usize,
+    ) -> Result<bool> {
+        let frame = self.frame + skip;
+
+        let path = self
+            .files
+            .get(frame)
+            .ok_or_else(|| anyhow!("End of stream"))?;
+
+        let flow = self.format.read(path)?;
+
+        self.frame = frame + 1;
+        self.aspect = flow.dim();
+
+        if let Some(frame_info) = frame_info {
+            *frame_info = FrameInfo {
+                pts: Some(frame as f64 / self.framerate as f64),
+                decode_index: frame,
+                forward: true,
+                ..Default::default()
+            };
+        }
+
+        let (w, h) = self.aspect;
+
+        if w == 0 || h == 0 {
+            return Ok(false);
+        }
+
+        // Average the flow of all pixels within each output cell.
+        let (dw, dh) = self.field_size();
+        let mut cells = vec![(na::Vector2::zeros(), 0usize, 0usize); dw * dh];
+
+        for (x, y, motion) in flow.iter() {
+            let cell = &mut cells[y * dh / h * dw + x * dw / w];
+            cell.2 += 1;
+            if flow.is_valid(x, y) {
+                cell.0 += motion;
+                cell.1 += 1;
+            }
+        }
+
+        // Flow is stored in pixels, while motion vectors are in 0-1 range.
+        let frame_norm = na::Vector2::new(1f32 / w as f32, 1f32 / h as f32);
+        let cell_norm = na::Vector2::new(1f32 / dw as f32, 1f32 / dh as f32);
+
+        for (i, (sum, valid, total)) in cells.into_iter().enumerate() {
+            if valid == 0 {
+                continue;
+            }
+
+            let pos = na::Vector2::new((i % dw) as f32 + 0.5, (i / dw) as f32 + 0.5)
+                .component_mul(&cell_norm)
+                .into();
+
+            let motion = (sum / valid as f32).component_mul(&frame_norm);
+
+            mf.push(
+                MotionVector::new(pos, motion)
+                    .confidence(valid as f32 / total as f32)
+                    .footprint(cell_norm)
+                    .source(VectorSource::Forward),
+            );
+        }
+
+        Ok(true)
+    }
+
+    fn get_framerate(&self) -> Option<f64> {
+        Some(self.framerate as f64)
+    }
+
+    fn get_aspect(&self) -> Option<(usize, usize)> {
+        Some(self.aspect)
+    }
+
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    fn seek_to_frame(&mut self, frame: usize) -> Result<()> {
+        if frame > self.files.len() {
+            return Err(anyhow!("Frame {} is out of bounds", frame));
+        }
+
+        self.frame = frame;
+
+        Ok(())
+    }
+
+    fn frame_count(&self) -> Option<usize> {
+        Some(self.files.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufWriter;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flo-decoder-{}-{name}", std::process::id()))
+    }
+
+    /// Write a field in pixels, as flow-extract does, and decode it back to motion vectors.
+    fn round_trip(format: FlowFormat, mf: &MotionField) -> Result<MotionVectors> {
+        let dir = temp_dir(format.extension());
+        std::fs::create_dir_all(&dir)?;
+
+        let file = BufWriter::new(File::create(
+            dir.join(format!("000000.{}", format.extension())),
+        )?);
+        match format {
+            FlowFormat::Flo => mf.to_pixels().write_flo(file)?,
+            FlowFormat::Kitti => mf.to_pixels().write_kitti_png(file)?,
+        }
+
+        let mut decoder = FloDecoder::try_new(&dir, format)?;
+        let mut vectors = MotionVectors::new();
+        let ret = decoder.process_frame(&mut vectors, None, None, 0);
+        std::fs::remove_dir_all(&dir)?;
+
+        assert!(ret?);
+        Ok(vectors)
+    }
+
+    #[test]
+    fn round_trip_normalised() -> Result<()> {
+        let mut mf = MotionField::new(40, 20);
+        for y in 0..20 {
+            for x in 0..40 {
+                mf.set_motion(x, y, na::Vector2::new(0.1, -0.05));
+            }
+        }
+
+        for format in [FlowFormat::Flo, FlowFormat::Kitti] {
+            let vectors = round_trip(format, &mf)?;
+            assert_eq!(vectors.len(), mf.size());
+            for motion in vectors.motions() {
+                assert!((motion - na::Vector2::new(0.1, -0.05)).magnitude() < 1e-3);
+            }
+        }
+
+        Ok(())
+    }
+}
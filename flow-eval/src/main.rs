@@ -0,0 +1,151 @@
+//! Evaluate decoder optical flow against ground truth.
+//!
+//! Frame `i` of the input is compared against the `i`-th ground-truth file of the given directory,
+//! in lexicographical order. Ground truth may be stored either as `.flo` files, or KITTI flow
+//! images.
+
+use clap::*;
+use nalgebra as na;
+use ofps::evaluation::{FlowMetrics, OutlierThreshold};
+use ofps::prelude::v1::{anyhow, MotionField, MotionVectors, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+fn main() -> Result<()> {
+    let matches = Command::new("flow-eval")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .arg(
+            Arg::new("plugin")
+                .long("plugin")
+                .short('p')
+                .help("Decoder plugin to use (detected from the input by default)")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("ground-truth")
+                .long("ground-truth")
+                .short('g')
+                .help("Directory of ground-truth .flo files or KITTI flow images")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("outlier-abs")
+                .long("outlier-abs")
+                .help("Absolute endpoint error threshold of outliers, in pixels")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("outlier-rel")
+                .long("outlier-rel")
+                .help("Endpoint error threshold of outliers, relative to true motion")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("input")
+                .help("Clip to evaluate the decoder on")
+                .takes_value(true)
+                .required(true),
+        )
+        .get_matches();
+
+    let input = matches.value_of("input").unwrap();
+    let plugin = matches.value_of("plugin");
+    let ground_truth = ground_truth_files(matches.value_of("ground-truth").unwrap())?;
+
+    let default = OutlierThreshold::default();
+    let threshold = OutlierThreshold {
+        absolute: matches
+            .value_of("outlier-abs")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(default.absolute),
+        relative: matches
+            .value_of("outlier-rel")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(default.relative),
+    };
+
+    let mut c = motion_loader::create_decoder(input, plugin)?;
+
+    let mut motion_vectors = MotionVectors::new();
+    let mut total = FlowMetrics::default();
+
+    println!(
+        "{:>6} {:>10} {:>10} {:>10} {:>10}",
+        "frame", "epe", "ae", "fl-all", "coverage"
+    );
+
+    for (frame, path) in ground_truth.iter().enumerate() {
+        let filled = match c.process_frame(&mut motion_vectors, None, None, 0) {
+            Ok(filled) => filled,
+            Err(_) => break,
+        };
+
+        // Frames without motion, such as I frames, are not evaluated.
+        if filled {
+            let truth = read_ground_truth(path)?;
+            let (w, h) = truth.dim();
+
+            // Decoders output motion in normalised units, while ground truth is in pixels.
+            let metrics = FlowMetrics::compare_vectors(
+                &motion_vectors,
+                &truth,
+                na::Vector2::new(w as f32, h as f32),
+                threshold,
+            );
+
+            print_metrics(&frame.to_string(), &metrics);
+            total += metrics;
+        }
+
+        motion_vectors.clear();
+    }
+
+    print_metrics("total", &total);
+
+    Ok(())
+}
+
+fn print_metrics(label: &str, metrics: &FlowMetrics) {
+    println!(
+        "{:>6} {:>10.4} {:>10.4} {:>9.2}% {:>9.2}%",
+        label,
+        metrics.endpoint_error(),
+        metrics.angular_error(),
+        metrics.outlier_ratio() * 100.0,
+        metrics.coverage() * 100.0
+    );
+}
+
+/// List ground-truth files of a directory, sorted by name.
+fn ground_truth_files(dir: &str) -> Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    files.retain(|p| p.is_file() && p.extension().is_some_and(|e| e == "flo" || e == "png"));
+    files.sort();
+
+    if files.is_empty() {
+        return Err(anyhow!("{dir} contains no ground-truth files"));
+    }
+
+    Ok(files)
+}
+
+fn read_ground_truth(path: &Path) -> Result<MotionField> {
+    let file = BufReader::new(File::open(path)?);
+
+    if path.extension().is_some_and(|e| e == "png") {
+        MotionField::read_kitti_png(file)
+    } else {
+        MotionField::read_flo(file)
+    }
+}
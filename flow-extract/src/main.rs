@@ -1,11 +1,13 @@
-//! Extract motion vectors to .flo files
+//! Extract motion vectors to .flo files, KITTI flow images, or NumPy arrays
 
 use clap::*;
+use ofps::npy::NpyDump;
 use ofps::prelude::v1::{Result, *};
 use opencv::core::*;
 use opencv::imgproc::*;
 use opencv::types::VectorOfMat;
-use opencv::video::*;
+use std::fs::File;
+use std::io::BufWriter;
 
 fn main() -> Result<()> {
     let matches = Command::new("flow-extract")
@@ -38,6 +40,44 @@ fn main() -> Result<()> {
                 .short('d')
                 .required(false),
         )
+        .arg(
+            Arg::new("max-extrapolation")
+                .long("max-extrapolation")
+                .short('e')
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("kernel")
+                .long("kernel")
+                .short('k')
+                .takes_value(true)
+                .possible_values(DensifyKernel::NAMES)
+                .required(false),
+        )
+        .arg(
+            Arg::new("kernel-radius")
+                .long("kernel-radius")
+                .short('r')
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::new("camera")
+                .long("camera")
+                .short('c')
+                .takes_value(true)
+                .possible_values(["standard", "equirectangular"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .short('f')
+                .takes_value(true)
+                .possible_values(["flo", "kitti", "npz", "npy"])
+                .required(false),
+        )
         .arg(Arg::new("output").takes_value(true).required(true))
         .get_matches();
 
@@ -46,12 +86,38 @@ fn main() -> Result<()> {
     let width: usize = matches.value_of("width").unwrap().parse()?;
     let height: usize = matches.value_of("height").unwrap().parse()?;
     let draw_flow = matches.occurrences_of("draw-flow") > 0;
+    let max_extrapolation = matches
+        .value_of("max-extrapolation")
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(usize::MAX);
+    let format = matches.value_of("format").unwrap_or("flo");
+    let kernel_radius = matches
+        .value_of("kernel-radius")
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(1.0);
+    let kernel = matches
+        .value_of("kernel")
+        .map(|name| {
+            DensifyKernel::from_name(name, kernel_radius)
+                .ok_or_else(|| anyhow!("Unknown kernel {name}"))
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    // Only the projection of the camera affects densification, thus the field of view of the
+    // standard camera does not matter.
+    let camera: Box<dyn CameraModel> = match matches.value_of("camera") {
+        Some("equirectangular") => Box::new(EquirectangularCamera::new()),
+        _ => Box::new(StandardCamera::new(width as f32 / height as f32, 90.0)),
+    };
 
     let mut c = motion_loader::create_decoder(input, None)?;
 
     std::fs::create_dir_all(output)?;
 
-    let mut motion_vectors = vec![];
+    let mut motion_vectors = MotionVectors::new();
     let mut mf = MotionField::new(width, height);
     let (x, y) = mf.dim();
     let mut flow = Mat::new_size_with_default(
@@ -62,55 +128,52 @@ fn main() -> Result<()> {
         CV_32FC2,
         Default::default(),
     )?;
-    let mut flow2 = flow.clone();
 
     let mut cnt = 0usize;
+    let mut dump = NpyDump::new();
+    let mut frame_info = FrameInfo::default();
+
+    while let Ok(filled) = c.process_frame(&mut motion_vectors, None, Some(&mut frame_info), 0) {
+        // Prefer decoder timestamps, fall back to assuming constant framerate.
+        let timestamp = frame_info
+            .pts
+            .or_else(|| c.get_framerate().map(|fps| cnt as f64 / fps));
 
-    while let Ok(filled) = c.process_frame(&mut motion_vectors, None, 0) {
         // If motion vectors were filled, update the dense field.
         // Else, reuse the previous values (this typically happens on an I frame).
         if filled {
+            if matches!(format, "npz" | "npy") {
+                dump.add_vectors(cnt, timestamp, &motion_vectors);
+            }
+
             // Densify the field.
-            let mut densify_mf = mf.new_densifier();
+            let mut densify_mf = mf.new_densifier().kernel(kernel).camera(&*camera);
 
-            for &(pos, motion) in &motion_vectors {
-                densify_mf.add_vector(pos, motion);
-            }
+            densify_mf.add_vectors(&motion_vectors);
 
-            // Interpolate any empty cells. TODO: configure this?
-            let _ = densify_mf.interpolate_empty_cells();
+            // Interpolate empty cells up to the given number of cells away from data.
+            let _ = densify_mf.interpolate_empty_cells_within(max_extrapolation);
 
-            mf.from_densifier(&densify_mf);
+            let dense = MotionField::from(densify_mf);
 
-            //mf.gaussian_blur(5);
+            // Only replace the field if there was any data to fill it with.
+            if dense.valid_count() > 0 {
+                mf = dense;
 
-            // Convert into CV Mat.
-            for (x, y, motion) in mf.iter() {
-                // TODO: report Rust ICE once open source:
-                // flow.at_2d_mut::<f32>(y as _, x as _)? = motion.x;
-                let pt = flow.at_2d_mut::<Point2f>(y as _, x as _)?;
-                pt.x = motion.x;
-                pt.y = motion.y;
+                // Smooth out the displayed field.
+                if draw_flow {
+                    let mut blurred = mf.clone();
+                    blurred.gaussian_blur(2.0);
+                    field_to_mat(&blurred, &mut flow)?;
+                }
             }
 
-            gaussian_blur(
-                &flow,
-                &mut flow2,
-                Size_ {
-                    width: 11,
-                    height: 11,
-                },
-                0.0,
-                0.0,
-                BorderTypes::BORDER_REPLICATE as _,
-            )?;
-
             // Clear the field
             motion_vectors.clear();
         }
 
         if draw_flow {
-            let flw = flow_to_display(&flow2)?;
+            let flw = flow_to_display(&flow)?;
 
             if opencv::highgui::wait_key(1)? >= 1 {
                 break;
@@ -119,13 +182,44 @@ fn main() -> Result<()> {
             opencv::highgui::imshow("fl1", &flw)?;
         }
 
-        if !write_optical_flow(&format!("{output}/{cnt:06}.flo"), &flow)? {
-            return Err(anyhow!("Fail to write"));
+        match format {
+            // Flow files store motion in pixels, so that tools made for Sintel and KITTI can
+            // read them. NumPy arrays keep the normalised units.
+            "kitti" => {
+                let file = File::create(format!("{output}/{cnt:06}.png"))?;
+                mf.to_pixels().write_kitti_png(BufWriter::new(file))?;
+            }
+            "npz" | "npy" => dump.add_field(cnt, timestamp, &mf)?,
+            _ => {
+                let file = File::create(format!("{output}/{cnt:06}.flo"))?;
+                mf.to_pixels().write_flo(BufWriter::new(file))?;
+            }
         }
 
         cnt += 1;
     }
 
+    // NumPy arrays are written once the whole stream is processed.
+    match format {
+        "npz" => {
+            dump.write_npz(BufWriter::new(File::create(format!("{output}/flow.npz"))?))?;
+        }
+        "npy" => dump.write_npy_dir(output)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn field_to_mat(mf: &MotionField, flow: &mut Mat) -> Result<()> {
+    for (x, y, motion) in mf.iter() {
+        // TODO: report Rust ICE once open source:
+        // flow.at_2d_mut::<f32>(y as _, x as _)? = motion.x;
+        let pt = flow.at_2d_mut::<Point2f>(y as _, x as _)?;
+        pt.x = motion.x;
+        pt.y = motion.y;
+    }
+
     Ok(())
 }
 
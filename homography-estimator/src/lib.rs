@@ -4,6 +4,7 @@
 //! which allows to retrieve camera rotation.
 
 use nalgebra as na;
+use ofps::frames::Frame;
 use ofps::prelude::v1::*;
 use opencv::calib3d::{decompose_homography_mat, find_homography_ext, LMEDS, RANSAC};
 use opencv::core::*;
@@ -75,15 +76,15 @@ impl Default for HomographyEstimator {
 impl HomographyEstimator {
     fn homography(
         &self,
-        motion: impl Iterator<Item = MotionEntry>,
-        camera: &StandardCamera,
+        motion: impl Iterator<Item = MotionEntry<f64>>,
+        camera: &dyn CameraModel,
     ) -> Result<(Mat, Mat, Mat, Mat, Mat)> {
         let mut p1 = vec![];
         let mut p2 = vec![];
 
         for (s, e) in motion.map(|(s, m)| (s, s + m)) {
-            p1.push(Point2f::new(s.x, s.y));
-            p2.push(Point2f::new(e.x, e.y));
+            p1.push(Point2d::new(s.x, s.y));
+            p2.push(Point2d::new(e.x, e.y));
         }
 
         let p1 = Mat::from_slice(&*p1)?;
@@ -91,8 +92,7 @@ impl HomographyEstimator {
 
         let cam_matrix = camera.intrinsics();
 
-        let cam_matrix = cam_matrix.transpose();
-        let cam_matrix = na::Matrix3::from_iterator(cam_matrix.into_iter().map(|v| *v as f64));
+        let cam_matrix = cam_matrix.transpose().cast::<f64>();
 
         let cam_matrix = Mat::from_slice_2d(&[
             cam_matrix.column(0).as_slice(),
@@ -121,11 +121,25 @@ impl HomographyEstimator {
 impl Estimator for HomographyEstimator {
     fn estimate(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
-        _: Option<f32>,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
     ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
-        let (h, _, _, cam_matrix, _) = self.homography(motion_vectors.iter().copied(), camera)?;
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        _: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+        let entries = motion_vectors
+            .entries()
+            .map(|(pos, motion)| (pos.cast(), motion.cast()));
+        let (h, _, _, cam_matrix, _) = self.homography(entries, camera)?;
 
         let mut r: Vector<Mat> = Default::default();
         let mut t: Vector<Mat> = Default::default();
@@ -145,13 +159,13 @@ impl Estimator for HomographyEstimator {
             })
             .unwrap();
 
-        let r = na::Matrix3::from_iterator(r.iter::<f64>()?.map(|(_, v)| v as _));
+        // OpenCV matrices are stored in row-major order.
+        let r = na::Matrix3::from_iterator(r.iter::<f64>()?.map(|(_, v)| v)).transpose();
 
-        // Swap Y and Z axis in the rotation matrix to be line-in-line with
-        // the rest of the codebase.
+        // OpenCV rotates points from the first camera to the second, while we need the rotation
+        // of the camera itself.
         let r = na::UnitQuaternion::from_matrix(&r).inverse();
-        let (x, z, y) = r.euler_angles();
-        let r = na::UnitQuaternion::from_euler_angles(x * -1.0, y * -1.0, z);
+        let r = Frame::OpenCv.convert_rotation(Frame::Body, r);
         Ok((r, Default::default()))
     }
 }
@@ -184,11 +198,7 @@ mod tests {
     }
 
     fn calc_view(rot: na::UnitQuaternion<f32>, pos: na::Point3<f32>) -> na::Matrix4<f32> {
-        na::Matrix4::look_at_rh(
-            &pos,
-            &(pos + rot.transform_vector(&na::Vector3::new(0.0, 1.0, 0.0))),
-            &rot.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0)),
-        )
+        ofps::frames::view_matrix(pos, rot)
     }
 
     fn project_grid<'a>(
@@ -202,7 +212,7 @@ mod tests {
     fn calc_field(
         p1: impl IntoIterator<Item = na::Point2<f32>>,
         p2: impl IntoIterator<Item = na::Point2<f32>>,
-    ) -> Vec<MotionEntry> {
+    ) -> MotionVectors {
         p1.into_iter()
             .zip(p2.into_iter())
             .filter(|(p1, p2)| p1.coords.magnitude() <= 0.71 && p2.coords.magnitude() <= 0.71)
@@ -253,6 +263,8 @@ mod tests {
                 println!("ROTATION: {:?}", r.euler_angles());
                 println!("DELTA: {:?}", rot.angle_to(&r).to_degrees());
                 println!("TR: {:?}", tr);
+
+                assert!(rot.angle_to(&r).to_degrees() < 0.1 * ROT);
             }
         }
     }
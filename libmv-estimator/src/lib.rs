@@ -6,6 +6,7 @@
 
 use nalgebra as na;
 
+use ofps::frames::Frame;
 use ofps::prelude::v1::*;
 use std::collections::BTreeMap;
 
@@ -14,48 +15,32 @@ ofps::define_descriptor!(libmv, Estimator, |_| Ok(
 ));
 
 pub fn fundamental(
-    entries: impl Iterator<Item = MotionEntry>,
+    entries: impl Iterator<Item = MotionEntry<f64>>,
     outlier_proba: f64,
     max_error: f64,
     algo_points: usize,
-) -> Option<(f64, na::Matrix3<f32>, Vec<usize>)> {
+) -> Option<(f64, na::Matrix3<f64>, Vec<usize>)> {
     let func = match algo_points {
         7 => libmv::multiview::robust_fundamental::from_correspondences_7_point,
         8 => libmv::multiview::robust_fundamental::from_correspondences_8_point,
         _ => return None,
     };
-    func(
-        entries.map(|(a, m)| (a, a + m)).map(|(a, b)| {
-            (
-                na::Point2::new(a.x as _, a.y as _),
-                na::Point2::new(b.x as _, b.y as _),
-            )
-        }),
-        max_error,
-        outlier_proba,
-    )
-    .map(|(e, m, i)| {
-        (
-            e as _,
-            na::Matrix3::from_iterator(m.into_iter().map(|v| *v as _)),
-            i,
-        )
-    })
+    func(entries.map(|(a, m)| (a, a + m)), max_error, outlier_proba)
 }
 
-type Float = noisy_float::NoisyFloat<f32, noisy_float::checkers::NumChecker>;
+type Float = noisy_float::NoisyFloat<f64, noisy_float::checkers::NumChecker>;
 
 struct PrevMotion {
-    mv: BTreeMap<Float, BTreeMap<Float, MotionEntry>>,
-    rot: na::UnitQuaternion<f32>,
-    tr: na::Vector3<f32>,
+    mv: BTreeMap<Float, BTreeMap<Float, MotionEntry<f64>>>,
+    rot: na::UnitQuaternion<f64>,
+    tr: na::Vector3<f64>,
 }
 
 impl PrevMotion {
     fn new(
-        mv: impl Iterator<Item = MotionEntry>,
-        rot: na::UnitQuaternion<f32>,
-        tr: na::Vector3<f32>,
+        mv: impl Iterator<Item = MotionEntry<f64>>,
+        rot: na::UnitQuaternion<f64>,
+        tr: na::Vector3<f64>,
     ) -> Self {
         let mut s = Self {
             mv: Default::default(),
@@ -68,11 +53,11 @@ impl PrevMotion {
         s
     }
 
-    fn mv_iter(&self) -> impl Iterator<Item = MotionEntry> + '_ {
+    fn mv_iter(&self) -> impl Iterator<Item = MotionEntry<f64>> + '_ {
         self.mv.values().flat_map(|m| m.values().copied())
     }
 
-    fn set_mv(&mut self, mv: impl Iterator<Item = MotionEntry>) {
+    fn set_mv(&mut self, mv: impl Iterator<Item = MotionEntry<f64>>) {
         self.mv.clear();
         for (pos, motion) in mv {
             let ep = pos + motion;
@@ -82,8 +67,12 @@ impl PrevMotion {
         }
     }
 
-    fn find_nearest_entry(&self, (pos, _): MotionEntry, range: f32) -> Option<MotionEntry> {
-        let mut best_entry: Option<(f32, MotionEntry)> = None;
+    fn find_nearest_entry(
+        &self,
+        (pos, _): MotionEntry<f64>,
+        range: f64,
+    ) -> Option<MotionEntry<f64>> {
+        let mut best_entry: Option<(f64, MotionEntry<f64>)> = None;
 
         for (y, m) in self
             .mv
@@ -92,7 +81,7 @@ impl PrevMotion {
             for (x, mv) in
                 m.range(Float::unchecked_new(pos.x - range)..Float::unchecked_new(pos.x + range))
             {
-                let dist = (pos.y - f32::from(*y)).abs() + (pos.x - f32::from(*x)).abs();
+                let dist = (pos.y - f64::from(*y)).abs() + (pos.x - f64::from(*x)).abs();
                 if let Some((best_dist, _)) = best_entry {
                     if dist < best_dist {
                         best_entry = Some((dist, *mv))
@@ -165,39 +154,50 @@ impl Default for LibmvEstimator {
 impl Estimator for LibmvEstimator {
     fn estimate(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
-        _: Option<f32>,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
     ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        _: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        // Libmv works in double precision, thus convert the input once.
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+        let entries = motion_vectors
+            .entries()
+            .map(|(pos, motion)| (pos.cast::<f64>(), motion.cast::<f64>()))
+            .collect::<Vec<_>>();
+        let k = camera.intrinsics().cast::<f64>();
+
         let (_, f, inliers) = fundamental(
-            motion_vectors.iter().copied(),
+            entries.iter().copied(),
             self.outlier_proba as _,
             self.max_error as _,
             self.algo_points,
         )
         .ok_or_else(|| anyhow!("failed to compute fundamental matrix"))?;
-        let e = camera.essential(f);
 
         // TODO: reimplement in pure Rust
-        let e = na::Matrix3::from_iterator(e.into_iter().map(|v| *v as _));
-        let k = na::Matrix3::from_iterator(camera.intrinsics().into_iter().map(|v| *v as _));
-        let point = motion_vectors[inliers[0]];
-        let x1 = na::Point2::new(point.0.x as _, point.0.y as _);
-        let x2 = x1 + na::Vector2::new(point.1.x as _, point.1.y as _);
+        let e = k.transpose() * f * k;
+        let (x1, motion) = *entries
+            .get(inliers[0])
+            .ok_or_else(|| anyhow!("no inliers"))?;
+        let x2 = x1 + motion;
 
         let (r, t) = libmv::multiview::fundamental::motion_from_essential_and_correspondence(
             e, k, x1, k, x2,
         )
         .ok_or_else(|| anyhow!("failed to extract motion"))?;
 
-        let r = na::Matrix3::from_iterator(r.into_iter().map(|v| *v as _));
-        let t = na::Vector3::from_iterator(t.into_iter().map(|v| *v as _));
-
-        // Swap Y and Z axis in the rotation matrix to be line-in-line with
-        // the rest of the codebase.
+        // Motion is accumulated in the OpenCV camera frame, and converted on output.
         let r = na::UnitQuaternion::from_matrix(&r);
-        let (x, z, y) = r.euler_angles();
-        let r = na::UnitQuaternion::from_euler_angles(x * -1.0, y * -1.0, z);
 
         let tm = t.magnitude();
 
@@ -217,7 +217,7 @@ impl Estimator for LibmvEstimator {
             // at current endpoint.
 
             #[allow(clippy::needless_collect)]
-            let mv = motion_vectors
+            let mv = entries
                 .iter()
                 .filter_map(|&me| {
                     prev_motion
@@ -239,32 +239,22 @@ impl Estimator for LibmvEstimator {
                     self.algo_points,
                 )
                 .ok_or_else(|| anyhow!("failed to compute secondary fundamental matrix"))?;
-                let e = camera.essential(f);
+                let e = k.transpose() * f * k;
 
-                let e = na::Matrix3::from_iterator(e.into_iter().map(|v| *v as _));
-                let k =
-                    na::Matrix3::from_iterator(camera.intrinsics().into_iter().map(|v| *v as _));
-                let point = prev_motion.mv_iter().nth(inliers[0]).unwrap();
-                let x1 = na::Point2::new(point.0.x as _, point.0.y as _);
-                let x2 = x1 + na::Vector2::new(point.1.x as _, point.1.y as _);
+                let (x1, motion) = prev_motion.mv_iter().nth(inliers[0]).unwrap();
+                let x2 = x1 + motion;
 
-                let (_, t2) =
+                let (_, t13) =
                     libmv::multiview::fundamental::motion_from_essential_and_correspondence(
                         e, k, x1, k, x2,
                     )
                     .ok_or_else(|| anyhow!("failed to extract secondary motion"))?;
 
-                let t13 = na::Vector3::from_iterator(t2.into_iter().map(|v| *v as f32));
-
                 let t23 = prev_motion.rot * t;
 
                 let scale = ofps::utils::triangulate_scale(prev_motion.tr, t23, t13);
 
-                self.prev_motion = Some(PrevMotion::new(
-                    motion_vectors.iter().copied(),
-                    r,
-                    t * scale,
-                ));
+                self.prev_motion = Some(PrevMotion::new(entries.into_iter(), r, t * scale));
 
                 scale
             }
@@ -274,11 +264,16 @@ impl Estimator for LibmvEstimator {
         } else {
             // If there is no prev motion and positive translation magnitude,
             // just set prev motion to current motion.
-            self.prev_motion = Some(PrevMotion::new(motion_vectors.iter().copied(), r, t));
+            self.prev_motion = Some(PrevMotion::new(entries.into_iter(), r, t));
             1.0
         };
 
-        Ok((r, t * -sf))
+        // Libmv rotates points from the first camera to the second, while we need the motion of
+        // the camera itself.
+        Ok((
+            Frame::OpenCv.convert_rotation(Frame::Body, r.inverse()),
+            Frame::OpenCv.convert_translation(Frame::Body, t * -sf),
+        ))
     }
 }
 
@@ -310,11 +305,7 @@ mod tests {
     }
 
     fn calc_view(rot: na::UnitQuaternion<f32>, pos: na::Point3<f32>) -> na::Matrix4<f32> {
-        na::Matrix4::look_at_rh(
-            &pos,
-            &(pos + rot.transform_vector(&na::Vector3::new(0.0, 1.0, 0.0))),
-            &rot.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0)),
-        )
+        ofps::frames::view_matrix(pos, rot)
     }
 
     fn project_grid<'a>(
@@ -328,7 +319,7 @@ mod tests {
     fn calc_field(
         p1: impl IntoIterator<Item = na::Point2<f32>>,
         p2: impl IntoIterator<Item = na::Point2<f32>>,
-    ) -> Vec<MotionEntry> {
+    ) -> MotionVectors {
         p1.into_iter()
             .zip(p2.into_iter())
             .filter(|(p1, p2)| p1.coords.magnitude() <= 0.71 && p2.coords.magnitude() <= 0.71)
@@ -1,37 +1,192 @@
 //! Extract motion vectors into a easy-to-read file.
 
-use ofps::prelude::v1::*;
+use clap::*;
+use motion_loader::mvec::{frame_flags, MvecCompression, MvecHeader, MvecWriter};
+use ofps::npy::NpyDump;
+use ofps::prelude::v1::{anyhow, DecoderPlugin, FrameInfo, MotionVectors, Result};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::BufWriter;
 
 fn main() -> Result<()> {
-    let input = std::env::args()
-        .nth(1)
-        .ok_or_else(|| anyhow!("Please supply a video file!"))?;
+    let matches = Command::new("motion-extract")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .arg(
+            Arg::new("legacy")
+                .long("legacy")
+                .help("Write the legacy, headerless .mvec format")
+                .required(false),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .short('f')
+                .help("Output format. NumPy arrays are written as .npz archive, or .npy files")
+                .takes_value(true)
+                .possible_values(["mvec", "npz", "npy"])
+                .conflicts_with("legacy")
+                .required(false),
+        )
+        .arg(
+            Arg::new("compress")
+                .long("compress")
+                .short('c')
+                .help("Store motion vectors in compressed form (mvec format only)")
+                .conflicts_with("legacy")
+                .required(false),
+        )
+        .arg(
+            Arg::new("motion-scale")
+                .long("motion-scale")
+                .help("Quantisation steps per pixel of compressed vectors")
+                .takes_value(true)
+                .requires("compress")
+                .required(false),
+        )
+        .arg(
+            Arg::new("keyframe-interval")
+                .long("keyframe-interval")
+                .help("Maximum number of frames between compressed prediction resets")
+                .takes_value(true)
+                .requires("compress")
+                .required(false),
+        )
+        .arg(
+            Arg::new("input")
+                .help("Video file to extract the motion from")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Output path, without the extension (defaults to input)")
+                .takes_value(true)
+                .required(false),
+        )
+        .get_matches();
+
+    let input = matches.value_of("input").unwrap();
+    let legacy = matches.occurrences_of("legacy") > 0;
+    let format = matches.value_of("format").unwrap_or("mvec");
+
+    // Only .mvec files can be compressed.
+    if matches.occurrences_of("compress") > 0 && format != "mvec" {
+        return Err(anyhow!("--compress is not supported with {format} format"));
+    }
+
+    let compression = if matches.occurrences_of("compress") > 0 {
+        let default = MvecCompression::default();
+        Some(MvecCompression {
+            motion_scale: matches
+                .value_of("motion-scale")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(default.motion_scale),
+            keyframe_interval: matches
+                .value_of("keyframe-interval")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(default.keyframe_interval),
+        })
+    } else {
+        None
+    };
+
+    let output = matches.value_of("output").unwrap_or(input);
+
+    let mut c = motion_loader::create_decoder(input, None)?;
+
+    if format != "mvec" {
+        return extract_numpy(&mut c, output, format == "npz");
+    }
 
     // Output file must always end with `.mvec` for the loader to detect it.
-    let output = std::env::args().nth(2);
-    let output = output.as_deref().unwrap_or(&input);
     let output = format!("{output}.mvec");
 
-    let mut c = motion_loader::create_decoder(&input, None)?;
-
-    let out = File::create(output)?;
-    let mut motion_vectors = vec![];
-    let mut out = BufWriter::new(out);
-
-    while c.process_frame(&mut motion_vectors, None, 0).is_ok() {
-        // First encode number of MVs in a 32-bit LE integer.
-        out.write_all(&(motion_vectors.len() as u32).to_le_bytes())?;
-        // Then write each MV as a 4 f32 groups (in LE).
-        for v in motion_vectors
-            .iter()
-            .flat_map(|(a, m)| [a.x, a.y, m.x, m.y])
-        {
-            out.write_all(&v.to_le_bytes())?;
-        }
+    let out = BufWriter::new(File::create(output)?);
+    let mut out = Some(out);
+    let mut writer = None;
+    let mut motion_vectors = MotionVectors::new();
+    let mut frame = 0usize;
+
+    let mut frame_info = FrameInfo::default();
+
+    while let Ok(filled) = c.process_frame(&mut motion_vectors, None, Some(&mut frame_info), 0) {
+        // The header can only be written once stream dimensions are known,
+        // which is after the first frame gets decoded.
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                let out = out.take().unwrap();
+                let writer_new = if legacy {
+                    MvecWriter::legacy(out)
+                } else {
+                    let header =
+                        MvecHeader::new(c.get_aspect(), c.get_framerate()).compression(compression);
+                    MvecWriter::new(out, header)?
+                };
+                writer.insert(writer_new)
+            }
+        };
+
+        // Prefer decoder timestamps, fall back to assuming constant framerate.
+        let timestamp = frame_info
+            .pts
+            .or_else(|| c.get_framerate().map(|fps| frame as f64 / fps));
+        let flags = frame_flags(&frame_info, filled);
+
+        writer.write_frame(timestamp, flags, &motion_vectors)?;
+
         // Clear the buffer.
         motion_vectors.clear();
+        frame += 1;
+    }
+
+    match (writer, out) {
+        (Some(writer), _) => {
+            writer.finish()?;
+        }
+        // No frames were decoded, write out an empty stream.
+        (None, Some(out)) if !legacy => {
+            MvecWriter::new(out, MvecHeader::new(c.get_aspect(), c.get_framerate()))?.finish()?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Extract all motion vectors into NumPy arrays.
+///
+/// # Arguments
+///
+/// * `c` - decoder to extract the vectors from.
+/// * `output` - output path, without the extension.
+/// * `npz` - write a single `.npz` archive, instead of a directory of `.npy` files.
+fn extract_numpy(c: &mut DecoderPlugin, output: &str, npz: bool) -> Result<()> {
+    let mut dump = NpyDump::new();
+    let mut motion_vectors = MotionVectors::new();
+    let mut frame_info = FrameInfo::default();
+    let mut frame = 0usize;
+
+    while c
+        .process_frame(&mut motion_vectors, None, Some(&mut frame_info), 0)
+        .is_ok()
+    {
+        let timestamp = frame_info
+            .pts
+            .or_else(|| c.get_framerate().map(|fps| frame as f64 / fps));
+
+        dump.add_vectors(frame, timestamp, &motion_vectors);
+
+        motion_vectors.clear();
+        frame += 1;
+    }
+
+    if npz {
+        dump.write_npz(BufWriter::new(File::create(format!("{output}.npz"))?))?;
+    } else {
+        dump.write_npy_dir(format!("{output}_npy"))?;
     }
 
     Ok(())
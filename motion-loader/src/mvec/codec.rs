@@ -0,0 +1,643 @@
+//! # Compressed motion vector encoding
+//!
+//! Codec motion vectors lie on a regular block grid, with motion quantised to fractions of a
+//! pixel. Compressed streams exploit this by storing positions and motion as integers in units
+//! of `1 / motion_scale` pixels.
+//!
+//! Positions are implied by a grid, described by its origin, cell size and column count. When
+//! a frame covers every cell of the grid exactly once, in row-major order, no per-vector
+//! positions are stored at all. Otherwise, grid cells of vectors are delta coded against the
+//! previous vector in the frame. Motion is delta coded against the motion at the same position
+//! in the previous frame.
+//!
+//! All values are zigzag varint encoded, with the grid and positions stored before all motion
+//! values, and the result is compressed with deflate.
+//!
+//! Prediction from the previous frame is reset every `keyframe_interval` frames. Such frames
+//! have [`FRAME_RESET`](super::FRAME_RESET) flag set, and decoding can start from them.
+
+use super::*;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::collections::HashMap;
+
+/// Compression parameters of a `.mvec` stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MvecCompression {
+    /// Number of quantisation steps per pixel, for both positions and motion.
+    ///
+    /// This should match the motion vector precision of the source codec. Most codecs use
+    /// quarter pixel precision.
+    pub motion_scale: u32,
+    /// Maximum number of frames between motion prediction resets.
+    ///
+    /// Lower values make random access cheaper, at the cost of compression ratio.
+    pub keyframe_interval: u32,
+}
+
+impl Default for MvecCompression {
+    fn default() -> Self {
+        Self {
+            motion_scale: 4,
+            keyframe_interval: 32,
+        }
+    }
+}
+
+impl MvecCompression {
+    /// Serialized size of the compression parameters.
+    pub(crate) const SIZE: usize = 8;
+
+    pub(crate) fn write(&self, out: &mut impl Write) -> Result<()> {
+        out.write_all(&self.motion_scale.to_le_bytes())?;
+        out.write_all(&self.keyframe_interval.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub(crate) fn read(input: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            motion_scale: read_u32(input)?,
+            keyframe_interval: read_u32(input)?,
+        })
+    }
+}
+
+/// Maximum length of a single varint.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Number of varints describing the position grid of a frame.
+const GRID_LEN: usize = 5;
+
+/// Upper bound of the uncompressed payload size for given vector count.
+fn max_raw_size(count: u32) -> usize {
+    (count as usize * 4 + GRID_LEN) * MAX_VARINT_LEN
+}
+
+/// Upper bound of the compressed payload size for given vector count.
+///
+/// Deflate expands incompressible data only by a few bytes per block, thus this is a generous
+/// limit used to reject corrupt payload lengths.
+pub(crate) fn max_payload_size(count: u32) -> usize {
+    let raw = max_raw_size(count);
+    raw + raw / 8 + 64
+}
+
+/// Greatest common divisor.
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Regular grid that quantised positions of a frame lie on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PositionGrid {
+    origin: [i32; 2],
+    step: [u32; 2],
+    /// Number of columns, if the frame covers every cell exactly once, in row-major order.
+    dense_cols: Option<u32>,
+}
+
+impl PositionGrid {
+    /// Find the coarsest grid all positions lie on.
+    fn new(positions: &[[i32; 2]]) -> Self {
+        let mut origin = [0; 2];
+        let mut step = [1; 2];
+
+        for axis in 0..2 {
+            let values = positions.iter().map(|p| p[axis]);
+            origin[axis] = values.clone().min().unwrap_or_default();
+            let step_gcd = values.fold(0, |g, v| gcd(g, v.abs_diff(origin[axis]) as u64));
+            step[axis] = u32::try_from(step_gcd).unwrap_or(1).max(1);
+        }
+
+        let mut grid = Self {
+            origin,
+            step,
+            dense_cols: None,
+        };
+
+        let cells = positions.iter().map(|&p| grid.cell(p)).collect::<Vec<_>>();
+        let cols = cells.iter().map(|c| c[0] + 1).max().unwrap_or_default();
+        let rows = cells.iter().map(|c| c[1] + 1).max().unwrap_or_default();
+
+        if !positions.is_empty()
+            && cols as u64 * rows as u64 == positions.len() as u64
+            && cells
+                .iter()
+                .enumerate()
+                .all(|(i, c)| c[0] as u64 + c[1] as u64 * cols as u64 == i as u64)
+        {
+            grid.dense_cols = Some(cols);
+        }
+
+        grid
+    }
+
+    /// Get the cell of a position on the grid.
+    fn cell(&self, pos: [i32; 2]) -> [u32; 2] {
+        [0, 1].map(|axis| pos[axis].abs_diff(self.origin[axis]) / self.step[axis])
+    }
+
+    /// Get the position of a grid cell.
+    fn position(&self, cell: [u32; 2]) -> Result<[i32; 2]> {
+        let mut pos = [0; 2];
+
+        for axis in 0..2 {
+            pos[axis] = (self.step[axis] as i64)
+                .checked_mul(cell[axis] as i64)
+                .and_then(|v| v.checked_add(self.origin[axis] as i64))
+                .and_then(|v| i32::try_from(v).ok())
+                .ok_or_else(|| anyhow!("compressed frame has out of range positions"))?;
+        }
+
+        Ok(pos)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for axis in 0..2 {
+            write_varint(out, self.origin[axis] as i64);
+            write_varint(out, self.step[axis] as i64);
+        }
+        // Dense grids always have columns, thus zero marks a sparse one.
+        write_varint(out, self.dense_cols.unwrap_or_default() as i64);
+    }
+
+    fn read(input: &mut &[u8]) -> Result<Self> {
+        let mut origin = [0; 2];
+        let mut step = [0; 2];
+
+        for axis in 0..2 {
+            origin[axis] = i32::try_from(read_varint(input)?)?;
+            step[axis] = u32::try_from(read_varint(input)?)?;
+            if step[axis] == 0 {
+                return Err(anyhow!("compressed frame has zero grid step"));
+            }
+        }
+
+        let cols = u32::try_from(read_varint(input)?)?;
+
+        Ok(Self {
+            origin,
+            step,
+            dense_cols: Some(cols).filter(|&cols| cols != 0),
+        })
+    }
+}
+
+/// Quantisation of normalised coordinates into integer steps.
+#[derive(Clone, Copy)]
+struct Quantizer {
+    scale: f32,
+    steps: [f64; 2],
+    norm: [f32; 2],
+}
+
+impl Quantizer {
+    fn new(header: &MvecHeader, compression: &MvecCompression) -> Result<Self> {
+        let (width, height) = header
+            .aspect()
+            .ok_or_else(|| anyhow!("compressed mvec streams require known dimensions"))?;
+
+        if compression.motion_scale == 0 {
+            return Err(anyhow!("motion scale must be non-zero"));
+        }
+
+        let scale = compression.motion_scale as f64;
+
+        Ok(Self {
+            scale: compression.motion_scale as f32,
+            steps: [width as f64 * scale, height as f64 * scale],
+            norm: [1f32 / width as f32, 1f32 / height as f32],
+        })
+    }
+
+    fn quantize(&self, v: f32, axis: usize) -> Result<i32> {
+        let q = (v as f64 * self.steps[axis]).round();
+
+        if q.is_finite() && q >= i32::MIN as f64 && q <= i32::MAX as f64 {
+            Ok(q as i32)
+        } else {
+            Err(anyhow!("value {v} can not be quantised"))
+        }
+    }
+
+    /// Convert back into normalised coordinates.
+    ///
+    /// This performs the same float operations as `AvDecoder`, thus values coming from it
+    /// round-trip exactly.
+    fn dequantize(&self, q: i32, axis: usize) -> f32 {
+        (q as f32 / self.scale) * self.norm[axis]
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, v: i64) {
+    // Zigzag encode so that small negative values stay small.
+    let mut v = ((v << 1) ^ (v >> 63)) as u64;
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<i64> {
+    let mut v = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = input
+            .split_first()
+            .ok_or_else(|| anyhow!("compressed frame is truncated"))?;
+        *input = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok((v >> 1) as i64 ^ -((v & 1) as i64));
+        }
+    }
+
+    Err(anyhow!("invalid varint"))
+}
+
+/// Stateful encoder of compressed frames.
+pub(crate) struct FrameEncoder {
+    quantizer: Quantizer,
+    keyframe_interval: u32,
+    since_reset: u32,
+    prev: HashMap<[i32; 2], [i32; 2]>,
+}
+
+impl FrameEncoder {
+    pub fn new(header: &MvecHeader, compression: &MvecCompression) -> Result<Self> {
+        Ok(Self {
+            quantizer: Quantizer::new(header, compression)?,
+            keyframe_interval: compression.keyframe_interval.max(1),
+            since_reset: 0,
+            prev: Default::default(),
+        })
+    }
+
+    /// Encode a frame.
+    ///
+    /// Returns whether prediction was reset at this frame, and the compressed payload. The
+    /// prediction state is left untouched if encoding fails.
+    pub fn encode(&mut self, vectors: &MotionVectors) -> Result<(bool, Vec<u8>)> {
+        let reset = self.since_reset == 0;
+
+        let q = &self.quantizer;
+        let quantized = vectors
+            .entries()
+            .map(|(pos, motion)| {
+                Ok((
+                    [q.quantize(pos.x, 0)?, q.quantize(pos.y, 1)?],
+                    [q.quantize(motion.x, 0)?, q.quantize(motion.y, 1)?],
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let positions = quantized.iter().map(|&(pos, _)| pos).collect::<Vec<_>>();
+        let grid = PositionGrid::new(&positions);
+
+        let mut data = Vec::with_capacity(vectors.len() * 2 + GRID_LEN);
+        grid.write(&mut data);
+
+        if grid.dense_cols.is_none() {
+            let mut last = [0; 2];
+
+            for &pos in &positions {
+                let cell = grid.cell(pos);
+                for i in 0..2 {
+                    write_varint(&mut data, cell[i] as i64 - last[i] as i64);
+                }
+                last = cell;
+            }
+        }
+
+        let empty = HashMap::new();
+        let prev = if reset { &empty } else { &self.prev };
+
+        for (pos, motion) in &quantized {
+            let pred = prev.get(pos).copied().unwrap_or_default();
+
+            for i in 0..2 {
+                write_varint(&mut data, motion[i] as i64 - pred[i] as i64);
+            }
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&data)?;
+        let payload = encoder.finish()?;
+
+        self.since_reset = (self.since_reset + 1) % self.keyframe_interval;
+        self.prev = quantized.into_iter().collect();
+
+        Ok((reset, payload))
+    }
+}
+
+/// Add a decoded residual to its prediction.
+///
+/// Corrupt streams may hold residuals that do not fit the quantised range, these are rejected.
+fn add_residual(pred: i32, residual: i64) -> Result<i32> {
+    (pred as i64)
+        .checked_add(residual)
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or_else(|| anyhow!("compressed frame has out of range values"))
+}
+
+/// Stateful decoder of compressed frames.
+pub(crate) struct FrameDecoder {
+    quantizer: Quantizer,
+    prev: HashMap<[i32; 2], [i32; 2]>,
+}
+
+impl FrameDecoder {
+    pub fn new(header: &MvecHeader, compression: &MvecCompression) -> Result<Self> {
+        Ok(Self {
+            quantizer: Quantizer::new(header, compression)?,
+            prev: Default::default(),
+        })
+    }
+
+    /// Decode a frame, appending its vectors to `out`.
+    pub fn decode(
+        &mut self,
+        frame: &FrameHeader,
+        payload: &[u8],
+        out: &mut MotionVectors,
+    ) -> Result<()> {
+        if frame.flags & FRAME_RESET != 0 {
+            self.prev.clear();
+        }
+
+        let count = frame.count as usize;
+
+        let mut data = vec![];
+        DeflateDecoder::new(payload)
+            .take(max_raw_size(frame.count) as u64)
+            .read_to_end(&mut data)?;
+
+        let mut input = data.as_slice();
+        let mut positions = Vec::with_capacity(count);
+
+        let grid = PositionGrid::read(&mut input)?;
+
+        if let Some(cols) = grid.dense_cols {
+            if !count.is_multiple_of(cols as usize) {
+                return Err(anyhow!("compressed frame does not fill its grid"));
+            }
+
+            for i in 0..count as u32 {
+                positions.push(grid.position([i % cols, i / cols])?);
+            }
+        } else {
+            let mut last = [0u32; 2];
+
+            for _ in 0..count {
+                for l in &mut last {
+                    *l = (*l as i64)
+                        .checked_add(read_varint(&mut input)?)
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or_else(|| anyhow!("compressed frame has out of range grid cells"))?;
+                }
+                positions.push(grid.position(last)?);
+            }
+        }
+
+        let mut cur = HashMap::with_capacity(count);
+        out.reserve(count);
+
+        for pos in positions {
+            let pred = self.prev.get(&pos).copied().unwrap_or_default();
+            let motion = [
+                add_residual(pred[0], read_varint(&mut input)?)?,
+                add_residual(pred[1], read_varint(&mut input)?)?,
+            ];
+
+            let q = &self.quantizer;
+            out.push((
+                na::Point2::new(q.dequantize(pos[0], 0), q.dequantize(pos[1], 1)),
+                na::Vector2::new(q.dequantize(motion[0], 0), q.dequantize(motion[1], 1)),
+            ));
+
+            cur.insert(pos, motion);
+        }
+
+        if !input.is_empty() {
+            return Err(anyhow!("compressed frame has trailing data"));
+        }
+
+        self.prev = cur;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Generate codec-like vectors, with motion in whole quarter pixels.
+    fn grid_frame(frame: i32, width: usize, height: usize) -> MotionVectors {
+        let norm = na::Vector2::new(1f32 / width as f32, 1f32 / height as f32);
+
+        (0..(height / 16))
+            .flat_map(|y| (0..(width / 16)).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = (x as i32 * 16 + 8, y as i32 * 16 + 8);
+                let (mx, my) = ((x / 16 + frame) % 5 - 2, (y / 16) % 3 - 1);
+                let pos = na::Vector2::new(x as f32, y as f32).component_mul(&norm);
+                let motion = na::Vector2::new(mx as f32, my as f32)
+                    .component_div(&na::Vector2::new(4f32, 4f32))
+                    .component_mul(&-norm);
+                (pos.into(), motion)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compressed_roundtrip() -> Result<()> {
+        let (width, height) = (1280, 720);
+        let header = MvecHeader::new(Some((width, height)), Some(30.0))
+            .compression(Some(Default::default()));
+
+        let frames = (0..40)
+            .map(|f| grid_frame(f, width, height))
+            .collect::<Vec<_>>();
+
+        let mut writer = MvecWriter::new(vec![], header)?;
+        let mut raw = MvecWriter::new(vec![], header.compression(None))?;
+
+        for vectors in &frames {
+            writer.write_frame(None, 0, vectors)?;
+            raw.write_frame(None, 0, vectors)?;
+        }
+
+        let data = writer.finish()?;
+        let raw = raw.finish()?;
+
+        assert!(data.len() * 10 < raw.len());
+
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+        assert_eq!(file.header().compression, Some(Default::default()));
+
+        for vectors in &frames {
+            let mut field = MotionVectors::new();
+            file.read_frame(&mut field)?;
+            // Values must round-trip exactly.
+            assert_eq!(&field, vectors);
+        }
+
+        assert!(file.read_frame(&mut MotionVectors::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn grid_positions() -> Result<()> {
+        let (width, height) = (1280, 720);
+        let header = MvecHeader::new(Some((width, height)), Some(30.0));
+        let dense = grid_frame(0, width, height);
+
+        // Drop some blocks, and split others into two vectors at half the block size.
+        let sparse = dense
+            .entries()
+            .enumerate()
+            .filter(|(i, _)| i % 7 != 3)
+            .flat_map(|(i, (pos, motion))| {
+                let split =
+                    (i % 5 == 0).then(|| (pos + na::Vector2::new(4.0 / width as f32, 0.0), motion));
+                std::iter::once((pos, motion)).chain(split)
+            })
+            .chain(dense.entries().take(2))
+            .collect::<MotionVectors>();
+
+        let quantize = |vectors: &MotionVectors| {
+            let q = Quantizer::new(&header, &Default::default()).unwrap();
+            vectors
+                .entries()
+                .map(|(p, _)| [q.quantize(p.x, 0).unwrap(), q.quantize(p.y, 1).unwrap()])
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            PositionGrid::new(&quantize(&dense)).dense_cols,
+            Some(width as u32 / 16)
+        );
+        assert_eq!(PositionGrid::new(&quantize(&sparse)).dense_cols, None);
+
+        let mut encoder = FrameEncoder::new(&header, &Default::default())?;
+        let mut decoder = FrameDecoder::new(&header, &Default::default())?;
+
+        for vectors in [dense, sparse, MotionVectors::new()] {
+            let (reset, payload) = encoder.encode(&vectors)?;
+            let frame = FrameHeader {
+                timestamp: None,
+                flags: if reset { FRAME_RESET } else { 0 },
+                count: vectors.len() as u32,
+            };
+
+            let mut out = MotionVectors::new();
+            decoder.decode(&frame, &payload, &mut out)?;
+            assert_eq!(out, vectors);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_encode() -> Result<()> {
+        let (width, height) = (1280, 720);
+        let header = MvecHeader::new(Some((width, height)), Some(30.0));
+        let frames = [grid_frame(0, width, height), grid_frame(1, width, height)];
+
+        let mut invalid = frames[0].clone();
+        invalid.push((na::Point2::new(0.5, 0.5), na::Vector2::new(f32::NAN, 0.0)));
+
+        let mut encoder = FrameEncoder::new(&header, &Default::default())?;
+        let mut decoder = FrameDecoder::new(&header, &Default::default())?;
+
+        // Failures must neither use up the reset, nor change the prediction.
+        assert!(encoder.encode(&invalid).is_err());
+
+        for (i, vectors) in frames.iter().enumerate() {
+            let (reset, payload) = encoder.encode(vectors)?;
+            assert_eq!(reset, i == 0);
+
+            assert!(encoder.encode(&invalid).is_err());
+
+            let frame = FrameHeader {
+                timestamp: None,
+                flags: if reset { FRAME_RESET } else { 0 },
+                count: vectors.len() as u32,
+            };
+
+            let mut out = MotionVectors::new();
+            decoder.decode(&frame, &payload, &mut out)?;
+            assert_eq!(&out, vectors);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn varint_roundtrip() -> Result<()> {
+        let values = [0, 1, -1, 63, -64, 64, i32::MAX as i64, i64::MIN, i64::MAX];
+
+        let mut buf = vec![];
+        for &v in &values {
+            write_varint(&mut buf, v);
+        }
+
+        let mut input = buf.as_slice();
+        for &v in &values {
+            assert_eq!(read_varint(&mut input)?, v);
+        }
+
+        assert!(input.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_residuals() -> Result<()> {
+        let header = MvecHeader::new(Some((1280, 720)), Some(30.0));
+        let frame = FrameHeader {
+            timestamp: None,
+            flags: FRAME_RESET,
+            count: 1,
+        };
+
+        // Sparse grid with unit steps at the origin, followed by a single vector.
+        let compress = |values: &[i64]| -> Result<Vec<u8>> {
+            let mut raw = vec![];
+            for &v in [0, 1, 0, 1, 0].iter().chain(values) {
+                write_varint(&mut raw, v);
+            }
+            let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+            encoder.write_all(&raw)?;
+            Ok(encoder.finish()?)
+        };
+
+        let mut decoder = FrameDecoder::new(&header, &Default::default())?;
+        let mut out = MotionVectors::new();
+        decoder.decode(&frame, &compress(&[8, 8, -4, 4])?, &mut out)?;
+        assert_eq!(out.len(), 1);
+
+        // Values outside of the quantised range are errors, rather than wrapping around.
+        for values in [
+            [i64::MAX, 0, 0, 0],
+            [i32::MAX as i64 + 1, 0, 0, 0],
+            [0, 0, i64::MIN, 0],
+            [0, 0, 0, i32::MIN as i64 - 1],
+        ] {
+            let mut decoder = FrameDecoder::new(&header, &Default::default())?;
+            assert!(decoder
+                .decode(&frame, &compress(&values)?, &mut MotionVectors::new())
+                .is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+//! # Memory mapped `.mvec` reader
+//!
+//! Provides random access to frames of large `.mvec` files without reading them in whole.
+
+use super::*;
+use bytemuck::{Pod, Zeroable};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+/// Motion vector, as laid out in a `.mvec` file.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct MvecEntry {
+    pub pos: [f32; 2],
+    pub motion: [f32; 2],
+}
+
+impl MvecEntry {
+    /// Size of the serialized entry in bytes.
+    pub const SIZE: usize = std::mem::size_of::<Self>();
+
+    fn to_native(self) -> Self {
+        let conv = |v: f32| f32::from_bits(u32::from_le(v.to_bits()));
+        Self {
+            pos: self.pos.map(conv),
+            motion: self.motion.map(conv),
+        }
+    }
+}
+
+impl From<MvecEntry> for MotionEntry {
+    fn from(entry: MvecEntry) -> Self {
+        (entry.pos.into(), entry.motion.into())
+    }
+}
+
+impl From<MotionEntry> for MvecEntry {
+    fn from((pos, motion): MotionEntry) -> Self {
+        Self {
+            pos: pos.coords.into(),
+            motion: motion.into(),
+        }
+    }
+}
+
+/// Single frame of a memory mapped `.mvec` file.
+#[derive(Clone, Debug)]
+pub struct MvecFrame<'a> {
+    /// Header of the frame.
+    pub header: FrameHeader,
+    entries: Cow<'a, [MvecEntry]>,
+}
+
+impl<'a> MvecFrame<'a> {
+    /// Get the motion vectors of the frame.
+    ///
+    /// On little endian targets this slice points directly into the mapped file.
+    pub fn entries(&self) -> &[MvecEntry] {
+        &self.entries
+    }
+
+    /// Iterate the motion vectors of the frame as `MotionEntry` elements.
+    pub fn motion_entries(&self) -> impl Iterator<Item = MotionEntry> + '_ {
+        self.entries.iter().copied().map(<_>::into)
+    }
+}
+
+/// Location of a frame within a `.mvec` file.
+struct FrameLayout {
+    header: FrameHeader,
+    /// Byte range of the vectors, or the compressed payload.
+    payload: Range<usize>,
+    /// Offset of the next frame.
+    next: usize,
+}
+
+/// Memory mapped `.mvec` file reader.
+///
+/// Upon opening, the frame index is loaded from the end of the file. If the file has no index,
+/// or is in legacy format, the index is built by scanning the frame headers.
+pub struct MvecMap {
+    map: Mmap,
+    header: MvecHeader,
+    offsets: Vec<usize>,
+}
+
+impl MvecMap {
+    /// Map a `.mvec` file into memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path to the file to open.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the file must not be modified while it is mapped. This is the same
+        // assumption any other reader of the file makes.
+        let map = unsafe { Mmap::map(&file)? };
+        Self::new(map)
+    }
+
+    /// Create a reader from an existing memory map.
+    pub fn new(map: Mmap) -> Result<Self> {
+        let data = &map[..];
+
+        let header = if data.starts_with(&MVEC_MAGIC) {
+            MvecHeader::read_after_magic(&mut &data[MVEC_MAGIC.len()..])?
+        } else {
+            MvecHeader {
+                version: 1,
+                ..Default::default()
+            }
+        };
+
+        let offsets = match Self::read_index(data, &header) {
+            Some(offsets) => offsets,
+            None => Self::scan_index(data, &header),
+        };
+
+        Ok(Self {
+            map,
+            header,
+            offsets,
+        })
+    }
+
+    /// Load the frame index footer, if it is present and valid.
+    fn read_index(data: &[u8], header: &MvecHeader) -> Option<Vec<usize>> {
+        if header.version < 2 || !data.ends_with(&INDEX_MAGIC) {
+            return None;
+        }
+
+        let trailer = data.len().checked_sub(INDEX_MAGIC.len() + 8)?;
+        let count = u64::from_le_bytes(data[trailer..][..8].try_into().ok()?);
+        let index = trailer.checked_sub(usize::try_from(count).ok()?.checked_mul(8)?)?;
+        let end = index.checked_sub(FrameHeader::SIZE)?;
+
+        if end < header.size() || !FrameHeader::read(&mut &data[end..]).ok()?.is_end() {
+            return None;
+        }
+
+        data[index..trailer]
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .map(|offset| {
+                let offset = usize::try_from(offset).ok()?;
+
+                if offset < header.size() {
+                    return None;
+                }
+
+                let frame = Self::parse_frame(&data[..end], header, offset)?;
+
+                if frame.header.is_end() {
+                    None
+                } else {
+                    Some(offset)
+                }
+            })
+            .collect()
+    }
+
+    /// Build the frame index by walking through all frame headers.
+    ///
+    /// A truncated frame at the end of the file is ignored.
+    fn scan_index(data: &[u8], header: &MvecHeader) -> Vec<usize> {
+        let mut offsets = vec![];
+
+        let mut offset = if header.version < 2 { 0 } else { header.size() };
+
+        while let Some(frame) = Self::parse_frame(data, header, offset) {
+            if frame.header.is_end() {
+                break;
+            }
+
+            offsets.push(offset);
+            offset = frame.next;
+        }
+
+        offsets
+    }
+
+    /// Parse the layout of a frame at given offset.
+    ///
+    /// `None` is returned if the frame is invalid, or does not fit in `data`.
+    fn parse_frame(data: &[u8], header: &MvecHeader, offset: usize) -> Option<FrameLayout> {
+        let mut input = data.get(offset..)?;
+
+        let frame = if header.version < 2 {
+            FrameHeader {
+                count: check_count(read_u32(&mut input).ok()?).ok()?,
+                ..Default::default()
+            }
+        } else {
+            FrameHeader::read(&mut input).ok()?
+        };
+
+        let start = data.len() - input.len();
+
+        let (payload, next) = if frame.is_end() {
+            (start..start, start)
+        } else if header.compression.is_some() {
+            let size = read_payload_size(&mut input, frame.count).ok()?;
+            let start = start + std::mem::size_of::<u32>();
+            (start..(start + size), start + size + payload_padding(size))
+        } else {
+            let end = start + frame.count as usize * MvecEntry::SIZE;
+            (start..end, end)
+        };
+
+        if next > data.len() {
+            None
+        } else {
+            Some(FrameLayout {
+                header: frame,
+                payload,
+                next,
+            })
+        }
+    }
+
+    /// Get the header of the file.
+    pub fn header(&self) -> &MvecHeader {
+        &self.header
+    }
+
+    /// Get the number of frames in the file.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Check whether the file contains no frames.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Get a frame at given index.
+    ///
+    /// Frames of compressed files are decoded starting from the last prediction reset, thus
+    /// their entries are never borrowed from the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - index of the frame to get.
+    pub fn frame(&self, idx: usize) -> Result<MvecFrame<'_>> {
+        let layout = |idx: usize| {
+            let offset = *self
+                .offsets
+                .get(idx)
+                .ok_or_else(|| anyhow!("frame {idx} out of range ({})", self.len()))?;
+
+            Self::parse_frame(&self.map, &self.header, offset)
+                .ok_or_else(|| anyhow!("invalid frame at {offset}"))
+        };
+
+        let frame = layout(idx)?;
+
+        if let Some(compression) = &self.header.compression {
+            // Walk back to the frame prediction was last reset at.
+            let mut start = idx;
+            while start > 0 && layout(start)?.header.flags & FRAME_RESET == 0 {
+                start -= 1;
+            }
+
+            let mut decoder = FrameDecoder::new(&self.header, compression)?;
+            let mut vectors = MotionVectors::new();
+
+            for i in start..=idx {
+                let frame = layout(i)?;
+                vectors.clear();
+                decoder.decode(&frame.header, &self.map[frame.payload], &mut vectors)?;
+            }
+
+            return Ok(MvecFrame {
+                header: frame.header,
+                entries: Cow::Owned(vectors.entries().map(<_>::into).collect()),
+            });
+        }
+
+        let bytes = &self.map[frame.payload];
+
+        let entries = match bytemuck::try_cast_slice::<_, MvecEntry>(bytes) {
+            Ok(entries) if cfg!(target_endian = "little") => Cow::Borrowed(entries),
+            _ => Cow::Owned(
+                bytes
+                    .chunks_exact(MvecEntry::SIZE)
+                    .map(|c| bytemuck::pod_read_unaligned::<MvecEntry>(c).to_native())
+                    .collect(),
+            ),
+        };
+
+        Ok(MvecFrame {
+            header: frame.header,
+            entries,
+        })
+    }
+
+    /// Iterate over all frames in the file.
+    pub fn frames(&self) -> impl Iterator<Item = Result<MvecFrame<'_>>> {
+        (0..self.len()).map(|i| self.frame(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("motion-loader-{}-{name}.mvec", std::process::id()))
+    }
+
+    fn test_frames() -> Vec<MotionVectors> {
+        (0..10)
+            .map(|f| {
+                (0..f)
+                    .map(|i| {
+                        let v = (f * 100 + i) as f32;
+                        (na::Point2::new(v, -v), na::Vector2::new(v * 0.5, v * 2.0))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn check_map(path: &Path, frames: &[MotionVectors]) -> Result<()> {
+        let map = MvecMap::open(path)?;
+        assert_eq!(map.len(), frames.len());
+
+        // Access in reverse to exercise random access.
+        for (i, vectors) in frames.iter().enumerate().rev() {
+            let frame = map.frame(i)?;
+            if map.header().version >= 2 {
+                assert_eq!(frame.header.timestamp, Some(i as f64));
+            }
+            assert_eq!(&frame.motion_entries().collect::<MotionVectors>(), vectors);
+        }
+
+        assert!(map.frame(frames.len()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mmap_random_access() -> Result<()> {
+        let frames = test_frames();
+
+        let compression = Some(MvecCompression {
+            motion_scale: 4,
+            keyframe_interval: 3,
+        });
+
+        for (name, index, legacy, compression) in [
+            ("indexed", true, false, None),
+            ("unindexed", false, false, None),
+            ("legacy", false, true, None),
+            ("compressed", true, false, compression),
+            ("compressed-unindexed", false, false, compression),
+        ] {
+            let path = temp_path(name);
+            let out = File::create(&path)?;
+
+            let mut writer = if legacy {
+                MvecWriter::legacy(out)
+            } else {
+                let header = MvecHeader::new(Some((64, 64)), None).compression(compression);
+                MvecWriter::new(out, header)?.index(index)
+            };
+
+            for (i, vectors) in frames.iter().enumerate() {
+                writer.write_frame(Some(i as f64), 0, vectors)?;
+            }
+
+            writer.finish()?;
+
+            let ret = check_map(&path, &frames);
+            std::fs::remove_file(&path)?;
+            ret?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_stops_at_index() -> Result<()> {
+        let frames = test_frames();
+
+        let mut writer = MvecWriter::new(vec![], MvecHeader::default())?;
+        for vectors in &frames {
+            writer.write_frame(None, 0, vectors)?;
+        }
+        let data = writer.finish()?;
+
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+
+        for vectors in &frames {
+            let mut field = MotionVectors::new();
+            file.read_frame(&mut field)?;
+            assert_eq!(&field, vectors);
+        }
+
+        assert!(file.read_frame(&mut MotionVectors::new()).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1207 @@
+//! # Motion vector container format
+//!
+//! `.mvec` files store raw motion vectors extracted by a decoder, so that they can be replayed
+//! without decoding the video again.
+//!
+//! ## Legacy format
+//!
+//! The legacy format has no header. Each frame is a little endian `u32` vector count, followed
+//! by `count` groups of 4 little endian `f32` values (`pos.x`, `pos.y`, `motion.x`, `motion.y`).
+//!
+//! ## Version 2
+//!
+//! Version 2 starts with a header:
+//!
+//! | Type      | Field     | Description                                 |
+//! |-----------|-----------|---------------------------------------------|
+//! | `[u8; 4]` | magic     | `MVEC`                                      |
+//! | `u32`     | version   | format version, currently `2`               |
+//! | `u32`     | width     | stream width in pixels (`0` if unknown)     |
+//! | `u32`     | height    | stream height in pixels (`0` if unknown)    |
+//! | `f64`     | framerate | stream framerate (`0` if unknown)           |
+//! | `u32`     | flags     | stream flags, see [`STREAM_COMPRESSED`]     |
+//!
+//! Each frame is then prefixed with a frame header:
+//!
+//! | Type  | Field     | Description                                 |
+//! |-------|-----------|---------------------------------------------|
+//! | `f64` | timestamp | presentation time in seconds (`NaN` if unknown) |
+//! | `u32` | flags     | frame flags, see [`FRAME_INTRA`]            |
+//! | `u32` | count     | number of motion vectors in the frame       |
+//!
+//! The vectors follow in the same layout as in the legacy format. All values are little endian.
+//!
+//! ### Compression
+//!
+//! If [`STREAM_COMPRESSED`] flag is set, the header is followed by compression parameters:
+//!
+//! | Type  | Field             | Description                                 |
+//! |-------|-------------------|---------------------------------------------|
+//! | `u32` | motion_scale      | quantisation steps per pixel                |
+//! | `u32` | keyframe_interval | maximum frames between prediction resets    |
+//!
+//! Vectors of each frame are then replaced with a `u32` payload length, followed by the
+//! compressed payload, padded with zeroes to a multiple of 4 bytes. See [`codec`] module for
+//! details.
+//!
+//! ### Frame index
+//!
+//! Versioned streams may end with a frame index, allowing random access to frames without
+//! scanning the whole file. The index is preceded by a frame header with [`FRAME_END`] flag set,
+//! which makes streaming readers stop before the index:
+//!
+//! | Type       | Field   | Description                                  |
+//! |------------|---------|----------------------------------------------|
+//! | `[u64; n]` | offsets | byte offsets of each frame header            |
+//! | `u64`      | n       | number of frames                             |
+//! | `[u8; 4]`  | magic   | `MIDX`                                       |
+
+use ofps::prelude::v1::*;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use nalgebra as na;
+
+pub mod codec;
+pub mod mmap;
+
+pub use codec::MvecCompression;
+use codec::{FrameDecoder, FrameEncoder};
+pub use mmap::{MvecEntry, MvecFrame, MvecMap};
+
+/// Magic bytes at the start of versioned `.mvec` files.
+pub const MVEC_MAGIC: [u8; 4] = *b"MVEC";
+
+/// Latest `.mvec` format version.
+pub const MVEC_VERSION: u32 = 2;
+
+/// Maximum number of motion vectors accepted in a single frame.
+///
+/// This guards against allocating absurd amounts of memory when reading corrupt files.
+pub const MAX_VECTORS: u32 = 1 << 24;
+
+/// Magic bytes at the end of versioned `.mvec` files containing a frame index.
+pub const INDEX_MAGIC: [u8; 4] = *b"MIDX";
+
+/// Stream vectors are stored in compressed form.
+pub const STREAM_COMPRESSED: u32 = 1 << 0;
+
+/// Frame was intra coded, and thus it does not contain motion vectors.
+pub const FRAME_INTRA: u32 = 1 << 0;
+
+/// Motion prediction of a compressed stream is reset at this frame.
+///
+/// This flag is managed by the writer, and can not be set explicitly.
+pub const FRAME_RESET: u32 = 1 << 1;
+
+/// Mask of the picture type bits of frame flags.
+///
+/// If none of the bits are set, picture type is unknown.
+pub const FRAME_TYPE_MASK: u32 = 0b11 << 2;
+
+/// Frame is an I picture.
+pub const FRAME_TYPE_I: u32 = 1 << 2;
+
+/// Frame is a P picture.
+pub const FRAME_TYPE_P: u32 = 2 << 2;
+
+/// Frame is a B picture.
+pub const FRAME_TYPE_B: u32 = 3 << 2;
+
+/// Frame is a keyframe.
+pub const FRAME_KEY: u32 = 1 << 4;
+
+/// Frame contains vectors referencing a past frame.
+pub const FRAME_FORWARD: u32 = 1 << 5;
+
+/// Frame contains vectors referencing a future frame.
+pub const FRAME_BACKWARD: u32 = 1 << 6;
+
+/// Build frame flags from decoder frame metadata.
+///
+/// # Arguments
+///
+/// * `info` - metadata of the frame.
+/// * `has_motion` - whether the frame contains motion vectors, as returned by
+///   `Decoder::process_frame`. If not, [`FRAME_INTRA`] flag is set.
+pub fn frame_flags(info: &FrameInfo, has_motion: bool) -> u32 {
+    let mut flags = match info.picture_type {
+        PictureType::Unknown => 0,
+        PictureType::I => FRAME_TYPE_I,
+        PictureType::P => FRAME_TYPE_P,
+        PictureType::B => FRAME_TYPE_B,
+    };
+
+    for (set, flag) in [
+        (!has_motion, FRAME_INTRA),
+        (info.keyframe, FRAME_KEY),
+        (info.forward, FRAME_FORWARD),
+        (info.backward, FRAME_BACKWARD),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+
+    flags
+}
+
+/// Marks the end of the stream. No frames follow, but a frame index may.
+pub const FRAME_END: u32 = 1 << 31;
+
+/// `.mvec` stream header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MvecHeader {
+    /// Format version. Legacy files are reported as version 1.
+    pub version: u32,
+    /// Width of the stream in pixels, `0` if unknown.
+    pub width: u32,
+    /// Height of the stream in pixels, `0` if unknown.
+    pub height: u32,
+    /// Framerate of the stream, `0` if unknown.
+    pub framerate: f64,
+    /// Compression parameters, `None` if vectors are stored raw.
+    pub compression: Option<MvecCompression>,
+}
+
+impl Default for MvecHeader {
+    fn default() -> Self {
+        Self {
+            version: MVEC_VERSION,
+            width: 0,
+            height: 0,
+            framerate: 0.0,
+            compression: None,
+        }
+    }
+}
+
+impl MvecHeader {
+    /// Size of the serialized header without compression parameters in bytes.
+    pub const BASE_SIZE: usize = 28;
+
+    /// Create a header from decoder stream properties.
+    ///
+    /// # Arguments
+    ///
+    /// * `aspect` - dimensions of the stream, as returned by `Decoder::get_aspect`.
+    /// * `framerate` - framerate of the stream, as returned by `Decoder::get_framerate`.
+    pub fn new(aspect: Option<(usize, usize)>, framerate: Option<f64>) -> Self {
+        let (width, height) = aspect.unwrap_or_default();
+
+        Self {
+            width: width as u32,
+            height: height as u32,
+            framerate: framerate.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Set compression parameters of the stream.
+    pub fn compression(self, compression: Option<MvecCompression>) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    /// Size of the serialized header in bytes.
+    pub fn size(&self) -> usize {
+        Self::BASE_SIZE
+            + if self.compression.is_some() {
+                MvecCompression::SIZE
+            } else {
+                0
+            }
+    }
+
+    /// Get the stream framerate, if known.
+    pub fn framerate(&self) -> Option<f64> {
+        Some(self.framerate).filter(|&f| f > 0.0 && f.is_finite())
+    }
+
+    /// Get the stream dimensions, if known.
+    pub fn aspect(&self) -> Option<(usize, usize)> {
+        Some((self.width as usize, self.height as usize)).filter(|&(w, h)| w > 0 && h > 0)
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<()> {
+        out.write_all(&MVEC_MAGIC)?;
+        out.write_all(&self.version.to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&self.framerate.to_le_bytes())?;
+
+        if let Some(compression) = &self.compression {
+            out.write_all(&STREAM_COMPRESSED.to_le_bytes())?;
+            compression.write(out)?;
+        } else {
+            out.write_all(&0u32.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the rest of the header after the magic bytes.
+    fn read_after_magic(input: &mut impl Read) -> Result<Self> {
+        let mut header = Self {
+            version: read_u32(input)?,
+            width: read_u32(input)?,
+            height: read_u32(input)?,
+            framerate: read_f64(input)?,
+            compression: None,
+        };
+
+        if header.version < 2 || header.version > MVEC_VERSION {
+            return Err(anyhow!("unsupported mvec version {}", header.version));
+        }
+
+        let flags = read_u32(input)?;
+
+        if flags & !STREAM_COMPRESSED != 0 {
+            return Err(anyhow!("unsupported mvec flags {:x}", flags));
+        }
+
+        if flags & STREAM_COMPRESSED != 0 {
+            header.compression = Some(MvecCompression::read(input)?);
+        }
+
+        Ok(header)
+    }
+}
+
+/// Per-frame header of a versioned `.mvec` stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameHeader {
+    /// Presentation time of the frame in seconds.
+    pub timestamp: Option<f64>,
+    /// Frame flags.
+    pub flags: u32,
+    /// Number of motion vectors in the frame.
+    pub count: u32,
+}
+
+impl FrameHeader {
+    /// Size of the serialized frame header in bytes.
+    pub const SIZE: usize = 16;
+
+    /// Check whether the frame was intra coded.
+    pub fn is_intra(&self) -> bool {
+        self.flags & FRAME_INTRA != 0
+    }
+
+    /// Check whether this header marks the end of the stream.
+    pub fn is_end(&self) -> bool {
+        self.flags & FRAME_END != 0
+    }
+
+    /// Get the picture type stored in the frame flags.
+    pub fn picture_type(&self) -> PictureType {
+        match self.flags & FRAME_TYPE_MASK {
+            FRAME_TYPE_I => PictureType::I,
+            FRAME_TYPE_P => PictureType::P,
+            FRAME_TYPE_B => PictureType::B,
+            _ => PictureType::Unknown,
+        }
+    }
+
+    /// Convert the header into decoder frame metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `decode_index` - index of the frame in the stream.
+    pub fn info(&self, decode_index: usize) -> FrameInfo {
+        FrameInfo {
+            pts: self.timestamp,
+            decode_index,
+            picture_type: self.picture_type(),
+            keyframe: self.flags & FRAME_KEY != 0,
+            forward: self.flags & FRAME_FORWARD != 0,
+            backward: self.flags & FRAME_BACKWARD != 0,
+        }
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<()> {
+        out.write_all(&self.timestamp.unwrap_or(f64::NAN).to_le_bytes())?;
+        out.write_all(&self.flags.to_le_bytes())?;
+        out.write_all(&self.count.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn read(input: &mut impl Read) -> Result<Self> {
+        let timestamp = Some(read_f64(input)?).filter(|t| t.is_finite());
+        let flags = read_u32(input)?;
+        let count = check_count(read_u32(input)?)?;
+
+        Ok(Self {
+            timestamp,
+            flags,
+            count,
+        })
+    }
+}
+
+fn check_count(count: u32) -> Result<u32> {
+    if count > MAX_VECTORS {
+        Err(anyhow!(
+            "frame has {count} motion vectors, exceeding the limit of {MAX_VECTORS}"
+        ))
+    } else {
+        Ok(count)
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; std::mem::size_of::<u32>()];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; std::mem::size_of::<u64>()];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64(input: &mut impl Read) -> std::io::Result<f64> {
+    let mut buf = [0u8; std::mem::size_of::<f64>()];
+    input.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+fn write_vectors(out: &mut impl Write, vectors: &MotionVectors) -> Result<()> {
+    // Write each MV as a 4 f32 groups (in LE).
+    for v in vectors.entries().flat_map(|(a, m)| [a.x, a.y, m.x, m.y]) {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read the length of a compressed payload, and check it against the vector count.
+fn read_payload_size(input: &mut impl Read, count: u32) -> Result<usize> {
+    let size = read_u32(input)? as usize;
+
+    if size > codec::max_payload_size(count) {
+        Err(anyhow!("compressed payload of {size} bytes is too large"))
+    } else {
+        Ok(size)
+    }
+}
+
+/// Get the number of padding bytes after a compressed payload.
+fn payload_padding(size: usize) -> usize {
+    (4 - size % 4) % 4
+}
+
+fn read_vectors(input: &mut impl Read, count: u32, field: &mut MotionVectors) -> Result<()> {
+    field.reserve(count as usize);
+    for _ in 0..count {
+        let mut data = [[0u8; std::mem::size_of::<f32>()]; 4];
+        for b in &mut data {
+            input.read_exact(&mut *b)?;
+        }
+        field.push((
+            na::Point2::new(f32::from_le_bytes(data[0]), f32::from_le_bytes(data[1])),
+            na::Vector2::new(f32::from_le_bytes(data[2]), f32::from_le_bytes(data[3])),
+        ))
+    }
+    Ok(())
+}
+
+/// Writes motion vectors into a `.mvec` stream.
+pub struct MvecWriter<W> {
+    writer: W,
+    legacy: bool,
+    /// Current position in the stream.
+    pos: u64,
+    /// Offsets of written frames, `None` if the index is not being written.
+    offsets: Option<Vec<u64>>,
+    /// Encoder of compressed streams.
+    encoder: Option<FrameEncoder>,
+}
+
+impl<W: Write> MvecWriter<W> {
+    /// Create a new versioned stream writer.
+    ///
+    /// The header is written immediately. By default, a frame index is appended when the
+    /// writer is finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - output to write the stream to.
+    /// * `header` - stream header. Its version is overridden with the latest one. If
+    ///   compression is enabled, stream dimensions must be known.
+    pub fn new(mut writer: W, header: MvecHeader) -> Result<Self> {
+        let header = MvecHeader {
+            version: MVEC_VERSION,
+            ..header
+        };
+
+        let encoder = header
+            .compression
+            .map(|compression| FrameEncoder::new(&header, &compression))
+            .transpose()?;
+
+        header.write(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            legacy: false,
+            pos: header.size() as u64,
+            offsets: Some(vec![]),
+            encoder,
+        })
+    }
+
+    /// Set whether to append a frame index at the end of the stream.
+    ///
+    /// Legacy streams never contain an index.
+    pub fn index(self, index: bool) -> Self {
+        Self {
+            offsets: if index && !self.legacy {
+                Some(self.offsets.unwrap_or_default())
+            } else {
+                None
+            },
+            ..self
+        }
+    }
+
+    /// Create a new writer outputting the legacy, headerless format.
+    ///
+    /// Legacy streams do not store timestamps or frame flags, they get silently dropped.
+    pub fn legacy(writer: W) -> Self {
+        Self {
+            writer,
+            legacy: true,
+            pos: 0,
+            offsets: None,
+            encoder: None,
+        }
+    }
+
+    /// Write a single frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - presentation time of the frame in seconds.
+    /// * `flags` - frame flags, such as [`FRAME_INTRA`].
+    /// * `vectors` - motion vectors of the frame. Only positions and motion get stored.
+    pub fn write_frame(
+        &mut self,
+        timestamp: Option<f64>,
+        flags: u32,
+        vectors: &MotionVectors,
+    ) -> Result<()> {
+        let count = check_count(vectors.len().try_into()?)?;
+
+        if flags & (FRAME_END | FRAME_RESET) != 0 {
+            return Err(anyhow!(
+                "reserved frame flags can not be written explicitly"
+            ));
+        }
+
+        // Encode before anything is written, so that failures leave the stream intact.
+        let encoded = self
+            .encoder
+            .as_mut()
+            .map(|encoder| encoder.encode(vectors))
+            .transpose()?;
+
+        if let Some(offsets) = &mut self.offsets {
+            offsets.push(self.pos);
+        }
+
+        if self.legacy {
+            self.writer.write_all(&count.to_le_bytes())?;
+            self.pos += std::mem::size_of::<u32>() as u64;
+        } else if let Some((reset, payload)) = encoded {
+            FrameHeader {
+                timestamp,
+                flags: if reset { flags | FRAME_RESET } else { flags },
+                count,
+            }
+            .write(&mut self.writer)?;
+
+            let padding = payload_padding(payload.len());
+            self.writer
+                .write_all(&(payload.len() as u32).to_le_bytes())?;
+            self.writer.write_all(&payload)?;
+            self.writer.write_all(&[0; 4][..padding])?;
+
+            self.pos += (FrameHeader::SIZE + 4 + payload.len() + padding) as u64;
+
+            return Ok(());
+        } else {
+            FrameHeader {
+                timestamp,
+                flags,
+                count,
+            }
+            .write(&mut self.writer)?;
+            self.pos += FrameHeader::SIZE as u64;
+        }
+
+        write_vectors(&mut self.writer, vectors)?;
+        self.pos += (vectors.len() * MvecEntry::SIZE) as u64;
+
+        Ok(())
+    }
+
+    /// Finish the stream and return the underlying writer.
+    ///
+    /// This writes out the frame index, if enabled, and flushes the writer.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(offsets) = self.offsets.take() {
+            FrameHeader {
+                timestamp: None,
+                flags: FRAME_END,
+                count: 0,
+            }
+            .write(&mut self.writer)?;
+
+            for offset in &offsets {
+                self.writer.write_all(&offset.to_le_bytes())?;
+            }
+
+            self.writer
+                .write_all(&(offsets.len() as u64).to_le_bytes())?;
+            self.writer.write_all(&INDEX_MAGIC)?;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads `.mvec` streams of either legacy or versioned format.
+///
+/// If the underlying reader supports seeking, so does the stream. Frame offsets are loaded from
+/// the frame index, if there is one, otherwise they are discovered while reading the stream.
+pub struct MvecFile<T> {
+    reader: T,
+    header: MvecHeader,
+    /// Vector count of the first legacy frame, read while probing for the header.
+    pending_count: Option<u32>,
+    /// Decoder of compressed streams.
+    decoder: Option<FrameDecoder>,
+    /// Whether the reader supports seeking.
+    seekable: bool,
+    /// Current position in the stream.
+    pos: u64,
+    /// Index of the next frame to be read.
+    frame: usize,
+    /// Known frame offsets. The last element is the offset of the first frame not yet read.
+    offsets: Vec<u64>,
+    /// Whether the end of the stream is known, meaning the last offset is past the last frame.
+    complete: bool,
+}
+
+impl<T: Read + Seek> MvecFile<T> {
+    /// Open a `.mvec` stream.
+    ///
+    /// The format is detected from the first bytes of the stream.
+    pub fn new(mut reader: T) -> Result<Self> {
+        let seekable = reader.stream_position().is_ok();
+        let mut magic = [0u8; 4];
+
+        let legacy = MvecHeader {
+            version: 1,
+            ..Default::default()
+        };
+
+        let (header, pending_count, complete) = match reader.read_exact(&mut magic) {
+            Ok(()) if magic == MVEC_MAGIC => {
+                (MvecHeader::read_after_magic(&mut reader)?, None, false)
+            }
+            // Legacy file - the first 4 bytes were the vector count.
+            Ok(()) => (legacy, Some(u32::from_le_bytes(magic)), false),
+            // Empty legacy file.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => (legacy, None, true),
+            Err(e) => return Err(e.into()),
+        };
+
+        let decoder = header
+            .compression
+            .map(|compression| FrameDecoder::new(&header, &compression))
+            .transpose()?;
+
+        let pos = if pending_count.is_some() || complete {
+            0
+        } else {
+            header.size() as u64
+        };
+
+        let mut ret = Self {
+            reader,
+            header,
+            pending_count,
+            decoder,
+            seekable,
+            pos,
+            frame: 0,
+            offsets: vec![pos],
+            complete,
+        };
+
+        if seekable && !ret.is_legacy() {
+            let index = ret.read_index();
+            ret.reader.seek(SeekFrom::Start(ret.pos))?;
+
+            if let Some(offsets) = index.ok().flatten() {
+                ret.offsets = offsets;
+                ret.complete = true;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Read the frame index at the end of the stream.
+    ///
+    /// The returned offsets include the offset of the end of stream marker.
+    fn read_index(&mut self) -> Result<Option<Vec<u64>>> {
+        let trailer = (INDEX_MAGIC.len() + 8) as u64;
+        let end = self.reader.seek(SeekFrom::End(0))?;
+
+        if end < self.pos + FrameHeader::SIZE as u64 + trailer {
+            return Ok(None);
+        }
+
+        self.reader.seek(SeekFrom::Start(end - trailer))?;
+        let count = read_u64(&mut self.reader)?;
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+
+        let index = count
+            .checked_mul(8)
+            .and_then(|size| (end - trailer).checked_sub(size))
+            .and_then(|index| index.checked_sub(FrameHeader::SIZE as u64))
+            .map(|marker| (marker, marker + FrameHeader::SIZE as u64));
+
+        let (marker, index) = match index {
+            Some(index) if magic == INDEX_MAGIC && index.0 >= self.pos => index,
+            _ => return Ok(None),
+        };
+
+        self.reader.seek(SeekFrom::Start(marker))?;
+
+        if !FrameHeader::read(&mut self.reader)?.is_end() {
+            return Ok(None);
+        }
+
+        self.reader.seek(SeekFrom::Start(index))?;
+
+        let mut offsets = (0..count)
+            .map(|_| read_u64(&mut self.reader))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        offsets.push(marker);
+
+        if offsets.first() != Some(&self.pos) || offsets.windows(2).any(|w| w[0] >= w[1]) {
+            return Ok(None);
+        }
+
+        Ok(Some(offsets))
+    }
+
+    /// Get the header of the stream.
+    pub fn header(&self) -> &MvecHeader {
+        &self.header
+    }
+
+    /// Check whether the stream is in legacy format.
+    pub fn is_legacy(&self) -> bool {
+        self.header.version < 2
+    }
+
+    /// Read the next frame.
+    ///
+    /// Motion vectors are appended to `field`, and the header of the frame is returned. Legacy
+    /// frames have no timestamp and no flags.
+    ///
+    /// An error is returned once the end of the stream is reached.
+    pub fn read_frame(&mut self, field: &mut MotionVectors) -> Result<FrameHeader> {
+        self.next_frame(Some(field))
+    }
+
+    /// Read the next frame header, and either decode or skip its vectors.
+    ///
+    /// Skipping leaves prediction state of compressed streams stale, and thus must only be
+    /// done while seeking.
+    fn next_frame(&mut self, field: Option<&mut MotionVectors>) -> Result<FrameHeader> {
+        let header = if self.is_legacy() {
+            match self.pending_count.take() {
+                Some(count) => Ok(count),
+                None => read_u32(&mut self.reader).map_err(Into::into),
+            }
+            .and_then(check_count)
+            .map(|count| FrameHeader {
+                count,
+                ..Default::default()
+            })
+        } else {
+            FrameHeader::read(&mut self.reader)
+        };
+
+        let frame = match header {
+            Ok(frame) if !frame.is_end() => frame,
+            Ok(_) => {
+                self.complete = true;
+                return Err(anyhow!("end of mvec stream"));
+            }
+            Err(e) => {
+                if let Some(ErrorKind::UnexpectedEof) =
+                    e.downcast_ref::<std::io::Error>().map(|e| e.kind())
+                {
+                    self.complete = true;
+                }
+                return Err(e);
+            }
+        };
+
+        let header_size = if self.is_legacy() {
+            std::mem::size_of::<u32>()
+        } else {
+            FrameHeader::SIZE
+        };
+
+        let payload_size = if let Some(decoder) = &mut self.decoder {
+            let size = read_payload_size(&mut self.reader, frame.count)?;
+            let padded = size + payload_padding(size);
+
+            if let Some(field) = field {
+                let mut payload = vec![0; padded];
+                self.reader.read_exact(&mut payload)?;
+                decoder.decode(&frame, &payload[..size], field)?;
+            } else {
+                self.reader.seek(SeekFrom::Current(padded as i64))?;
+            }
+
+            std::mem::size_of::<u32>() + padded
+        } else {
+            let size = frame.count as usize * MvecEntry::SIZE;
+
+            if let Some(field) = field {
+                read_vectors(&mut self.reader, frame.count, field)?;
+            } else {
+                self.reader.seek(SeekFrom::Current(size as i64))?;
+            }
+
+            size
+        };
+
+        self.pos += (header_size + payload_size) as u64;
+        self.frame += 1;
+
+        if self.frame == self.offsets.len() {
+            self.offsets.push(self.pos);
+        }
+
+        Ok(frame)
+    }
+
+    /// Move the reader to the start of a frame with known offset.
+    fn goto(&mut self, frame: usize) -> Result<()> {
+        let pos = self.offsets[frame];
+        self.reader.seek(SeekFrom::Start(pos))?;
+        self.pending_count = None;
+        self.pos = pos;
+        self.frame = frame;
+        Ok(())
+    }
+
+    /// Seek to the given frame.
+    ///
+    /// Compressed streams are decoded starting from the last prediction reset before the frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - index of the frame to be read next.
+    pub fn seek_frame(&mut self, frame: usize) -> Result<()> {
+        if !self.seekable {
+            return Err(anyhow!("mvec stream is not seekable"));
+        }
+
+        // Discover offsets of frames up to the target.
+        if frame >= self.offsets.len() - 1 && !self.complete {
+            self.goto(self.offsets.len() - 1)?;
+
+            while frame >= self.offsets.len() - 1 && !self.complete {
+                // Errors at the end of the stream are caught by the range check below.
+                if self.next_frame(None).is_err() && !self.complete {
+                    return Err(anyhow!("failed to read mvec frame headers"));
+                }
+            }
+        }
+
+        if frame >= self.offsets.len() - 1 {
+            return Err(anyhow!(
+                "frame {frame} out of range ({})",
+                self.offsets.len() - 1
+            ));
+        }
+
+        let mut start = frame;
+
+        if self.decoder.is_some() {
+            while start > 0 {
+                self.goto(start)?;
+                if FrameHeader::read(&mut self.reader)?.flags & FRAME_RESET != 0 {
+                    break;
+                }
+                start -= 1;
+            }
+        }
+
+        self.goto(start)?;
+
+        let mut field = MotionVectors::new();
+
+        while self.frame < frame {
+            field.clear();
+            self.read_frame(&mut field)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the last frame starting at or before given time.
+    ///
+    /// Returns `None` if the stream does not have timestamps.
+    fn find_time(&mut self, time: f64) -> Result<Option<usize>> {
+        if self.is_legacy() || !self.seekable {
+            return Ok(None);
+        }
+
+        let current = self.frame;
+
+        // Make sure all frame offsets are known. This fails with out of range error.
+        if !self.complete {
+            let _ = self.seek_frame(usize::MAX);
+        }
+
+        // Binary search assuming timestamps are monotonic.
+        let (mut lo, mut hi) = (0, self.offsets.len() - 1);
+
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            self.goto(mid)?;
+
+            match FrameHeader::read(&mut self.reader)?.timestamp {
+                Some(t) if t <= time => lo = mid + 1,
+                Some(_) => hi = mid,
+                None => {
+                    self.goto(current)?;
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(Some(lo.saturating_sub(1)))
+    }
+
+    /// Get the number of frames in the stream, if known.
+    ///
+    /// This is known if the stream has a frame index, or it has been read until the end.
+    pub fn frame_count(&self) -> Option<usize> {
+        Some(self.offsets.len() - 1).filter(|_| self.complete)
+    }
+}
+
+impl<T> Properties for MvecFile<T> {}
+
+impl<T: Read + Seek> Decoder for MvecFile<T> {
+    /// Process a single frame in the stream.
+    ///
+    /// This function will take in a single frame, and attempt extracting motion
+    /// vectors from it. If the frame contains MVs, `Ok(true)` is returned, and `field`
+    /// gets updated. If there are no motion vectors, `Ok(false)` is returned, and if
+    /// there is an error while processing, `Err` is returned.
+    ///
+    /// If the decoder supports it, `out_frame` may also be written at either of the `Ok` cases.
+    /// Likewise, `frame_info` gets filled with metadata of the frame. Fields the decoder does
+    /// not know are left at their default values.
+    fn process_frame(
+        &mut self,
+        field: &mut MotionVectors,
+        _: Option<(&mut Vec<RGBA>, &mut usize)>,
+        frame_info: Option<&mut FrameInfo>,
+        _: usize,
+    ) -> Result<bool> {
+        let frame = self.read_frame(field)?;
+
+        if let Some(frame_info) = frame_info {
+            *frame_info = frame.info(self.frame - 1);
+        }
+
+        Ok(!frame.is_intra())
+    }
+
+    /// Get the framerate of the stream.
+    ///
+    /// This will return `Some(framerate)` if it is known. On realtime streams it may
+    /// not always be known. In such cases, `None` is returned.
+    fn get_framerate(&self) -> Option<f64> {
+        self.header.framerate()
+    }
+
+    /// Get aspect ratio of the stream.
+    ///
+    /// This will return `Some((width, height))` if the aspect ratio is known. On rare
+    /// cases it may not be known, which will then return `None`. Aspect ratio may change
+    /// after the first frame is processed.
+    fn get_aspect(&self) -> Option<(usize, usize)> {
+        self.header.aspect()
+    }
+
+    fn can_seek(&self) -> bool {
+        self.seekable
+    }
+
+    fn seek_to_frame(&mut self, frame: usize) -> Result<()> {
+        self.seek_frame(frame)
+    }
+
+    /// Seek to a given time.
+    ///
+    /// If the stream has timestamps, this seeks to the last frame starting at or before `time`.
+    /// Otherwise, the framerate is used to compute the target frame.
+    fn seek_to_time(&mut self, time: f64) -> Result<()> {
+        if let Some(frame) = self.find_time(time)? {
+            return self.seek_frame(frame);
+        }
+
+        let framerate = self
+            .get_framerate()
+            .ok_or_else(|| anyhow!("Framerate is unknown"))?;
+        self.seek_frame((time.max(0.0) * framerate).round() as usize)
+    }
+
+    fn frame_count(&self) -> Option<usize> {
+        MvecFile::frame_count(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn test_vectors(n: usize) -> MotionVectors {
+        (0..n)
+            .map(|i| {
+                let i = i as f32;
+                (
+                    na::Point2::new(i * 0.1, 1.0 - i * 0.1),
+                    na::Vector2::new(i * 0.01, -i * 0.02),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn v2_roundtrip() -> Result<()> {
+        let header = MvecHeader::new(Some((1920, 1080)), Some(30.0));
+        let frames = [
+            (Some(0.0), FRAME_INTRA, MotionVectors::new()),
+            (Some(1.0 / 30.0), 0, test_vectors(5)),
+            (None, 0, MotionVectors::new()),
+        ];
+
+        let mut writer = MvecWriter::new(vec![], header)?;
+        for (timestamp, flags, vectors) in &frames {
+            writer.write_frame(*timestamp, *flags, vectors)?;
+        }
+        let data = writer.finish()?;
+
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+        assert!(!file.is_legacy());
+        assert_eq!(file.get_aspect(), Some((1920, 1080)));
+        assert_eq!(file.get_framerate(), Some(30.0));
+
+        for (timestamp, flags, vectors) in &frames {
+            let mut field = MotionVectors::new();
+            let frame = file.read_frame(&mut field)?;
+            assert_eq!(frame.timestamp, *timestamp);
+            assert_eq!(frame.flags, *flags);
+            assert_eq!(&field, vectors);
+        }
+
+        assert!(file
+            .process_frame(&mut MotionVectors::new(), None, None, 0)
+            .is_err());
+
+        // Intra frames must not be reported as having motion.
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+        assert!(!file.process_frame(&mut MotionVectors::new(), None, None, 0)?);
+        assert!(file.process_frame(&mut MotionVectors::new(), None, None, 0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn frame_info_roundtrip() -> Result<()> {
+        let infos = [
+            FrameInfo {
+                pts: Some(0.0),
+                picture_type: PictureType::I,
+                keyframe: true,
+                ..Default::default()
+            },
+            FrameInfo {
+                pts: Some(0.1),
+                picture_type: PictureType::P,
+                forward: true,
+                ..Default::default()
+            },
+            FrameInfo {
+                pts: Some(0.05),
+                picture_type: PictureType::B,
+                forward: true,
+                backward: true,
+                ..Default::default()
+            },
+            FrameInfo::default(),
+        ];
+
+        let mut writer = MvecWriter::new(vec![], MvecHeader::default())?;
+        for info in &infos {
+            let has_motion = info.picture_type != PictureType::I;
+            writer.write_frame(info.pts, frame_flags(info, has_motion), &test_vectors(2))?;
+        }
+        let data = writer.finish()?;
+
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+
+        for (i, info) in infos.iter().enumerate() {
+            let mut out = FrameInfo::default();
+            let has_motion =
+                file.process_frame(&mut MotionVectors::new(), None, Some(&mut out), 0)?;
+            assert_eq!(has_motion, info.picture_type != PictureType::I);
+            assert_eq!(
+                out,
+                FrameInfo {
+                    decode_index: i,
+                    ..*info
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_roundtrip() -> Result<()> {
+        let frames = [test_vectors(3), MotionVectors::new(), test_vectors(7)];
+
+        let mut writer = MvecWriter::legacy(vec![]);
+        for vectors in &frames {
+            writer.write_frame(Some(1.0), FRAME_INTRA, vectors)?;
+        }
+        let data = writer.finish()?;
+
+        let mut file = MvecFile::new(Cursor::new(&data))?;
+        assert!(file.is_legacy());
+        assert_eq!(file.get_aspect(), None);
+        assert_eq!(file.get_framerate(), None);
+
+        for vectors in &frames {
+            let mut field = MotionVectors::new();
+            let frame = file.read_frame(&mut field)?;
+            assert_eq!(frame.timestamp, None);
+            assert!(!frame.is_intra());
+            assert_eq!(&field, vectors);
+        }
+
+        assert!(file.read_frame(&mut MotionVectors::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeking() -> Result<()> {
+        let frames = (0..20)
+            .map(|i| {
+                test_vectors(i % 7)
+                    .entries()
+                    .map(|(p, m)| {
+                        // Keep values exactly representable in compressed form.
+                        let quantize = |v: f32| (v * 256.0).round() / 256.0;
+                        (p.map(quantize), m.map(quantize))
+                    })
+                    .collect::<MotionVectors>()
+            })
+            .collect::<Vec<_>>();
+
+        let compression = Some(MvecCompression {
+            motion_scale: 4,
+            keyframe_interval: 4,
+        });
+
+        for (legacy, index, compression) in [
+            (false, true, None),
+            (false, false, None),
+            (true, false, None),
+            (false, true, compression),
+            (false, false, compression),
+        ] {
+            let mut writer = if legacy {
+                MvecWriter::legacy(vec![])
+            } else {
+                let header = MvecHeader::new(Some((64, 64)), Some(10.0)).compression(compression);
+                MvecWriter::new(vec![], header)?.index(index)
+            };
+
+            for (i, vectors) in frames.iter().enumerate() {
+                writer.write_frame(Some(i as f64 / 10.0), 0, vectors)?;
+            }
+
+            let data = writer.finish()?;
+            let mut file = MvecFile::new(Cursor::new(&data))?;
+
+            assert!(file.can_seek());
+            assert_eq!(file.frame_count().is_some(), index);
+
+            for target in [5, 19, 0, 13, 14, 2, 3] {
+                file.seek_to_frame(target)?;
+                let mut field = MotionVectors::new();
+                file.process_frame(&mut field, None, None, 0)?;
+                assert_eq!(field, frames[target], "{legacy} {index} {compression:?}");
+            }
+
+            assert!(file.seek_to_frame(20).is_err());
+            assert_eq!(file.frame_count(), Some(20));
+
+            if !legacy {
+                assert_eq!(file.duration(), Some(2.0));
+                file.seek_to_time(0.75)?;
+                let mut field = MotionVectors::new();
+                let frame = file.read_frame(&mut field)?;
+                assert_eq!(frame.timestamp, Some(0.7));
+                assert_eq!(field, frames[7]);
+            }
+
+            file.rewind()?;
+            let mut field = MotionVectors::new();
+            file.process_frame(&mut field, None, None, 0)?;
+            assert_eq!(field, frames[0]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn vector_limit() {
+        let mut data = vec![];
+        MvecHeader::default().write(&mut data).unwrap();
+        FrameHeader {
+            count: MAX_VECTORS + 1,
+            ..Default::default()
+        }
+        .write(&mut data)
+        .unwrap();
+
+        let mut file = MvecFile::new(Cursor::new(&data)).unwrap();
+        assert!(file.read_frame(&mut MotionVectors::new()).is_err());
+    }
+}
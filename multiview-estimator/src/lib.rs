@@ -5,6 +5,7 @@
 //! invokations.
 
 use nalgebra as na;
+use ofps::frames::Frame;
 use ofps::prelude::v1::*;
 use opencv::calib3d::{find_essential_mat_matrix, recover_pose_estimated, LMEDS, RANSAC};
 use opencv::core::*;
@@ -66,15 +67,15 @@ impl Default for MultiviewEstimator {
 impl MultiviewEstimator {
     fn essential(
         &self,
-        motion: impl Iterator<Item = MotionEntry>,
-        camera: &StandardCamera,
+        motion: impl Iterator<Item = MotionEntry<f64>>,
+        camera: &dyn CameraModel,
     ) -> Result<(Mat, Mat, Mat, Mat, Mat)> {
         let mut p1 = vec![];
         let mut p2 = vec![];
 
         for (s, e) in motion.map(|(s, m)| (s, s + m)) {
-            p1.push(Point2f::new(s.x, s.y));
-            p2.push(Point2f::new(e.x, e.y));
+            p1.push(Point2d::new(s.x, s.y));
+            p2.push(Point2d::new(e.x, e.y));
         }
 
         let p1 = Mat::from_slice(&*p1)?;
@@ -82,7 +83,7 @@ impl MultiviewEstimator {
 
         let cam_matrix = camera.intrinsics();
 
-        let cam_matrix = cam_matrix.transpose();
+        let cam_matrix = cam_matrix.transpose().cast::<f64>();
 
         let cam_matrix = Mat::from_slice_2d(&[
             cam_matrix.column(0).as_slice(),
@@ -113,32 +114,45 @@ impl MultiviewEstimator {
 impl Estimator for MultiviewEstimator {
     fn estimate(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
-        _: Option<f32>,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
     ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
-        let (e, p1, p2, cam_matrix, mut inliers) =
-            self.essential(motion_vectors.iter().copied(), camera)?;
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        _: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+        let entries = motion_vectors
+            .entries()
+            .map(|(pos, motion)| (pos.cast(), motion.cast()));
+        let (e, p1, p2, cam_matrix, mut inliers) = self.essential(entries, camera)?;
 
         let mut r = Mat::default();
         let mut t = Mat::default();
 
         recover_pose_estimated(&e, &p1, &p2, &cam_matrix, &mut r, &mut t, &mut inliers)?;
 
-        let r = na::Matrix3::from_iterator(r.iter::<f64>()?.map(|(_, v)| v as _));
+        // OpenCV matrices are stored in row-major order.
+        let r = na::Matrix3::from_iterator(r.iter::<f64>()?.map(|(_, v)| v)).transpose();
 
-        // Swap Y and Z axis in the rotation matrix to be line-in-line with
-        // the rest of the codebase.
+        // OpenCV rotates points from the first camera to the second, while we need the rotation
+        // of the camera itself.
         let r = na::UnitQuaternion::from_matrix(&r).inverse();
-        let (x, z, y) = r.euler_angles();
-        let r = na::UnitQuaternion::from_euler_angles(x, y, z);
+        let r = Frame::OpenCv.convert_rotation(Frame::Body, r);
 
         // Check if rotation is over 90 degrees. Generally, this should not be possible, but is
         // caused by opencv acting weird.
         // TODO: look into the behavior.
-        let r = if r.angle() > std::f32::consts::FRAC_PI_2 {
+        let r = if r.angle() > std::f64::consts::FRAC_PI_2 {
             let (axis, angle) = r.axis_angle().unwrap();
-            let new_angle = (angle + std::f32::consts::PI) % (std::f32::consts::PI * 2.0);
+            let new_angle = (angle + std::f64::consts::PI) % (std::f64::consts::PI * 2.0);
             na::UnitQuaternion::from_axis_angle(&axis, new_angle)
         } else {
             r
@@ -176,11 +190,7 @@ mod tests {
     }
 
     fn calc_view(rot: na::UnitQuaternion<f32>, pos: na::Point3<f32>) -> na::Matrix4<f32> {
-        na::Matrix4::look_at_rh(
-            &pos,
-            &(pos + rot.transform_vector(&na::Vector3::new(0.0, 1.0, 0.0))),
-            &rot.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0)),
-        )
+        ofps::frames::view_matrix(pos, rot)
     }
 
     fn project_grid<'a>(
@@ -194,7 +204,7 @@ mod tests {
     fn calc_field(
         p1: impl IntoIterator<Item = na::Point2<f32>>,
         p2: impl IntoIterator<Item = na::Point2<f32>>,
-    ) -> Vec<MotionEntry> {
+    ) -> MotionVectors {
         p1.into_iter()
             .zip(p2.into_iter())
             .filter(|(p1, p2)| p1.coords.magnitude() <= 0.71 && p2.coords.magnitude() <= 0.71)
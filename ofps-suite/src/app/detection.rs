@@ -113,6 +113,7 @@ impl MotionDetectionState {
             .process_frame(
                 &mut out.motion_vectors,
                 Some((&mut out.frame, &mut out.frame_height)),
+                None,
                 0,
             )
             .is_ok()
@@ -180,7 +181,7 @@ struct MotionDetectionSettings {
 
 #[derive(Default)]
 struct MotionDetectionOutput {
-    motion_vectors: Vec<MotionEntry>,
+    motion_vectors: MotionVectors,
     frame: Vec<RGBA>,
     frame_height: usize,
     frames: usize,
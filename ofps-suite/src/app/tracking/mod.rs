@@ -9,13 +9,16 @@ use super::widgets::{
     CreatePluginUi, FileLoader, FilePicker,
 };
 use super::{OfpsAppContext, OfpsCtxApp};
+use almeida_estimator::FovCalibrator;
 use egui::*;
 use nalgebra as na;
+use ofps::frames::{self, Frame};
 use ofps::prelude::v1::*;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, TryRecvError},
     Arc, Mutex,
 };
 use widgets::plot::{Line, LinkedAxisGroup, Plot, Value, Values};
@@ -103,6 +106,8 @@ pub struct MotionTrackingConfig {
     camera_aspect: f32,
     camera_fov_y: f32,
     #[serde(default)]
+    camera_equirectangular: bool,
+    #[serde(default)]
     realtime_processing: bool,
     #[serde(default)]
     decoder_properties: BTreeMap<String, Property>,
@@ -131,19 +136,20 @@ impl GroundTruth {
     }
 
     fn rot(&self) -> na::UnitQuaternion<f32> {
-        na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+        let rot = na::UnitQuaternion::from_quaternion(na::Quaternion::new(
             self.rot_w, self.rot_i, self.rot_j, self.rot_k,
-        ))
+        ));
+        Frame::Blender.convert_rotation(Frame::Body, rot)
     }
 
     fn deltas<'a>(
         truth: &'a [Self],
-        poses: &'a [(na::Point3<f32>, na::UnitQuaternion<f32>)],
+        poses: &'a [(na::Point3<f64>, na::UnitQuaternion<f64>)],
     ) -> impl Iterator<Item = (usize, na::UnitQuaternion<f32>)> + 'a {
         truth
             .iter()
             .filter_map(move |t| poses.get(t.frame - 1).zip(Some(t)))
-            .map(|((_, rot), truth)| (truth.frame - 1, truth.rot().rotation_to(rot)))
+            .map(|((_, rot), truth)| (truth.frame - 1, truth.rot().rotation_to(&rot.cast())))
     }
 
     fn gen_stats<'a>(
@@ -154,11 +160,11 @@ impl GroundTruth {
             .zip(Self::deltas(truth, &state.poses))
             .map(move |(stats, (frame, rot))| {
                 assert_eq!(stats.frame, frame);
-                let total_rot = state.poses[frame].1;
-                let (p, r, y) = total_rot.euler_angles();
+                let total_rot = state.poses[frame].1.cast();
+                let (r, p, y) = frames::roll_pitch_yaw(&total_rot);
 
                 let delta = rot.angle();
-                let (delta_p, delta_r, delta_y) = rot.euler_angles();
+                let (delta_r, delta_p, delta_y) = frames::roll_pitch_yaw(&rot);
 
                 (
                     stats,
@@ -189,9 +195,8 @@ impl GroundTruth {
                 let q1 = prev_truth.rot();
                 let q2 = truth.rot();
                 let q = q1.rotation_to(&q2);
-                // Swap roll and pitch since data is coming from blender
-                let (p, r, y) = rot.euler_angles();
-                let (pt, rt, yt) = q.euler_angles();
+                let (r, p, y) = frames::roll_pitch_yaw(rot);
+                let (rt, pt, yt) = frames::roll_pitch_yaw(&q);
                 let [error_r, error_p, error_y] =
                     [r - rt, p - pt, y - yt].map(|v| v.abs() % std::f32::consts::PI);
 
@@ -228,6 +233,7 @@ pub struct MotionTrackingApp {
     ground_truth_link_axis: LinkedAxisGroup,
     draw_ground_truth: DrawGroundTruth,
     draw_perf_stats: DrawPerfStats,
+    fov_calibration: Option<Receiver<Result<f32>>>,
 }
 
 impl Default for MotionTrackingApp {
@@ -244,6 +250,7 @@ impl Default for MotionTrackingApp {
             ground_truth_link_axis: LinkedAxisGroup::x(),
             draw_ground_truth: Default::default(),
             draw_perf_stats: Default::default(),
+            fov_calibration: None,
         }
     }
 }
@@ -297,7 +304,7 @@ impl MotionTrackingApp {
         }
 
         let (offset_factor, x_scale) = {
-            let intrinsics = self.app_settings.camera.intrinsics();
+            let intrinsics = self.app_settings.camera_model().intrinsics();
             (intrinsics[(1, 1)], intrinsics[(1, 1)] / intrinsics[(0, 0)])
         };
 
@@ -352,6 +359,7 @@ impl MotionTrackingApp {
             estimators,
             camera_aspect,
             camera_fov_y,
+            camera_equirectangular,
             realtime_processing,
             decoder_properties,
         }: MotionTrackingConfig,
@@ -367,6 +375,7 @@ impl MotionTrackingApp {
         self.create_decoder_state.config = decoder.0;
 
         self.app_settings.camera = StandardCamera::new(camera_aspect, camera_fov_y);
+        self.app_settings.equirectangular = camera_equirectangular;
         self.app_settings.settings.clear();
         self.app_settings.realtime_processing = realtime_processing;
         self.app_settings.decoder_properties = decoder_properties;
@@ -450,6 +459,7 @@ impl MotionTrackingApp {
                 .collect(),
             camera_aspect,
             camera_fov_y,
+            camera_equirectangular: self.app_settings.equirectangular,
             realtime_processing: self.app_settings.realtime_processing,
             decoder_properties: self.app_settings.decoder_properties.clone(),
         }
@@ -565,13 +575,69 @@ impl OfpsCtxApp for MotionTrackingApp {
                     Grid::new("camera_settings".to_string()).show(ui, |ui| {
                         let (_, mut fov_y) = self.app_settings.camera.fov();
                         let mut aspect = self.app_settings.camera.aspect_ratio();
+                        let equirectangular = self.app_settings.equirectangular;
+
+                        ui.label("Projection");
+                        ui.checkbox(&mut self.app_settings.equirectangular, "Equirectangular")
+                            .on_hover_text("360° footage");
+                        ui.end_row();
 
                         ui.label("Aspect ratio");
                         ui.add(Slider::new(&mut aspect, 0.01..=5.0));
                         ui.end_row();
 
                         ui.label("Vertical FOV");
-                        ui.add(Slider::new(&mut fov_y, 0.01..=179.0));
+                        ui.add_enabled(!equirectangular, Slider::new(&mut fov_y, 0.01..=179.0));
+                        ui.end_row();
+
+                        // Pick up the result of a calibration running in the background.
+                        if let Some(rx) = &self.fov_calibration {
+                            match rx.try_recv() {
+                                Ok(Ok(calibrated)) => {
+                                    fov_y = calibrated;
+                                    self.fov_calibration = None;
+                                }
+                                Ok(Err(e)) => {
+                                    log::error!("Unable to calibrate FOV: {e}");
+                                    self.fov_calibration = None;
+                                }
+                                Err(TryRecvError::Empty) => ui.ctx().request_repaint(),
+                                Err(TryRecvError::Disconnected) => self.fov_calibration = None,
+                            }
+                        }
+
+                        let calibrating = self.fov_calibration.is_some();
+
+                        if ui
+                            .add_enabled(
+                                self.app_state.is_some() && !calibrating && !equirectangular,
+                                Button::new(if calibrating {
+                                    "Calibrating..."
+                                } else {
+                                    "Auto FOV"
+                                }),
+                            )
+                            .on_hover_text("Estimate FOV from recent frames of a rotating camera")
+                            .clicked()
+                        {
+                            let recent_motion = self
+                                .app_state
+                                .as_ref()
+                                .and_then(|s| s.worker.read().ok())
+                                .map(|o| o.recent_motion.clone())
+                                .unwrap_or_default();
+
+                            let (tx, rx) = mpsc::channel();
+
+                            std::thread::spawn(move || {
+                                let calibration = FovCalibrator::new(aspect)
+                                    .max_samples(128)
+                                    .calibrate(recent_motion.iter().map(Arc::as_ref));
+                                let _ = tx.send(calibration.map(|c| c.fov_y));
+                            });
+
+                            self.fov_calibration = Some(rx);
+                        }
                         ui.end_row();
 
                         self.app_settings.camera = StandardCamera::new(aspect, fov_y);
@@ -866,8 +932,7 @@ impl OfpsCtxApp for MotionTrackingApp {
 
                                 for truth in ground_truth {
                                     let r = truth.rot();
-                                    // Again, swap pitch with roll
-                                    let (p, r, y) = r.euler_angles();
+                                    let (r, p, y) = frames::roll_pitch_yaw(&r);
 
                                     for (i, v) in [r, p, y].iter().enumerate() {
                                         gt[i].push(Value::new(
@@ -895,8 +960,7 @@ impl OfpsCtxApp for MotionTrackingApp {
                                         let mut pred = [vec![], vec![], vec![]];
 
                                         for (frame, (_, rot)) in est.poses.iter().enumerate() {
-                                            // Same swap here
-                                            let (p, r, y) = rot.euler_angles();
+                                            let (r, p, y) = frames::roll_pitch_yaw(&rot.cast());
                                             for (i, v) in [r, p, y].iter().enumerate() {
                                                 pred[i]
                                                     .push(Value::new(frame as f32, v.to_degrees()));
@@ -1004,7 +1068,8 @@ impl OfpsCtxApp for MotionTrackingApp {
                                             GroundTruth::deltas(ground_truth, &est.poses)
                                         {
                                             let delta = rot.angle();
-                                            let (delta_p, delta_r, delta_y) = rot.euler_angles();
+                                            let (delta_r, delta_p, delta_y) =
+                                                frames::roll_pitch_yaw(&rot);
 
                                             for (i, dt) in [delta, delta_r, delta_p, delta_y]
                                                 .iter()
@@ -8,7 +8,7 @@ use ofps::prelude::v1::*;
 use once_cell::sync::OnceCell;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     mpsc::{self, Receiver, Sender, SyncSender},
@@ -18,6 +18,9 @@ use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, Instant};
 use wimrend::material::Material;
 
+/// Number of most recent frames of motion kept for camera calibration.
+const CALIBRATION_FRAMES: usize = 30;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EstimatorSettings {
     pub scale_factor: f32,
@@ -50,7 +53,7 @@ pub enum FrameState {
 
 #[derive(Default, Clone)]
 pub struct EstimatorState {
-    pub poses: Vec<(na::Point3<f32>, na::UnitQuaternion<f32>)>,
+    pub poses: Vec<(na::Point3<f64>, na::UnitQuaternion<f64>)>,
     pub transforms: Vec<(na::Vector3<f32>, na::UnitQuaternion<f32>)>,
     pub times: Vec<Duration>,
     pub layered_frames: Vec<(usize, Arc<Mutex<FrameState>>)>,
@@ -61,9 +64,9 @@ pub struct EstimatorState {
 impl EstimatorState {
     fn apply_pose(
         &self,
-        tr: na::Vector3<f32>,
-        rot: na::UnitQuaternion<f32>,
-    ) -> (na::Point3<f32>, na::UnitQuaternion<f32>) {
+        tr: na::Vector3<f64>,
+        rot: na::UnitQuaternion<f64>,
+    ) -> (na::Point3<f64>, na::UnitQuaternion<f64>) {
         let (pos, old_rot) = self.poses.last().copied().unwrap_or_default();
         (pos + old_rot * tr, rot * old_rot)
     }
@@ -74,17 +77,17 @@ impl EstimatorState {
 
     fn push_pose(
         &mut self,
-        pos: na::Point3<f32>,
-        rot: na::UnitQuaternion<f32>,
-        tr: na::Vector3<f32>,
-        frot: na::UnitQuaternion<f32>,
+        pos: na::Point3<f64>,
+        rot: na::UnitQuaternion<f64>,
+        tr: na::Vector3<f64>,
+        frot: na::UnitQuaternion<f64>,
         frame: Option<Arc<Mutex<FrameState>>>,
         time: Duration,
     ) {
         let idx = self.poses.len();
 
         self.poses.push((pos, rot));
-        self.transforms.push((tr, frot));
+        self.transforms.push((tr.cast(), frot.cast()));
         self.times.push(time);
 
         if let Some(mat) = frame {
@@ -104,7 +107,7 @@ impl EstimatorState {
         self.layered_frames
             .iter()
             .cloned()
-            .map(move |(i, mat)| (self.poses[i].0, self.poses[i].1, mat))
+            .map(move |(i, mat)| (self.poses[i].0.cast(), self.poses[i].1.cast(), mat))
     }
 
     fn remove_least_significant_frame(&mut self) {
@@ -132,7 +135,7 @@ impl EstimatorState {
                         }
                     });
 
-                    let dist = dists.into_iter().take(5).sum::<f32>();
+                    let dist = dists.into_iter().take(5).sum::<f64>();
 
                     if let Some((frame, cur_dist)) = candidate {
                         if dist >= cur_dist {
@@ -168,8 +171,8 @@ fn decoder_job(
     sender: SyncSender<DecoderResult>,
     settings: Arc<Mutex<DecoderSettings>>,
 ) {
-    let mut motion_vectors = vec![];
-    let mut motion_vectors_2 = vec![];
+    let mut motion_vectors = MotionVectors::new();
+    let mut motion_vectors_2 = MotionVectors::new();
     let mut frame = vec![];
     let mut frame_2 = vec![];
     let mut frame_height = 0;
@@ -203,6 +206,7 @@ fn decoder_job(
         let frame = match decoder.process_frame(
             &mut motion_vectors_2,
             Some((&mut frame_2, &mut frame_height_2)),
+            None,
             0,
         ) {
             Ok(s) => {
@@ -275,6 +279,7 @@ pub struct TrackingState {
     estimator_states: Vec<Option<EstimatorState>>,
     frames_to_load: Sender<Arc<Mutex<FrameState>>>,
     frames: usize,
+    recent_motion: VecDeque<Arc<MotionVectors>>,
 }
 
 /// UI side of the worker.
@@ -291,6 +296,7 @@ impl TrackingState {
             estimator_states: vec![],
             frames_to_load,
             frames: 0,
+            recent_motion: Default::default(),
         }
     }
 
@@ -340,8 +346,15 @@ impl TrackingState {
         self.decoder_times.push(time);
         self.frames += 1;
 
+        // Frames are shared with the output, thus keeping them around does not copy any vectors.
+        let motion_vectors = Arc::new(motion_vectors);
+        if self.recent_motion.len() >= CALIBRATION_FRAMES {
+            self.recent_motion.pop_front();
+        }
+        self.recent_motion.push_back(motion_vectors.clone());
+
         let mut mat = OnceCell::new();
-        let camera = &settings.camera;
+        let camera = settings.camera_model();
 
         // Go through each estimator and execute it.
         self.estimator_states
@@ -358,7 +371,7 @@ impl TrackingState {
                     estimator_state.properties = Some(props);
 
                     let timer = Instant::now();
-                    if let Ok((frot, tr)) = estimator.estimate(&motion_vectors, camera, None) {
+                    if let Ok((frot, tr)) = estimator.estimate_f64(&motion_vectors, camera, None) {
                         if estimator_state.clear_count != est_settings.clear_count {
                             estimator_state.layered_frames.clear();
                             estimator_state.clear_count = est_settings.clear_count;
@@ -407,6 +420,7 @@ impl TrackingState {
 
         out.estimators = self.estimator_states.clone();
         out.decoder_times = self.decoder_times.clone();
+        out.recent_motion = self.recent_motion.clone();
 
         true
     }
@@ -422,6 +436,7 @@ pub struct TrackingOutput {
     pub estimators: Vec<Option<EstimatorState>>,
     pub decoder_times: Vec<Duration>,
     pub decoder_properties: Option<BTreeMap<String, Property>>,
+    pub recent_motion: VecDeque<Arc<MotionVectors>>,
 }
 
 pub type EstSettingsTuple = (
@@ -434,6 +449,7 @@ pub type EstSettingsTuple = (
 pub struct TrackingSettings {
     pub settings: Vec<EstSettingsTuple>,
     pub camera: StandardCamera,
+    pub equirectangular: bool,
     pub realtime_processing: bool,
     pub decoder_properties: BTreeMap<String, Property>,
 }
@@ -443,8 +459,23 @@ impl Default for TrackingSettings {
         Self {
             settings: vec![],
             camera: StandardCamera::new(16.0 / 9.0, 39.6 * 9.0 / 16.0),
+            equirectangular: false,
             realtime_processing: false,
             decoder_properties: Default::default(),
         }
     }
 }
+
+impl TrackingSettings {
+    /// Get the camera the estimators should use.
+    ///
+    /// Equirectangular footage covers all directions, thus the standard camera's parameters do
+    /// not apply to it.
+    pub fn camera_model(&self) -> &dyn CameraModel {
+        if self.equirectangular {
+            &EquirectangularCamera
+        } else {
+            &self.camera
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Benchmarks of empty cell interpolation on sparse macroblock fields.
+//!
+//! The previous, priority queue based implementation is kept here for comparison.
+
+use criterion::*;
+use nalgebra::*;
+use ofps::prelude::v1::*;
+use std::collections::BTreeSet;
+
+/// Macroblock motion vectors of a 1080p frame.
+fn macroblock_vectors() -> MotionVectors {
+    let (bw, bh) = (1920 / 16, 1080 / 16);
+
+    (0..bh)
+        .flat_map(|y| (0..bw).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let pos = Point2::new((x as f32 + 0.5) / bw as f32, (y as f32 + 0.5) / bh as f32);
+            let motion = (pos - Point2::new(0.5, 0.5)) * 0.01;
+            (pos, motion)
+        })
+        .collect()
+}
+
+/// Previous implementation of `MotionFieldDensifier::interpolate_empty_cells`.
+///
+/// Operates on motion sums and counts of each cell, which are stored the same way as in the
+/// densifier.
+fn legacy_interpolate(vf: &mut [Vector2<f32>], counts: &mut [f32], width: usize) {
+    #[derive(PartialOrd, PartialEq, Ord, Clone, Copy, Eq, Debug, Default)]
+    struct InterpCell {
+        neighbors: isize,
+        idx: usize,
+    }
+
+    impl std::borrow::Borrow<usize> for InterpCell {
+        fn borrow(&self) -> &usize {
+            &self.idx
+        }
+    }
+
+    let height = counts.len() / width;
+
+    let neighbors = [(-1, 0), (0, -1), (-1, -1), (1, 0), (0, 1), (1, 1)];
+
+    let calc_counts = |counts: &[f32], i| {
+        let mut cnt = 0;
+
+        let (x, y) = (i % width, i / width);
+
+        for (ox, oy) in neighbors {
+            let (x, y) = (x as isize + ox, y as isize + oy);
+            if x >= 0
+                && x < width as isize
+                && y >= 0
+                && y < height as isize
+                && counts[x as usize + y as usize * width] > 0.1
+            {
+                cnt += 1;
+            }
+        }
+
+        cnt
+    };
+
+    let mut queue = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c < 0.5)
+        .map(|(i, _)| InterpCell {
+            idx: i,
+            neighbors: -calc_counts(counts, i),
+        })
+        .collect::<BTreeSet<_>>();
+
+    if queue.len() == counts.len() {
+        return;
+    }
+
+    while let Some(cell) = queue.take(&queue.iter().copied().next().unwrap_or_default()) {
+        let i = cell.idx;
+
+        let (x, y) = (i % width, i / width);
+
+        let mut added = false;
+
+        for (ox, oy) in neighbors {
+            let (x, y) = (x as isize + ox, y as isize + oy);
+            if x >= 0 && x < width as isize && y >= 0 && y < height as isize {
+                let idx = x as usize + y as usize * width;
+                let cnt = counts[idx];
+                if cnt > 0.1 {
+                    let scale = 1.0 - ((ox * ox + oy * oy) as f32).sqrt() * 0.5;
+                    let inv_cnt = 1.0 / cnt;
+                    counts[i] += scale;
+                    vf[i] += scale * scale * inv_cnt * vf[idx];
+                    added = true;
+                }
+            }
+        }
+
+        if !added {
+            queue.insert(cell);
+        } else {
+            for (ox, oy) in neighbors {
+                let (x, y) = (x as isize + ox, y as isize + oy);
+                if x >= 0 && x < width as isize && y >= 0 && y < height as isize {
+                    let idx = x as usize + y as usize * width;
+                    let cnt = -calc_counts(counts, idx) + 1;
+                    if let Some(mut neighbor) = queue.take(&InterpCell {
+                        idx,
+                        neighbors: cnt,
+                    }) {
+                        neighbor.neighbors -= 1;
+                        queue.insert(neighbor);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn interpolate(c: &mut Criterion) {
+    let vectors = macroblock_vectors();
+
+    let mut group = c.benchmark_group("interpolate_empty_cells");
+    group.sample_size(10);
+
+    for (width, height) in [(240, 135), (480, 270), (960, 540)] {
+        let mut densifier = MotionFieldDensifier::new(width, height);
+        densifier.add_vectors(&vectors);
+
+        group.bench_with_input(
+            BenchmarkId::new("propagate", format!("{width}x{height}")),
+            &densifier,
+            |b, densifier| {
+                b.iter_batched(
+                    || densifier.clone(),
+                    |mut densifier| {
+                        densifier.interpolate_empty_cells().unwrap();
+                        densifier
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        let mut vf = vec![Vector2::zeros(); width * height];
+        let mut counts = vec![0.0; width * height];
+        for (pos, motion) in vectors.entries() {
+            let x = (pos.x * width as f32) as usize;
+            let y = (pos.y * height as f32) as usize;
+            vf[x + y * width] += motion;
+            counts[x + y * width] += 1.0;
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("legacy", format!("{width}x{height}")),
+            &(vf, counts),
+            |b, input| {
+                b.iter_batched(
+                    || input.clone(),
+                    |(mut vf, mut counts)| {
+                        legacy_interpolate(&mut vf, &mut counts, width);
+                        (vf, counts)
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, interpolate);
+criterion_main!(benches);
@@ -0,0 +1,448 @@
+//! # Camera calibration files
+//!
+//! Calibration is loaded from files written by OpenCV's `FileStorage`, in either YAML or JSON
+//! form. The following top-level entries are read:
+//!
+//! | Entry                     | Alternative names         | Description                        |
+//! |---------------------------|---------------------------|------------------------------------|
+//! | `camera_matrix`           | `cameraMatrix`, `K`       | 3x3 intrinsic matrix, in pixels    |
+//! | `distortion_coefficients` | `dist_coeffs`, `D`        | distortion coefficients (optional) |
+//! | `distortion_model`        |                           | distortion model (optional)        |
+//! | `image_width`             | `width`                   | width of calibrated images         |
+//! | `image_height`            | `height`                  | height of calibrated images        |
+//!
+//! Matrices may be stored either as `opencv-matrix` nodes with `rows`, `cols` and `data` fields,
+//! or as plain sequences in row major order.
+//!
+//! Distortion coefficients are in OpenCV order - `k1, k2, p1, p2, k3` for the Brown-Conrady
+//! model, and `k1, k2, k3, k4` when `distortion_model` is `fisheye` or `equidistant`. Missing
+//! trailing coefficients are treated as zero, while higher order models are not supported.
+
+use super::*;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Names of the intrinsic matrix entry.
+pub const CAMERA_MATRIX_KEYS: &[&str] = &["camera_matrix", "cameraMatrix", "K"];
+
+/// Names of the distortion coefficients entry.
+pub const DISTORTION_KEYS: &[&str] = &["distortion_coefficients", "dist_coeffs", "D"];
+
+/// Names of the image width entry.
+pub const WIDTH_KEYS: &[&str] = &["image_width", "width"];
+
+/// Names of the image height entry.
+pub const HEIGHT_KEYS: &[&str] = &["image_height", "height"];
+
+/// Parsed `FileStorage` node.
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Scalar(String),
+    Seq(Vec<f64>),
+    Map(HashMap<String, Node>),
+}
+
+impl Node {
+    /// Convert a JSON value to a node.
+    fn from_json(value: serde_json::Value) -> Result<Self> {
+        use serde_json::Value;
+
+        match value {
+            Value::Number(n) => Ok(Self::Scalar(n.to_string())),
+            Value::String(s) => Ok(Self::Scalar(s)),
+            Value::Array(values) => values
+                .into_iter()
+                .map(|v| {
+                    v.as_f64()
+                        .ok_or_else(|| anyhow!("non-numeric sequence value {v}"))
+                })
+                .collect::<Result<_>>()
+                .map(Self::Seq),
+            Value::Object(map) => map
+                .into_iter()
+                .map(|(k, v)| Ok((k, Self::from_json(v)?)))
+                .collect::<Result<_>>()
+                .map(Self::Map),
+            v => Err(anyhow!("unsupported value {v}")),
+        }
+    }
+
+    /// Parse a flow sequence, such as `[ 1., 2., 3. ]`.
+    fn parse_seq(seq: &str) -> Result<Self> {
+        seq.trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| anyhow!("non-numeric sequence value {v}"))
+            })
+            .collect::<Result<_>>()
+            .map(Self::Seq)
+    }
+
+    /// Parse a scalar, stripping any quotes.
+    fn parse_scalar(scalar: &str) -> Self {
+        Self::Scalar(scalar.trim().trim_matches(|c| c == '"' || c == '\'').into())
+    }
+
+    /// Get the numeric contents of a matrix node.
+    fn values(&self) -> Result<Vec<f64>> {
+        match self {
+            Self::Seq(values) => Ok(values.clone()),
+            Self::Map(map) => {
+                let values = map
+                    .get("data")
+                    .ok_or_else(|| anyhow!("matrix has no data"))?
+                    .values()?;
+
+                if let (Some(rows), Some(cols)) = (map.get("rows"), map.get("cols")) {
+                    let size = rows.as_usize()? * cols.as_usize()?;
+                    if size != values.len() {
+                        return Err(anyhow!(
+                            "matrix has {} values, expected {size}",
+                            values.len()
+                        ));
+                    }
+                }
+
+                Ok(values)
+            }
+            Self::Scalar(s) => Err(anyhow!("expected a matrix, got {s}")),
+        }
+    }
+
+    /// Get the node as an unsigned integer.
+    fn as_usize(&self) -> Result<usize> {
+        match self {
+            Self::Scalar(s) => s
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.fract() == 0.0 && *v >= 0.0)
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow!("expected an integer, got {s}")),
+            _ => Err(anyhow!("expected an integer")),
+        }
+    }
+}
+
+/// Parse the subset of YAML written by `FileStorage`.
+///
+/// Top-level entries may be scalars, flow sequences, or mappings of those, such as
+/// `opencv-matrix` nodes.
+fn parse_yaml(input: &str) -> Result<HashMap<String, Node>> {
+    // Indentation, key and value of each entry. Entries without a value start a mapping.
+    let mut lines = vec![];
+    // Sequence spanning multiple lines - its indentation, key and text so far.
+    let mut pending: Option<(usize, String, String)> = None;
+
+    for line in input.lines() {
+        if let Some((indent, key, mut seq)) = pending.take() {
+            seq.push(' ');
+            seq.push_str(line);
+            if line.contains(']') {
+                lines.push((indent, key, Some(Node::parse_seq(&seq)?)));
+            } else {
+                pending = Some((indent, key, seq));
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+
+        if trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with('%')
+            || trimmed.starts_with("---")
+            || trimmed == "..."
+        {
+            continue;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+
+        let (key, value) = trimmed
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid line {trimmed}"))?;
+
+        let key = key
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'')
+            .to_string();
+        let value = value.trim();
+
+        // Strip type tags, such as `!!opencv-matrix`.
+        let value = match value.strip_prefix("!!") {
+            Some(tagged) => tagged.split_once(' ').map(|(_, v)| v.trim()).unwrap_or(""),
+            None => value,
+        };
+
+        if value.is_empty() {
+            lines.push((indent, key, None));
+        } else if !value.starts_with('[') {
+            lines.push((indent, key, Some(Node::parse_scalar(value))));
+        } else if value.contains(']') {
+            lines.push((indent, key, Some(Node::parse_seq(value)?)));
+        } else {
+            pending = Some((indent, key, value.to_string()));
+        }
+    }
+
+    if pending.is_some() {
+        return Err(anyhow!("unterminated sequence"));
+    }
+
+    let mut entries = HashMap::new();
+    // Top-level mapping currently being filled.
+    let mut map: Option<(String, HashMap<String, Node>)> = None;
+
+    for (indent, key, node) in lines {
+        match (node, &mut map) {
+            (Some(node), Some((_, map))) if indent > 0 => {
+                map.insert(key, node);
+            }
+            (node, _) => {
+                if let Some((key, map)) = map.take() {
+                    entries.insert(key, Node::Map(map));
+                }
+
+                match node {
+                    Some(node) => {
+                        entries.insert(key, node);
+                    }
+                    None if indent == 0 => map = Some((key, HashMap::new())),
+                    None => return Err(anyhow!("nested mapping {key} is not supported")),
+                }
+            }
+        }
+    }
+
+    if let Some((key, map)) = map {
+        entries.insert(key, Node::Map(map));
+    }
+
+    Ok(entries)
+}
+
+/// Build distortion model from OpenCV coefficients.
+fn distortion_from_coefficients(coeffs: &[f64], fisheye: bool) -> Result<Distortion> {
+    let count = if fisheye { 4 } else { 5 };
+
+    if coeffs[count.min(coeffs.len())..].iter().any(|&c| c != 0.0) {
+        return Err(anyhow!(
+            "{} distortion coefficients are not supported",
+            coeffs.len()
+        ));
+    }
+
+    let c = |i: usize| coeffs.get(i).copied().unwrap_or_default() as f32;
+
+    let distortion = if fisheye {
+        Distortion::Fisheye {
+            k1: c(0),
+            k2: c(1),
+            k3: c(2),
+            k4: c(3),
+        }
+    } else {
+        Distortion::BrownConrady {
+            k1: c(0),
+            k2: c(1),
+            p1: c(2),
+            p2: c(3),
+            k3: c(4),
+        }
+    };
+
+    Ok(if distortion.is_none() {
+        Distortion::None
+    } else {
+        distortion
+    })
+}
+
+/// Build a camera from parsed calibration entries.
+fn camera_from_entries(entries: &HashMap<String, Node>) -> Result<CalibratedCamera> {
+    let find = |keys: &[&str]| keys.iter().find_map(|k| entries.get(*k));
+
+    let k = find(CAMERA_MATRIX_KEYS)
+        .ok_or_else(|| anyhow!("calibration has no camera matrix"))?
+        .values()?;
+
+    if k.len() != 9 {
+        return Err(anyhow!("camera matrix has {} values, expected 9", k.len()));
+    }
+
+    let k = na::Matrix3::from_row_slice(&k).map(|v| v as f32);
+
+    let width = find(WIDTH_KEYS)
+        .ok_or_else(|| anyhow!("calibration has no image width"))?
+        .as_usize()?;
+    let height = find(HEIGHT_KEYS)
+        .ok_or_else(|| anyhow!("calibration has no image height"))?
+        .as_usize()?;
+
+    if width == 0 || height == 0 {
+        return Err(anyhow!("invalid image dimensions {width}x{height}"));
+    }
+
+    let fisheye = matches!(
+        entries.get("distortion_model"),
+        Some(Node::Scalar(m)) if m == "fisheye" || m == "equidistant"
+    );
+
+    let distortion = match find(DISTORTION_KEYS) {
+        Some(node) => distortion_from_coefficients(&node.values()?, fisheye)?,
+        None => Distortion::None,
+    };
+
+    Ok(CalibratedCamera::from_pixels(k, (width, height)).distortion(distortion))
+}
+
+impl CalibratedCamera {
+    /// Load camera calibration from an OpenCV YAML file.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the file.
+    pub fn from_opencv_yaml(mut input: impl Read) -> Result<Self> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+        camera_from_entries(&parse_yaml(&text)?)
+    }
+
+    /// Load camera calibration from an OpenCV JSON file.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the file.
+    pub fn from_opencv_json(input: impl Read) -> Result<Self> {
+        match Node::from_json(serde_json::from_reader(input)?)? {
+            Node::Map(entries) => camera_from_entries(&entries),
+            _ => Err(anyhow!("calibration must be a JSON object")),
+        }
+    }
+
+    /// Load camera calibration from a file.
+    ///
+    /// The format is picked by the extension - `.json` files are parsed as JSON, while all
+    /// others as YAML.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path to the calibration file.
+    pub fn load_calibration(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+
+        if path.extension().is_some_and(|e| e == "json") {
+            Self::from_opencv_json(file)
+        } else {
+            Self::from_opencv_yaml(file)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"%YAML:1.0
+---
+calibration_time: "Fri 16 Oct 2026 10:00:00"
+image_width: 640
+image_height: 480
+camera_matrix: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 5.0000000000000000e+02, 0., 3.2000000000000000e+02, 0.,
+       4.0000000000000000e+02, 2.0000000000000000e+02, 0., 0., 1. ]
+distortion_coefficients: !!opencv-matrix
+   rows: 1
+   cols: 5
+   dt: d
+   data: [ -0.25, 0.1, 0.001, -0.002, 0. ]
+avg_reprojection_error: 0.2
+"#;
+
+    const JSON: &str = r#"{
+    "image_width": 640,
+    "image_height": 480,
+    "distortion_model": "fisheye",
+    "camera_matrix": {
+        "type_id": "opencv-matrix",
+        "rows": 3,
+        "cols": 3,
+        "dt": "d",
+        "data": [ 500.0, 0.0, 320.0, 0.0, 400.0, 200.0, 0.0, 0.0, 1.0 ]
+    },
+    "D": [ 0.05, -0.01, 0.0, 0.0 ]
+}"#;
+
+    fn expected_intrinsics() -> na::Matrix3<f32> {
+        na::matrix![
+            500.0 / 640.0, 0.0, 320.5 / 640.0;
+            0.0, 400.0 / 480.0, 200.5 / 480.0;
+            0.0, 0.0, 1.0
+        ]
+    }
+
+    #[test]
+    fn load_yaml() -> Result<()> {
+        let camera = CalibratedCamera::from_opencv_yaml(YAML.as_bytes())?;
+
+        assert!((camera.intrinsics() - expected_intrinsics()).norm() < 1e-6);
+        assert_eq!(
+            camera.get_distortion(),
+            Distortion::BrownConrady {
+                k1: -0.25,
+                k2: 0.1,
+                k3: 0.0,
+                p1: 0.001,
+                p2: -0.002,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_json() -> Result<()> {
+        let camera = CalibratedCamera::from_opencv_json(JSON.as_bytes())?;
+
+        assert!((camera.intrinsics() - expected_intrinsics()).norm() < 1e-6);
+        assert_eq!(
+            camera.get_distortion(),
+            Distortion::Fisheye {
+                k1: 0.05,
+                k2: -0.01,
+                k3: 0.0,
+                k4: 0.0,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_invalid() {
+        // Missing image dimensions.
+        let yaml = YAML.replace("image_width: 640\n", "");
+        assert!(CalibratedCamera::from_opencv_yaml(yaml.as_bytes()).is_err());
+
+        // Matrix size mismatch.
+        let json = JSON.replace("\"rows\": 3", "\"rows\": 2");
+        assert!(CalibratedCamera::from_opencv_json(json.as_bytes()).is_err());
+
+        // Unsupported rational model.
+        let yaml = YAML.replace("0.001, -0.002, 0. ]", "0.001, -0.002, 0., 0.5, 0., 0. ]");
+        assert!(CalibratedCamera::from_opencv_yaml(yaml.as_bytes()).is_err());
+
+        assert!(CalibratedCamera::from_opencv_json(&b"[1, 2]"[..]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use super::*;
+use std::f32::consts::{PI, TAU};
+
+/// Spherical camera with equirectangular projection
+///
+/// This camera sees in all directions, as is the case with 360° footage. Horizontal screen
+/// coordinate maps linearly to longitude, and vertical one to latitude, with the centre of the
+/// screen looking towards `-z`. The screen wraps around horizontally.
+///
+/// There is no pinhole equivalent of this projection, thus [`intrinsics`](Self::intrinsics)
+/// only approximate it near the centre of the screen. Estimators relying on pinhole geometry,
+/// such as the ones based on homography, are not suitable for this camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EquirectangularCamera;
+
+impl EquirectangularCamera {
+    /// Create a new camera
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn unproject(&self, coords: na::Point2<f32>, inv_view: na::Matrix4<f32>) -> na::Point3<f32> {
+        let angle = self.point_angle(coords);
+        let (lon_sin, lon_cos) = angle.x.sin_cos();
+        let (lat_sin, lat_cos) = angle.y.sin_cos();
+
+        // Point on a unit sphere.
+        let p = na::Point3::new(lat_cos * lon_sin, lat_sin, -lat_cos * lon_cos);
+
+        inv_view.transform_point(&p)
+    }
+
+    fn project(&self, world: na::Point3<f32>, view: na::Matrix4<f32>) -> na::Point2<f32> {
+        let p = view.transform_point(&world);
+
+        let lon = p.x.atan2(-p.z);
+        let lat = p.y.atan2(p.x.hypot(p.z));
+
+        na::Point2::new(0.5 + lon / TAU, 0.5 + lat / PI)
+    }
+
+    fn project_jacobian(
+        &self,
+        world: na::Point3<f32>,
+        view: na::Matrix4<f32>,
+    ) -> na::Matrix2x3<f32> {
+        let p = view.transform_point(&world);
+
+        let horizontal = p.x * p.x + p.z * p.z;
+        let dist = horizontal + p.y * p.y;
+        let rho = horizontal.sqrt();
+
+        // Derivatives of longitude and latitude.
+        let lon = na::RowVector3::new(-p.z, 0.0, p.x) / horizontal;
+        let lat = na::RowVector3::new(-p.y * p.x / rho, rho, -p.y * p.z / rho) / dist;
+
+        na::Matrix2x3::from_rows(&[lon / TAU, lat / PI]) * view.fixed_slice::<3, 3>(0, 0)
+    }
+
+    /// Get camera intrinsic parameters.
+    ///
+    /// The matrix is a linear approximation of the projection at the centre of the screen.
+    fn intrinsics(&self) -> na::Matrix3<f32> {
+        na::matrix![
+            1.0 / TAU, 0.0, 0.5;
+            0.0, 1.0 / PI, 0.5;
+            0.0, 0.0, 1.0
+        ]
+    }
+
+    /// Get longitude and latitude of a point in radians.
+    fn point_angle(&self, p: na::Point2<f32>) -> na::Vector2<f32> {
+        na::matrix![(p.x - 0.5) * TAU; (p.y - 0.5) * PI]
+    }
+
+    /// Horizontal motion is scaled by the cosine of latitude, as rows of the screen get
+    /// stretched towards the poles.
+    fn motion_scale(&self, coords: na::Point2<f32>) -> na::Vector2<f32> {
+        na::Vector2::new(self.point_angle(coords).y.cos().max(0.0), 1.0)
+    }
+
+    fn wraps_horizontally(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equirectangular_projection() {
+        let camera = EquirectangularCamera::new();
+        let view = na::Matrix4::identity();
+
+        for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75), (0.99, 0.01)] {
+            let p = na::Point2::new(p.0, p.1);
+            let world = camera.unproject(p, view);
+            assert!((world.coords.norm() - 1.0).abs() < 1e-5);
+            assert!((camera.project(world, view) - p).norm() < 1e-5, "{p}");
+        }
+
+        // Centre looks forward, while the sides look backwards.
+        let forward = camera.unproject(na::Point2::new(0.5, 0.5), view);
+        assert!((forward - na::Point3::new(0.0, 0.0, -1.0)).norm() < 1e-6);
+        let back = camera.unproject(na::Point2::new(0.0, 0.5), view);
+        assert!((back - na::Point3::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+
+        let angle = camera.point_angle(na::Point2::new(0.75, 0.25));
+        assert!((angle - na::Vector2::new(PI / 2.0, -PI / 4.0)).norm() < 1e-6);
+
+        // Rows get stretched towards the poles.
+        let centre = camera.motion_scale(na::Point2::new(0.3, 0.5));
+        assert!((centre - na::Vector2::repeat(1.0)).norm() < 1e-6);
+        assert!(camera.motion_scale(na::Point2::new(0.3, 0.0)).x < 1e-6);
+    }
+
+    #[test]
+    fn equirectangular_jacobian() {
+        crate::camera::tests::check_jacobian(
+            &EquirectangularCamera::new(),
+            &[(0.5, 0.5), (0.1, 0.2), (0.9, 0.75), (0.99, 0.01)],
+        );
+    }
+
+    #[test]
+    fn equirectangular_wrap() {
+        let camera = EquirectangularCamera::new();
+
+        // Rotate around the vertical axis, moving one of the points across the seam.
+        let rotation = na::Matrix4::from_euler_angles(0.0, 0.0, 20f32.to_radians());
+        let (a, b) = (na::Point2::new(0.02, 0.5), na::Point2::new(0.98, 0.5));
+
+        let delta = camera.delta(a, rotation);
+        assert!((delta.x.abs() - 1.0 / 18.0).abs() < 1e-4, "{delta}");
+        assert!(delta.y.abs() < 1e-5);
+        assert!((camera.delta(b, rotation) - delta).norm() < 1e-4);
+
+        assert!([a, b]
+            .iter()
+            .any(|&p| (camera.rotate(p, rotation) - p).x.abs() > 0.5));
+
+        assert_eq!(
+            camera.wrap_delta(na::Vector2::new(-0.75, 0.1)),
+            na::Vector2::new(0.25, 0.1)
+        );
+        assert_eq!(
+            StandardCamera::new(1.0, 90.0).wrap_delta(na::Vector2::new(-0.75, 0.1)),
+            na::Vector2::new(-0.75, 0.1)
+        );
+    }
+}
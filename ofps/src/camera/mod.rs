@@ -0,0 +1,888 @@
+//! # Camera abstraction
+
+use crate::frames;
+use crate::motion_vectors::{MotionVector, MotionVectors};
+use crate::utils::{self, Real};
+use nalgebra as na;
+use std::borrow::Cow;
+
+pub mod calibration;
+mod equirectangular;
+
+pub use equirectangular::EquirectangularCamera;
+
+/// Number of iterations used when inverting distortion models.
+const UNDISTORT_ITERS: usize = 20;
+
+/// Lens distortion model.
+///
+/// Models operate on normalised image coordinates, that is, coordinates of a point projected
+/// onto the `z = 1` plane, with principal point at the origin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Distortion {
+    /// No distortion, pure pinhole projection.
+    #[default]
+    None,
+    /// Brown-Conrady radial-tangential model, as used by OpenCV.
+    BrownConrady {
+        /// Radial coefficients.
+        k1: f32,
+        k2: f32,
+        k3: f32,
+        /// Tangential coefficients.
+        p1: f32,
+        p2: f32,
+    },
+    /// Equidistant fisheye model, as used by OpenCV's `fisheye` module.
+    Fisheye {
+        /// Coefficients of the polynomial of the angle of incidence.
+        k1: f32,
+        k2: f32,
+        k3: f32,
+        k4: f32,
+    },
+}
+
+impl Distortion {
+    /// Check whether the model has no effect.
+    pub fn is_none(&self) -> bool {
+        match *self {
+            Self::None => true,
+            Self::BrownConrady { k1, k2, k3, p1, p2 } => [k1, k2, k3, p1, p2] == [0.0; 5],
+            Self::Fisheye { k1, k2, k3, k4 } => [k1, k2, k3, k4] == [0.0; 4],
+        }
+    }
+
+    /// Apply distortion to a point.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - undistorted point, in normalised image coordinates.
+    pub fn distort(&self, p: na::Point2<f32>) -> na::Point2<f32> {
+        match *self {
+            Self::None => p,
+            Self::BrownConrady { k1, k2, k3, p1, p2 } => {
+                let (x, y) = (p.x, p.y);
+                let r2 = x * x + y * y;
+                let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                na::Point2::new(
+                    x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+                    y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+                )
+            }
+            Self::Fisheye { k1, k2, k3, k4 } => {
+                let r = p.coords.norm();
+
+                if r <= f32::EPSILON {
+                    return p;
+                }
+
+                let theta = r.atan();
+                let t2 = theta * theta;
+                let theta_d = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))));
+
+                p * (theta_d / r)
+            }
+        }
+    }
+
+    /// Remove distortion from a point.
+    ///
+    /// The models have no closed form inverse, thus the point is computed iteratively.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - distorted point, in normalised image coordinates.
+    pub fn undistort(&self, p: na::Point2<f32>) -> na::Point2<f32> {
+        match *self {
+            Self::None => p,
+            Self::BrownConrady { k1, k2, k3, p1, p2 } => {
+                // Fixed point iteration, same as in OpenCV's `undistortPoints`.
+                let mut u = p;
+
+                for _ in 0..UNDISTORT_ITERS {
+                    let (x, y) = (u.x, u.y);
+                    let r2 = x * x + y * y;
+                    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                    let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+                    let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+                    u = na::Point2::new((p.x - dx) / radial, (p.y - dy) / radial);
+                }
+
+                u
+            }
+            Self::Fisheye { k1, k2, k3, k4 } => {
+                let theta_d = p.coords.norm();
+
+                if theta_d <= f32::EPSILON {
+                    return p;
+                }
+
+                // Solve `theta_d = theta * (1 + k1 * theta^2 + ...)` with Newton's method.
+                let mut theta = theta_d;
+
+                for _ in 0..UNDISTORT_ITERS {
+                    let t2 = theta * theta;
+                    let f = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4)))) - theta_d;
+                    let df =
+                        1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+                    theta -= f / df;
+                }
+
+                let theta = theta.clamp(0.0, std::f32::consts::FRAC_PI_2 - f32::EPSILON);
+
+                p * (theta.tan() / theta_d)
+            }
+        }
+    }
+}
+
+/// Camera projection model.
+///
+/// Screen-space coordinates are in `[0; 1]` range, while camera space follows the OpenGL
+/// convention of looking towards `-z`. Implementors provide the projection and intrinsics, while
+/// the rest of functionality is derived from them.
+///
+/// Projection functions do not take lens distortion into account, thus input needs to be
+/// rectified first, see [`undistort_vectors`](Self::undistort_vectors).
+///
+/// Estimators may project points from multiple threads, thus cameras need to be [`Sync`].
+pub trait CameraModel: Sync {
+    /// Project a 3D point into screen space
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - point to project.
+    /// * `view` - camera view matrix.
+    fn project(&self, world: na::Point3<f32>, view: na::Matrix4<f32>) -> na::Point2<f32>;
+
+    /// Convert a screen-space point to 3D
+    ///
+    /// This function will convert a 2D point to 3D at an unspecified distance from camera.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen-space coordinates to unproject.
+    /// * `inv_view` - inverse of camera view matrix in 3D space.
+    fn unproject(&self, coords: na::Point2<f32>, inv_view: na::Matrix4<f32>) -> na::Point3<f32>;
+
+    /// Get camera intrinsic parameters.
+    ///
+    /// Focal lengths and principal point are expressed in screen-space units.
+    fn intrinsics(&self) -> na::Matrix3<f32>;
+
+    /// Get the lens distortion of the camera.
+    fn get_distortion(&self) -> Distortion {
+        Distortion::None
+    }
+
+    /// Get horizontal and vertical angle of a point in radians.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - point to have the angles computed for.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use assert_approx_eq::assert_approx_eq;
+    /// use ofps::camera::{CameraModel, StandardCamera};
+    /// use nalgebra as na;
+    ///
+    /// let camera = StandardCamera::new(1.0, 90.0);
+    ///
+    /// let angle = camera.point_angle(na::matrix![1.0; 0.5].into());
+    ///
+    /// assert_approx_eq!(angle.x.to_degrees(), 45.0f32, 0.01);
+    /// ```
+    fn point_angle(&self, p: na::Point2<f32>) -> na::Vector2<f32> {
+        let tan = self.screen_to_normalised(p);
+        na::matrix![tan.x.atan(); tan.y.atan()]
+    }
+
+    /// Rotate a 2D point around camera.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen space coordinates of the point to reproject.
+    /// * `rotation` - 3D rotation matrix of the point, in the [body frame](frames::Frame::Body).
+    fn rotate(&self, coords: na::Point2<f32>, rotation: na::Matrix4<f32>) -> na::Point2<f32> {
+        let view = frames::view_matrix(na::Point3::origin(), na::UnitQuaternion::identity());
+
+        let world = self.unproject(coords, view.transpose());
+
+        // Rotate the point as if it was on a sphere
+        let world = rotation.transform_point(&world);
+
+        self.project(world, view)
+    }
+
+    /// Calculate screen-space rotation of a 2D point being rotated around the camera.
+    fn delta(&self, coords: na::Point2<f32>, rotation: na::Matrix4<f32>) -> na::Vector2<f32> {
+        self.wrap_delta(self.rotate(coords, rotation) - coords)
+    }
+
+    /// Calculate the derivative of a projected point with respect to its 3D position.
+    ///
+    /// Returns a matrix that maps infinitesimal displacements of the point to screen-space
+    /// motion. By default, the derivative is computed numerically, while implementors may
+    /// provide a closed form.
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - point to project.
+    /// * `view` - camera view matrix.
+    fn project_jacobian(
+        &self,
+        world: na::Point3<f32>,
+        view: na::Matrix4<f32>,
+    ) -> na::Matrix2x3<f32> {
+        // Step relative to the distance of the point keeps the derivative accurate at any scale.
+        let dist = view.transform_point(&world).coords.norm();
+        let step = dist.max(f32::EPSILON) * 1e-3;
+
+        na::Matrix2x3::from_columns(&[0, 1, 2].map(|i| {
+            let offset = na::Vector3::ith(i, step);
+            let delta = self.project(world + offset, view) - self.project(world - offset, view);
+            self.wrap_delta(delta) / (2.0 * step)
+        }))
+    }
+
+    /// Get the scale of screen-space motion at a point, relative to the centre of the screen.
+    ///
+    /// Some projections, such as the equirectangular one, stretch parts of the screen.
+    /// Multiplying motion at the point by this scale brings it to the units of screen centre.
+    /// By default, no scaling is done.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen-space coordinates of the point.
+    fn motion_scale(&self, coords: na::Point2<f32>) -> na::Vector2<f32> {
+        let _ = coords;
+        na::Vector2::repeat(1.0)
+    }
+
+    /// Check whether the screen wraps around horizontally.
+    ///
+    /// Points leaving one side of such screen enter it from the other, as is the case with 360°
+    /// footage.
+    fn wraps_horizontally(&self) -> bool {
+        false
+    }
+
+    /// Convert a screen-space displacement to the shortest equivalent one.
+    ///
+    /// On screens that wrap around horizontally, displacements crossing the seam are off by the
+    /// width of the screen. This function removes the offset, while on other screens the input is
+    /// returned as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `delta` - displacement to wrap.
+    fn wrap_delta(&self, delta: na::Vector2<f32>) -> na::Vector2<f32> {
+        if self.wraps_horizontally() {
+            na::Vector2::new(delta.x - delta.x.round(), delta.y)
+        } else {
+            delta
+        }
+    }
+
+    /// Calculate the essential matrix given a fundamental one.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - input fundamental matrix.
+    fn essential(&self, f: na::Matrix3<f32>) -> na::Matrix3<f32> {
+        let k = self.intrinsics();
+        k.transpose() * f * k
+    }
+
+    /// Convert a screen-space point to normalised image coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen-space coordinates to convert.
+    fn screen_to_normalised(&self, coords: na::Point2<f32>) -> na::Point2<f32> {
+        let k = self.intrinsics();
+        let y = (coords.y - k[(1, 2)]) / k[(1, 1)];
+        let x = (coords.x - k[(0, 2)] - k[(0, 1)] * y) / k[(0, 0)];
+        na::Point2::new(x, y)
+    }
+
+    /// Convert normalised image coordinates to a screen-space point.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - normalised image coordinates to convert.
+    fn normalised_to_screen(&self, p: na::Point2<f32>) -> na::Point2<f32> {
+        let k = self.intrinsics();
+        na::Point2::new(
+            p.x * k[(0, 0)] + p.y * k[(0, 1)] + k[(0, 2)],
+            p.y * k[(1, 1)] + k[(1, 2)],
+        )
+    }
+
+    /// Apply lens distortion to a screen-space point.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - undistorted screen-space coordinates.
+    fn distort_point(&self, coords: na::Point2<f32>) -> na::Point2<f32> {
+        let p = self.screen_to_normalised(coords);
+        self.normalised_to_screen(self.get_distortion().distort(p))
+    }
+
+    /// Remove lens distortion from a screen-space point.
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - distorted screen-space coordinates.
+    fn undistort_point(&self, coords: na::Point2<f32>) -> na::Point2<f32> {
+        let p = self.screen_to_normalised(coords);
+        self.normalised_to_screen(self.get_distortion().undistort(p))
+    }
+
+    /// Remove lens distortion from a set of motion vectors.
+    ///
+    /// Both the start and the end point of each vector are undistorted. If the camera has no
+    /// distortion, the input is returned as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `vectors` - motion vectors captured by this camera.
+    fn undistort_vectors<'a>(&self, vectors: &'a MotionVectors) -> Cow<'a, MotionVectors> {
+        if self.get_distortion().is_none() {
+            return Cow::Borrowed(vectors);
+        }
+
+        let mut out = MotionVectors::with_capacity(vectors.len());
+
+        for vector in vectors.iter() {
+            let pos = self.undistort_point(vector.pos);
+            let end = self.undistort_point(vector.pos + vector.motion);
+            out.push(MotionVector {
+                pos,
+                motion: end - pos,
+                ..vector
+            });
+        }
+
+        Cow::Owned(out)
+    }
+}
+
+/// Standard pinhole camera
+///
+/// This camera is defined by x and y angle field-of-view and contains a simple perspective
+/// projection matrix.
+///
+/// The principal point is defined at `(0.5; 0.5)` coordinates. For cameras with arbitrary
+/// intrinsics, see [`CalibratedCamera`].
+///
+/// Optionally, the camera may have lens distortion.
+///
+/// The camera is generic over its scalar type, which is `f32` by default. Only the `f32` variant
+/// implements [`CameraModel`], while the projection functions are available for all scalar types.
+#[derive(Clone, Copy, Debug)]
+pub struct StandardCamera<T: Real = f32> {
+    aspect: T,
+    fov_y: T,
+    proj: na::Perspective3<T>,
+    inv_proj: na::Matrix4<T>,
+    distortion: Distortion,
+}
+
+impl StandardCamera {
+    /// Create a new camera
+    ///
+    /// Use [`from_fov`](StandardCamera::from_fov) for other scalar types.
+    ///
+    /// # Argumenta
+    ///
+    /// * `aspect` - screen aspect ratio.
+    /// * `fov_y` - vertical field-of-view (in degrees).
+    pub fn new(aspect: f32, fov_y: f32) -> Self {
+        Self::from_fov(aspect, fov_y)
+    }
+}
+
+impl<T: Real> StandardCamera<T> {
+    /// Create a new camera of any scalar type
+    ///
+    /// # Arguments
+    ///
+    /// * `aspect` - screen aspect ratio.
+    /// * `fov_y` - vertical field-of-view (in degrees).
+    pub fn from_fov(aspect: T, fov_y: T) -> Self {
+        let proj = na::Perspective3::new(
+            aspect,
+            to_radians(fov_y),
+            na::convert(0.1),
+            na::convert(10.0),
+        );
+
+        Self {
+            aspect,
+            fov_y,
+            inv_proj: proj.inverse(),
+            proj,
+            distortion: Distortion::None,
+        }
+    }
+
+    /// Set the lens distortion of the camera.
+    pub fn distortion(self, distortion: Distortion) -> Self {
+        Self { distortion, ..self }
+    }
+
+    /// Get the underlying projection matrix
+    ///
+    /// # Remarks
+    ///
+    /// The projective matrix will assume `(0; 0)` as the principal point point.
+    pub fn as_matrix(&self) -> &na::Matrix4<T> {
+        self.proj.as_matrix()
+    }
+
+    /// Get the camera's field of view.
+    ///
+    /// Returns horizontal and vertical field of view in degrees as a tuple.
+    pub fn fov(&self) -> (T, T) {
+        let two = na::convert::<f64, T>(2.0);
+        let ty = (to_radians(self.fov_y) / two).tan();
+        let tx = self.aspect * ty;
+        (to_degrees(tx.atan()) * two, self.fov_y)
+    }
+
+    /// Get the camera's aspect ratio.
+    ///
+    /// Returns vertical focal length divided by horizontal focal length.
+    pub fn aspect_ratio(&self) -> T {
+        self.aspect
+    }
+
+    /// Convert the camera to a different scalar type.
+    pub fn cast<U: Real>(&self) -> StandardCamera<U> {
+        StandardCamera::from_fov(utils::cast(self.aspect), utils::cast(self.fov_y))
+            .distortion(self.distortion)
+    }
+
+    /// Project a 3D point into screen space
+    ///
+    /// This is the same as [`CameraModel::project`].
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - point to project.
+    /// * `view` - camera view matrix.
+    pub fn project(&self, world: na::Point3<T>, view: na::Matrix4<T>) -> na::Point2<T> {
+        // Transform the point back into 2D. This already performs the perspective divide.
+        let screen = self.proj.project_point(&view.transform_point(&world));
+
+        // Transform the point back to [0; 1] range
+        (screen.xy() + na::Vector2::repeat(T::one())) * na::convert(0.5)
+    }
+
+    /// Convert a screen-space point to 3D
+    ///
+    /// This is the same as [`CameraModel::unproject`].
+    ///
+    /// # Arguments
+    ///
+    /// * `coords` - screen-space coordinates to unproject.
+    /// * `inv_view` - inverse of camera view matrix in 3D space.
+    pub fn unproject(&self, coords: na::Point2<T>, inv_view: na::Matrix4<T>) -> na::Point3<T> {
+        // Transform the point to [-1; 1] range
+        let coords = coords * na::convert(2.0) - na::Vector2::repeat(T::one());
+
+        // Transform the point into 3D space
+        (inv_view * self.inv_proj).transform_point(&na::Point3::new(coords.x, coords.y, T::one()))
+    }
+
+    /// Get camera intrinsic parameters.
+    ///
+    /// This is the same as [`CameraModel::intrinsics`].
+    pub fn intrinsics(&self) -> na::Matrix3<T> {
+        let half = na::convert::<f64, T>(0.5);
+        let fy = half / (to_radians(self.fov_y) * half).tan();
+        let fx = fy / self.aspect;
+
+        na::matrix![
+            fx, T::zero(), half;
+            T::zero(), fy, half;
+            T::zero(), T::zero(), T::one()
+        ]
+    }
+}
+
+impl CameraModel for StandardCamera {
+    fn unproject(&self, coords: na::Point2<f32>, inv_view: na::Matrix4<f32>) -> na::Point3<f32> {
+        StandardCamera::unproject(self, coords, inv_view)
+    }
+
+    fn project(&self, world: na::Point3<f32>, view: na::Matrix4<f32>) -> na::Point2<f32> {
+        StandardCamera::project(self, world, view)
+    }
+
+    fn project_jacobian(
+        &self,
+        world: na::Point3<f32>,
+        view: na::Matrix4<f32>,
+    ) -> na::Matrix2x3<f32> {
+        pinhole_jacobian(&self.intrinsics(), world, view)
+    }
+
+    fn intrinsics(&self) -> na::Matrix3<f32> {
+        StandardCamera::intrinsics(self)
+    }
+
+    fn get_distortion(&self) -> Distortion {
+        self.distortion
+    }
+}
+
+/// Compute the derivative of pinhole projection with respect to the position of a point.
+///
+/// # Arguments
+///
+/// * `intrinsics` - intrinsic matrix of the camera.
+/// * `world` - point to project.
+/// * `view` - camera view matrix.
+fn pinhole_jacobian(
+    intrinsics: &na::Matrix3<f32>,
+    world: na::Point3<f32>,
+    view: na::Matrix4<f32>,
+) -> na::Matrix2x3<f32> {
+    let p = view.transform_point(&world);
+    let inv_z = 1.0 / -p.z;
+
+    // Derivative of normalised coordinates `(x / -z, y / -z)`.
+    let normalised = na::matrix![
+        inv_z, 0.0, p.x * inv_z * inv_z;
+        0.0, inv_z, p.y * inv_z * inv_z
+    ];
+
+    intrinsics.fixed_slice::<2, 2>(0, 0) * normalised * view.fixed_slice::<3, 3>(0, 0)
+}
+
+/// Convert degrees to radians.
+fn to_radians<T: Real>(deg: T) -> T {
+    deg * (T::pi() / na::convert(180.0))
+}
+
+/// Convert radians to degrees.
+fn to_degrees<T: Real>(rad: T) -> T {
+    rad * (na::convert::<f64, T>(180.0) / T::pi())
+}
+
+/// Calibrated pinhole camera
+///
+/// This camera is defined by a full intrinsic matrix, thus it supports off-centre principal
+/// points, non-square pixels and skew. The matrix is expressed in screen-space units, that is,
+/// focal lengths and principal point are divided by image dimensions.
+///
+/// Cameras calibrated with OpenCV can be loaded with
+/// [`load_calibration`](Self::load_calibration).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibratedCamera {
+    intrinsics: na::Matrix3<f32>,
+    distortion: Distortion,
+}
+
+impl CalibratedCamera {
+    /// Create a new camera
+    ///
+    /// # Arguments
+    ///
+    /// * `intrinsics` - intrinsic matrix in screen-space units.
+    pub fn new(intrinsics: na::Matrix3<f32>) -> Self {
+        Self {
+            intrinsics,
+            distortion: Distortion::None,
+        }
+    }
+
+    /// Create a new camera from intrinsics in pixels.
+    ///
+    /// Calibration tools place the centre of the first pixel at `0`, while in screen space it is
+    /// at half a pixel, thus the principal point is shifted accordingly.
+    ///
+    /// # Arguments
+    ///
+    /// * `intrinsics` - intrinsic matrix in pixels, as produced by calibration tools.
+    /// * `size` - width and height of the calibrated images.
+    pub fn from_pixels(intrinsics: na::Matrix3<f32>, (width, height): (usize, usize)) -> Self {
+        let shift = na::Matrix3::new_translation(&na::Vector2::repeat(0.5));
+        let scale = na::Matrix3::new_nonuniform_scaling(
+            &na::matrix![1.0 / width as f32; 1.0 / height as f32],
+        );
+        Self::new(scale * shift * intrinsics)
+    }
+
+    /// Set the lens distortion of the camera.
+    pub fn distortion(self, distortion: Distortion) -> Self {
+        Self { distortion, ..self }
+    }
+}
+
+impl From<StandardCamera> for CalibratedCamera {
+    fn from(camera: StandardCamera) -> Self {
+        Self::new(camera.intrinsics()).distortion(camera.get_distortion())
+    }
+}
+
+impl CameraModel for CalibratedCamera {
+    fn unproject(&self, coords: na::Point2<f32>, inv_view: na::Matrix4<f32>) -> na::Point3<f32> {
+        let p = self.screen_to_normalised(coords);
+        inv_view.transform_point(&na::Point3::new(p.x, p.y, -1.0))
+    }
+
+    fn project(&self, world: na::Point3<f32>, view: na::Matrix4<f32>) -> na::Point2<f32> {
+        let p = view.transform_point(&world);
+        self.normalised_to_screen(na::Point2::new(p.x / -p.z, p.y / -p.z))
+    }
+
+    fn intrinsics(&self) -> na::Matrix3<f32> {
+        self.intrinsics
+    }
+
+    fn project_jacobian(
+        &self,
+        world: na::Point3<f32>,
+        view: na::Matrix4<f32>,
+    ) -> na::Matrix2x3<f32> {
+        pinhole_jacobian(&self.intrinsics, world, view)
+    }
+
+    fn get_distortion(&self) -> Distortion {
+        self.distortion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distortion_roundtrip() {
+        let models = [
+            Distortion::BrownConrady {
+                k1: -0.3,
+                k2: 0.1,
+                k3: 0.0,
+                p1: 0.001,
+                p2: -0.002,
+            },
+            Distortion::Fisheye {
+                k1: 0.05,
+                k2: -0.01,
+                k3: 0.002,
+                k4: 0.0,
+            },
+        ];
+
+        for model in models {
+            let camera = StandardCamera::new(16.0 / 9.0, 60.0).distortion(model);
+
+            for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75), (0.3, 0.95)] {
+                let p = na::Point2::new(p.0, p.1);
+                let distorted = camera.distort_point(p);
+                let undistorted = camera.undistort_point(distorted);
+                assert!(
+                    (undistorted - p).norm() < 1e-4,
+                    "{model:?} {p} {undistorted}"
+                );
+            }
+
+            // Principal point does not move, while the corners do.
+            let centre = na::Point2::new(0.5, 0.5);
+            assert!((camera.distort_point(centre) - centre).norm() < 1e-6);
+            assert!((camera.distort_point(na::Point2::new(0.0, 0.0)).x).abs() > 1e-3);
+        }
+    }
+
+    #[test]
+    fn project_roundtrip() {
+        let camera = StandardCamera::new(16.0 / 9.0, 60.0);
+
+        let view = na::Matrix4::look_at_rh(
+            &na::Point3::new(1.0, 2.0, -0.5),
+            &na::Point3::new(0.0, -1.0, 0.0),
+            &na::Vector3::new(0.0, 0.0, 1.0),
+        );
+        let inv_view = view.try_inverse().unwrap();
+        let origin = inv_view.transform_point(&na::Point3::origin());
+
+        // Points anywhere along the ray project back to the same screen position.
+        for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75)] {
+            let p = na::Point2::new(p.0, p.1);
+            let dir = camera.unproject(p, inv_view) - origin;
+            for depth in [0.01, 0.5, 1.0, 10.0, 1000.0] {
+                let world = origin + dir * depth;
+                assert!(
+                    (camera.project(world, view) - p).norm() < 1e-4,
+                    "{p} {depth}"
+                );
+                assert!((CalibratedCamera::from(camera).project(world, view) - p).norm() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn undistort_vectors() {
+        let mut vectors = MotionVectors::new();
+        vectors.push(
+            MotionVector::new(na::Point2::new(0.2, 0.3), na::Vector2::new(0.05, -0.02))
+                .confidence(0.5),
+        );
+
+        let camera = StandardCamera::new(1.0, 90.0);
+        assert!(matches!(
+            camera.undistort_vectors(&vectors),
+            Cow::Borrowed(_)
+        ));
+
+        let distortion = Distortion::BrownConrady {
+            k1: -0.2,
+            k2: 0.0,
+            k3: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+        };
+        let camera = camera.distortion(distortion);
+        let rectified = camera.undistort_vectors(&vectors);
+        let v = rectified.get(0).unwrap();
+
+        assert_eq!(v.confidence, 0.5);
+        assert!((v.pos - camera.undistort_point(na::Point2::new(0.2, 0.3))).norm() < 1e-6);
+        assert!(
+            (camera.distort_point(v.pos + v.motion) - na::Point2::new(0.25, 0.28)).norm() < 1e-4
+        );
+    }
+
+    #[test]
+    fn calibrated_camera() {
+        let standard = StandardCamera::new(16.0 / 9.0, 60.0);
+        let calibrated = CalibratedCamera::from(standard);
+
+        let rotation = na::Matrix4::from_euler_angles(0.01, 0.02, -0.01);
+
+        for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75)] {
+            let p = na::Point2::new(p.0, p.1);
+            assert!((standard.point_angle(p) - calibrated.point_angle(p)).norm() < 1e-6);
+            assert!((standard.delta(p, rotation) - calibrated.delta(p, rotation)).norm() < 1e-4);
+        }
+
+        // Off-centre principal point with skew.
+        let camera = CalibratedCamera::from_pixels(
+            na::matrix![
+                500.0, 2.0, 300.0;
+                0.0, 520.0, 260.0;
+                0.0, 0.0, 1.0
+            ],
+            (640, 480),
+        );
+
+        let view = na::Matrix4::look_at_rh(
+            &na::Point3::new(0.0, 0.0, 0.0),
+            &na::Point3::new(0.0, -1.0, 0.0),
+            &na::Vector3::new(0.0, 0.0, 1.0),
+        );
+        let inv_view = view.try_inverse().unwrap();
+
+        for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75)] {
+            let p = na::Point2::new(p.0, p.1);
+            let world = camera.unproject(p, inv_view);
+            assert!((camera.project(world, view) - p).norm() < 1e-5);
+        }
+
+        let centre = na::Point2::new(300.5 / 640.0, 260.5 / 480.0);
+        assert!(camera.point_angle(centre).norm() < 1e-6);
+    }
+
+    /// Compare closed form projection derivative to the numerical one.
+    pub(crate) fn check_jacobian(camera: &dyn CameraModel, points: &[(f32, f32)]) {
+        /// Camera with numerically computed derivatives.
+        struct Numeric<'a>(&'a dyn CameraModel);
+
+        impl CameraModel for Numeric<'_> {
+            fn project(&self, world: na::Point3<f32>, view: na::Matrix4<f32>) -> na::Point2<f32> {
+                self.0.project(world, view)
+            }
+
+            fn unproject(
+                &self,
+                coords: na::Point2<f32>,
+                inv_view: na::Matrix4<f32>,
+            ) -> na::Point3<f32> {
+                self.0.unproject(coords, inv_view)
+            }
+
+            fn intrinsics(&self) -> na::Matrix3<f32> {
+                self.0.intrinsics()
+            }
+
+            fn wraps_horizontally(&self) -> bool {
+                self.0.wraps_horizontally()
+            }
+        }
+
+        let view = frames::view_matrix(na::Point3::new(0.1, -0.2, 0.3), Default::default());
+        let inv_view = view.try_inverse().unwrap();
+
+        for &(x, y) in points {
+            let world = camera.unproject(na::Point2::new(x, y), inv_view);
+            let analytic = camera.project_jacobian(world, view);
+            let numeric = Numeric(camera).project_jacobian(world, view);
+
+            assert!(
+                (analytic - numeric).norm() < 1e-2 * numeric.norm(),
+                "{analytic} {numeric}"
+            );
+        }
+    }
+
+    #[test]
+    fn pinhole_jacobian() {
+        let points = [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75)];
+        check_jacobian(&StandardCamera::new(16.0 / 9.0, 60.0), &points);
+        check_jacobian(
+            &CalibratedCamera::new(na::matrix![
+                0.45, 0.01, 0.55;
+                0.0, 0.6, 0.42;
+                0.0, 0.0, 1.0
+            ]),
+            &points,
+        );
+    }
+
+    #[test]
+    fn standard_camera_f64() {
+        let camera = StandardCamera::new(16.0 / 9.0, 60.0);
+        let camera64 = camera.cast::<f64>();
+
+        let (fov_x, fov_y) = camera64.fov();
+        assert!((fov_x - camera.fov().0 as f64).abs() < 1e-4);
+        assert_eq!(fov_y, 60.0);
+        assert!((camera64.intrinsics().cast::<f32>() - camera.intrinsics()).norm() < 1e-6);
+
+        let view64 = na::Matrix4::look_at_rh(
+            &na::Point3::origin(),
+            &na::Point3::new(0.0, 1.0, 0.0),
+            &na::Vector3::z(),
+        );
+        let view = view64.cast::<f32>();
+
+        for p in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.75)] {
+            let p = na::Point2::new(p.0, p.1);
+            let world = camera64.unproject(p, view64.transpose());
+            let projected = camera64.project(world, view64);
+            assert!((projected - p).norm() < 1e-9);
+            assert!(
+                (projected.cast::<f32>() - CameraModel::project(&camera, world.cast(), view))
+                    .norm()
+                    < 1e-5
+            );
+        }
+    }
+}
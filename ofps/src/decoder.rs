@@ -2,7 +2,6 @@
 
 use crate::prelude::v1::*;
 use bytemuck::{Pod, Zeroable};
-use nalgebra as na;
 
 /// RGBA colour structure.
 #[repr(C)]
@@ -36,10 +35,38 @@ impl RGBA {
     }
 }
 
-/// Pair containing coordinates and motion at them.
-pub type MotionEntry = (na::Point2<f32>, na::Vector2<f32>);
-/// Vector of `MotionEntry` elements.
-pub type MotionVectors = Vec<MotionEntry>;
+pub use crate::motion_vectors::{MotionEntry, MotionVectors};
+
+/// Type of a coded picture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PictureType {
+    /// Picture type is not known, or the decoder does not distinguish picture types.
+    #[default]
+    Unknown,
+    /// Intra coded picture.
+    I,
+    /// Predicted picture.
+    P,
+    /// Bidirectionally predicted picture.
+    B,
+}
+
+/// Metadata of a processed frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameInfo {
+    /// Presentation timestamp in seconds since the start of the stream, if known.
+    pub pts: Option<f64>,
+    /// Index of the frame in decoding order.
+    pub decode_index: usize,
+    /// Type of the picture.
+    pub picture_type: PictureType,
+    /// Whether decoding can start at this frame.
+    pub keyframe: bool,
+    /// Whether some of the motion vectors reference a past frame.
+    pub forward: bool,
+    /// Whether some of the motion vectors reference a future frame.
+    pub backward: bool,
+}
 
 /// Optical flow decoder.
 pub trait Decoder {
@@ -51,10 +78,13 @@ pub trait Decoder {
     /// there is an error while processing, `Err` is returned.
     ///
     /// If the decoder supports it, `out_frame` may also be written at either of the `Ok` cases.
+    /// Likewise, `frame_info` gets filled with metadata of the frame. Fields the decoder does
+    /// not know are left at their default values.
     fn process_frame(
         &mut self,
         field: &mut MotionVectors,
         out_frame: Option<(&mut Vec<RGBA>, &mut usize)>,
+        frame_info: Option<&mut FrameInfo>,
         skip_frames: usize,
     ) -> Result<bool>;
 
@@ -70,4 +100,56 @@ pub trait Decoder {
     /// cases it may not be known, which will then return `None`. Aspect ratio may change
     /// after the first frame is processed.
     fn get_aspect(&self) -> Option<(usize, usize)>;
+
+    /// Check whether the decoder supports seeking.
+    ///
+    /// If this returns `false`, `seek_to_frame` and `seek_to_time` will always fail.
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    /// Seek to a given frame.
+    ///
+    /// Upon success, the next `process_frame` call will process the frame at index `frame`.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - zero-based index of the frame to seek to.
+    fn seek_to_frame(&mut self, frame: usize) -> Result<()> {
+        let _ = frame;
+        Err(anyhow!("Decoder does not support seeking"))
+    }
+
+    /// Seek to a given time.
+    ///
+    /// By default, this converts the time to a frame index using the framerate of the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - time to seek to, in seconds since the start of the stream.
+    fn seek_to_time(&mut self, time: f64) -> Result<()> {
+        let framerate = self
+            .get_framerate()
+            .ok_or_else(|| anyhow!("Framerate is unknown"))?;
+        self.seek_to_frame((time.max(0.0) * framerate).round() as usize)
+    }
+
+    /// Seek back to the start of the stream.
+    fn rewind(&mut self) -> Result<()> {
+        self.seek_to_frame(0)
+    }
+
+    /// Get the total number of frames in the stream.
+    ///
+    /// This will return `None` if the number is not known, such as on realtime streams.
+    fn frame_count(&self) -> Option<usize> {
+        None
+    }
+
+    /// Get the duration of the stream in seconds.
+    ///
+    /// By default, this is computed from the frame count and framerate.
+    fn duration(&self) -> Option<f64> {
+        Some(self.frame_count()? as f64 / self.get_framerate()?)
+    }
 }
@@ -8,5 +8,5 @@ pub trait Detector {
     ///
     /// This function will return `None`, if there is no motion, or a filtered motion field with
     /// the number of vectors in motion.
-    fn detect_motion(&self, motion: &[MotionEntry]) -> Option<(usize, MotionField)>;
+    fn detect_motion(&self, motion: &MotionVectors) -> Option<(usize, MotionField)>;
 }
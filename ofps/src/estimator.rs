@@ -18,16 +18,57 @@ pub trait Estimator {
     /// * `move_magnitude` - optional hint for translation magnitude.
     fn estimate(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
         move_magnitude: Option<f32>,
     ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)>;
 
+    /// Estimate single-frame of camera motion in double precision.
+    ///
+    /// Estimators that compute in double precision should override this function, so that their
+    /// estimates are not rounded to single precision on the way out. By default, the output of
+    /// [`estimate`](Self::estimate) is converted.
+    ///
+    /// # Arguments
+    ///
+    /// * `motion_vectors` - input optical flow motion field.
+    /// * `camera` - camera to use in estimation.
+    /// * `move_magnitude` - optional hint for translation magnitude.
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        let (r, tr) = self.estimate(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    /// Get the zoom estimated on the last frame.
+    ///
+    /// Returns the ratio of the new focal length to the previous one, or `None`, if the estimator
+    /// does not estimate zoom.
+    fn get_zoom(&self) -> Option<f32> {
+        None
+    }
+
+    /// Get the zoom accumulated over all estimated frames.
+    ///
+    /// Returns the ratio of the current focal length to the one of the input camera, or `None`,
+    /// if the estimator does not estimate zoom. This can be used to adjust field of view of the
+    /// camera.
+    fn get_focal_scale(&self) -> Option<f32> {
+        None
+    }
+
     /// Estimate camera motion and apply it to previous motion.
     ///
     /// This function processes the next motion field and produces rotation and translation
     /// estimates. Not all estimators are stateless, thus this function expects sequential frames.
     ///
+    /// The pose is kept in double precision, which avoids accumulating rounding errors over long
+    /// sequences. Estimates come from [`estimate_f64`](Self::estimate_f64).
+    ///
     /// # Arguments
     ///
     /// * `motion_vectors` - input optical flow motion field.
@@ -37,18 +78,72 @@ pub trait Estimator {
     /// * `pos` - camera position to modify.
     fn motion_step(
         &mut self,
-        motion_vectors: &[MotionEntry],
-        camera: &StandardCamera,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
         move_magnitude: Option<f32>,
-        rot: &mut na::UnitQuaternion<f32>,
-        pos: &mut na::Point3<f32>,
-    ) -> Result<()>
-    where
-        Self: Sized,
-    {
-        let (r, tr) = self.estimate(motion_vectors, camera, move_magnitude)?;
+        rot: &mut na::UnitQuaternion<f64>,
+        pos: &mut na::Point3<f64>,
+    ) -> Result<()> {
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
         *pos += *rot * tr;
         *rot = r * *rot;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Estimator that always turns the camera by the same amount.
+    struct ConstantEstimator(na::UnitQuaternion<f64>);
+
+    impl Estimator for ConstantEstimator {
+        fn estimate(
+            &mut self,
+            motion_vectors: &MotionVectors,
+            camera: &dyn CameraModel,
+            move_magnitude: Option<f32>,
+        ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
+            let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+            Ok((r.cast(), tr.cast()))
+        }
+
+        fn estimate_f64(
+            &mut self,
+            _: &MotionVectors,
+            _: &dyn CameraModel,
+            _: Option<f32>,
+        ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+            Ok((self.0, na::Vector3::y()))
+        }
+    }
+
+    #[test]
+    fn motion_step_f64() {
+        let step = 0.001f64;
+        let mut estimator: Box<dyn Estimator> = Box::new(ConstantEstimator(
+            na::UnitQuaternion::from_euler_angles(0.0, 0.0, step),
+        ));
+        let camera = StandardCamera::new(1.0, 90.0);
+        let mv = MotionVectors::new();
+
+        let mut rot = na::UnitQuaternion::identity();
+        let mut pos = na::Point3::origin();
+
+        for _ in 0..1000 {
+            estimator
+                .motion_step(&mv, &camera, None, &mut rot, &mut pos)
+                .unwrap();
+        }
+
+        // The camera walks forward while turning, along an arc. Nothing is rounded to single
+        // precision on the way.
+        let expected = na::UnitQuaternion::from_euler_angles(0.0, 0.0, step * 1000.0);
+        assert!((rot.coords - expected.coords).norm() < 1e-12);
+
+        let dist = (0..1000).map(|i| (i as f64 * step).cos()).sum::<f64>();
+        assert!((pos.y - dist).abs() < 1e-10);
+        assert!(pos.x < 0.0 && pos.z.abs() < 1e-12);
+    }
+}
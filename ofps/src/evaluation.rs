@@ -0,0 +1,250 @@
+//! # Optical flow evaluation
+//!
+//! This module measures accuracy of estimated flow against a ground-truth motion field, using
+//! the metrics common in optical flow benchmarks:
+//!
+//! * Average endpoint error (EPE) - mean euclidean distance between estimated and true motion.
+//! * Average angular error - mean angle between `(u, v, 1)` vectors of estimated and true motion,
+//!   in degrees.
+//! * Outlier ratio (KITTI `Fl-all`) - fraction of cells, whose endpoint error exceeds both the
+//!   absolute and relative thresholds of [`OutlierThreshold`].
+//! * Coverage - fraction of ground-truth cells, for which the estimate has data.
+//!
+//! Only cells that are valid in the ground truth are evaluated, thus its weights act as the
+//! validity mask. Error metrics are computed over cells covered by the estimate.
+
+use crate::prelude::v1::*;
+use nalgebra as na;
+use std::ops::{Add, AddAssign};
+
+/// Thresholds above which a cell is considered an outlier.
+///
+/// A cell is an outlier if its endpoint error is above `absolute`, and above `relative` times the
+/// magnitude of true motion. Defaults match KITTI 2015 benchmark.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlierThreshold {
+    /// Absolute endpoint error threshold, in ground-truth units.
+    pub absolute: f32,
+    /// Endpoint error threshold relative to the magnitude of true motion.
+    pub relative: f32,
+}
+
+impl Default for OutlierThreshold {
+    fn default() -> Self {
+        Self {
+            absolute: 3.0,
+            relative: 0.05,
+        }
+    }
+}
+
+/// Accumulated flow accuracy metrics.
+///
+/// Metrics of multiple frames can be added together, producing the aggregate over all evaluated
+/// cells of the frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlowMetrics {
+    endpoint_error: f64,
+    angular_error: f64,
+    outliers: usize,
+    evaluated: usize,
+    reference: usize,
+}
+
+impl FlowMetrics {
+    /// Compare an estimated motion field against ground truth.
+    ///
+    /// The estimate is sampled at the centre of each ground-truth cell, thus the fields do not
+    /// need to be of the same size.
+    ///
+    /// # Arguments
+    ///
+    /// * `estimate` - estimated motion field.
+    /// * `ground_truth` - ground-truth motion field. Invalid cells are not evaluated.
+    /// * `scale` - factor to convert estimated motion to ground-truth units. For instance, if the
+    ///   estimate is in normalised units, and ground truth is in pixels, this is the dimensions of
+    ///   the ground truth.
+    /// * `threshold` - outlier thresholds.
+    pub fn compare_fields(
+        estimate: &MotionField,
+        ground_truth: &MotionField,
+        scale: na::Vector2<f32>,
+        threshold: OutlierThreshold,
+    ) -> Self {
+        let (w, h) = ground_truth.dim();
+        let mut metrics = Self::default();
+
+        for (x, y, truth) in ground_truth.iter_valid() {
+            metrics.reference += 1;
+
+            let pos = na::Point2::new((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+            let (motion, weight) = estimate.sample_weighted(pos);
+
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let motion = motion.component_mul(&scale);
+            let error = (motion - truth).magnitude();
+
+            metrics.evaluated += 1;
+            metrics.endpoint_error += error as f64;
+            metrics.angular_error += angular_error(motion, truth) as f64;
+
+            if error > threshold.absolute && error > threshold.relative * truth.magnitude() {
+                metrics.outliers += 1;
+            }
+        }
+
+        metrics
+    }
+
+    /// Compare motion vectors against ground truth.
+    ///
+    /// Vectors are densified at the resolution of the ground truth using
+    /// [`DensifyKernel::Footprint`], so that each vector covers the cells of its area.
+    ///
+    /// # Arguments
+    ///
+    /// * `vectors` - estimated motion vectors.
+    /// * `ground_truth` - ground-truth motion field. Invalid cells are not evaluated.
+    /// * `scale` - factor to convert motion of the vectors to ground-truth units.
+    /// * `threshold` - outlier thresholds.
+    pub fn compare_vectors(
+        vectors: &MotionVectors,
+        ground_truth: &MotionField,
+        scale: na::Vector2<f32>,
+        threshold: OutlierThreshold,
+    ) -> Self {
+        let (w, h) = ground_truth.dim();
+        let mut densifier = MotionFieldDensifier::new(w, h).kernel(DensifyKernel::Footprint);
+        densifier.add_vectors(vectors);
+        Self::compare_fields(&densifier.into(), ground_truth, scale, threshold)
+    }
+
+    /// Average endpoint error of evaluated cells.
+    ///
+    /// Returns `NaN` if no cells were evaluated.
+    pub fn endpoint_error(&self) -> f32 {
+        (self.endpoint_error / self.evaluated as f64) as f32
+    }
+
+    /// Average angular error of evaluated cells, in degrees.
+    ///
+    /// Returns `NaN` if no cells were evaluated.
+    pub fn angular_error(&self) -> f32 {
+        (self.angular_error / self.evaluated as f64) as f32
+    }
+
+    /// Fraction of evaluated cells that are outliers.
+    ///
+    /// Returns `NaN` if no cells were evaluated.
+    pub fn outlier_ratio(&self) -> f32 {
+        self.outliers as f32 / self.evaluated as f32
+    }
+
+    /// Fraction of valid ground-truth cells covered by the estimate.
+    ///
+    /// Returns `NaN` if ground truth had no valid cells.
+    pub fn coverage(&self) -> f32 {
+        self.evaluated as f32 / self.reference as f32
+    }
+
+    /// Number of evaluated cells.
+    pub fn evaluated(&self) -> usize {
+        self.evaluated
+    }
+}
+
+impl Add for FlowMetrics {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for FlowMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.endpoint_error += other.endpoint_error;
+        self.angular_error += other.angular_error;
+        self.outliers += other.outliers;
+        self.evaluated += other.evaluated;
+        self.reference += other.reference;
+    }
+}
+
+/// Compute the angle between `(a.x, a.y, 1)` and `(b.x, b.y, 1)` vectors, in degrees.
+fn angular_error(a: na::Vector2<f32>, b: na::Vector2<f32>) -> f32 {
+    let cos = (a.dot(&b) + 1.0) / ((a.norm_squared() + 1.0) * (b.norm_squared() + 1.0)).sqrt();
+    cos.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn field_metrics() {
+        let mut truth = MotionField::new(4, 2);
+        truth.set_motion(0, 0, na::Vector2::new(10.0, 0.0));
+        truth.set_motion_weighted(3, 1, na::Vector2::zeros(), 0.0);
+
+        let mut estimate = MotionField::new(4, 2);
+        estimate.set_motion(0, 0, na::Vector2::new(6.0, 0.0));
+        estimate.set_motion(1, 0, na::Vector2::new(0.0, 1.0));
+        estimate.set_motion_weighted(2, 1, na::Vector2::zeros(), 0.0);
+
+        let metrics = FlowMetrics::compare_fields(
+            &estimate,
+            &truth,
+            na::Vector2::repeat(1.0),
+            Default::default(),
+        );
+
+        assert_eq!(metrics.evaluated(), 6);
+        assert_approx_eq!(metrics.coverage(), 6.0 / 7.0);
+        assert_approx_eq!(metrics.endpoint_error(), 5.0 / 6.0);
+        assert_approx_eq!(metrics.outlier_ratio(), 1.0 / 6.0);
+        assert_approx_eq!(metrics.angular_error(), 8.1253, 1e-3);
+
+        // Scale converts estimate units to those of ground truth.
+        let scaled = FlowMetrics::compare_fields(
+            &estimate,
+            &truth,
+            na::Vector2::repeat(10.0),
+            Default::default(),
+        );
+        assert!(scaled.endpoint_error() > metrics.endpoint_error());
+
+        let total = metrics + scaled;
+        assert_eq!(total.evaluated(), 12);
+        assert_approx_eq!(total.coverage(), 6.0 / 7.0);
+
+        assert!(FlowMetrics::default().endpoint_error().is_nan());
+    }
+
+    #[test]
+    fn vector_metrics() {
+        let truth = MotionField::new(8, 8);
+
+        let mut vectors = MotionVectors::new();
+        vectors.push(
+            MotionVector::new(na::Point2::new(0.25, 0.25), na::Vector2::new(0.1, 0.0))
+                .footprint(na::Vector2::new(0.5, 0.5)),
+        );
+
+        let metrics = FlowMetrics::compare_vectors(
+            &vectors,
+            &truth,
+            na::Vector2::new(8.0, 8.0),
+            Default::default(),
+        );
+
+        assert_approx_eq!(metrics.coverage(), 0.25);
+        assert_approx_eq!(metrics.endpoint_error(), 0.8);
+        assert_eq!(metrics.outlier_ratio(), 0.0);
+    }
+}
@@ -0,0 +1,240 @@
+//! # Coordinate frame conventions
+//!
+//! Different parts of the pipeline describe rotations and translations in different frames -
+//! OpenCV returns poses in its camera frame, projection works in OpenGL camera space, while
+//! ground truth comes from Blender. This module names the conventions and converts between them,
+//! so that estimator outputs are expressed in the same frame.
+//!
+//! Estimators output camera motion in the [`Frame::Body`] frame:
+//!
+//! * `+x` points to the right of the camera.
+//! * `+y` points forward, where the camera looks.
+//! * `+z` points up.
+//!
+//! Roll is rotation around the forward axis, pitch - around the right axis, and yaw - around the
+//! up axis. See [`from_roll_pitch_yaw`] and [`roll_pitch_yaw`].
+
+use crate::utils::Real;
+use nalgebra as na;
+
+/// Coordinate frame convention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// OFPS body frame - `x` right, `y` forward, `z` up.
+    Body,
+    /// OpenGL camera frame - `x` right, `y` up, camera looks towards `-z`.
+    ///
+    /// This is the camera space of [`CameraModel`](crate::camera::CameraModel).
+    OpenGl,
+    /// OpenCV camera frame - `x` right, `y` down, camera looks towards `+z`.
+    OpenCv,
+    /// Blender world frame - `z` up, camera at rest looks towards `+y`, same as the front view.
+    Blender,
+    /// East-North-Up world frame - camera at rest looks north.
+    Enu,
+}
+
+impl Frame {
+    /// Get the rotation that maps coordinates in this frame to the body frame.
+    pub fn to_body<T: Real>(self) -> na::UnitQuaternion<T> {
+        match self {
+            Self::Body | Self::Blender | Self::Enu => na::UnitQuaternion::identity(),
+            Self::OpenGl => {
+                na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), T::frac_pi_2())
+            }
+            Self::OpenCv => {
+                na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), -T::frac_pi_2())
+            }
+        }
+    }
+
+    /// Get the rotation that maps coordinates in this frame to the `other` frame.
+    pub fn change_of_basis<T: Real>(self, other: Frame) -> na::UnitQuaternion<T> {
+        other.to_body().inverse() * self.to_body()
+    }
+
+    /// Convert a rotation expressed in this frame to the `other` frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - frame to convert to.
+    /// * `rotation` - rotation to convert.
+    pub fn convert_rotation<T: Real>(
+        self,
+        other: Frame,
+        rotation: na::UnitQuaternion<T>,
+    ) -> na::UnitQuaternion<T> {
+        let basis = self.change_of_basis(other);
+        basis * rotation * basis.inverse()
+    }
+
+    /// Convert a translation expressed in this frame to the `other` frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - frame to convert to.
+    /// * `translation` - translation to convert.
+    pub fn convert_translation<T: Real>(
+        self,
+        other: Frame,
+        translation: na::Vector3<T>,
+    ) -> na::Vector3<T> {
+        self.change_of_basis(other) * translation
+    }
+}
+
+/// Build a body frame rotation from roll, pitch and yaw angles, in radians.
+///
+/// Rotations are applied in roll, pitch, yaw order.
+pub fn from_roll_pitch_yaw(roll: f32, pitch: f32, yaw: f32) -> na::UnitQuaternion<f32> {
+    na::UnitQuaternion::from_euler_angles(pitch, roll, yaw)
+}
+
+/// Decompose a body frame rotation into roll, pitch and yaw angles, in radians.
+///
+/// This is the inverse of [`from_roll_pitch_yaw`].
+pub fn roll_pitch_yaw(rotation: &na::UnitQuaternion<f32>) -> (f32, f32, f32) {
+    let (pitch, roll, yaw) = rotation.euler_angles();
+    (roll, pitch, yaw)
+}
+
+/// Build a view matrix of a camera in the body frame.
+///
+/// The resulting matrix transforms points to the OpenGL camera frame.
+///
+/// # Arguments
+///
+/// * `pos` - position of the camera.
+/// * `rot` - orientation of the camera.
+pub fn view_matrix(pos: na::Point3<f32>, rot: na::UnitQuaternion<f32>) -> na::Matrix4<f32> {
+    let to_camera = Frame::Body.change_of_basis(Frame::OpenGl) * rot.inverse();
+    na::Isometry3::from_parts(na::Translation3::from(to_camera * -pos.coords), to_camera)
+        .to_homogeneous()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: [Frame; 5] = [
+        Frame::Body,
+        Frame::OpenGl,
+        Frame::OpenCv,
+        Frame::Blender,
+        Frame::Enu,
+    ];
+
+    #[test]
+    fn camera_axes() {
+        let forward = na::Vector3::<f32>::y();
+
+        let gl = Frame::Body.convert_translation(Frame::OpenGl, forward);
+        assert!((gl - -na::Vector3::z()).norm() < 1e-6);
+
+        let cv = Frame::Body.convert_translation(Frame::OpenCv, forward);
+        assert!((cv - na::Vector3::z()).norm() < 1e-6);
+
+        // Right axis is shared, while up is opposite between the camera frames.
+        let up = na::Vector3::<f32>::z();
+        let right = na::Vector3::<f32>::x();
+        assert!(
+            (Frame::OpenGl.convert_translation(Frame::Body, na::Vector3::y()) - up).norm() < 1e-6
+        );
+        assert!(
+            (Frame::OpenCv.convert_translation(Frame::Body, na::Vector3::y()) + up).norm() < 1e-6
+        );
+        assert!((Frame::OpenCv.convert_translation(Frame::Body, right) - right).norm() < 1e-6);
+    }
+
+    #[test]
+    fn conversion_roundtrip() {
+        let rot = from_roll_pitch_yaw(0.1, -0.2, 0.3);
+        let tr = na::Vector3::new(1.0f32, 2.0, 3.0);
+
+        for a in FRAMES {
+            for b in FRAMES {
+                let r = a.convert_rotation(b, rot);
+                assert!(b.convert_rotation(a, r).angle_to(&rot) < 1e-5);
+                assert!((r.angle() - rot.angle()).abs() < 1e-5);
+
+                let t = a.convert_translation(b, tr);
+                assert!((b.convert_translation(a, t) - tr).norm() < 1e-5);
+                assert!((t.norm() - tr.norm()).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_axes() {
+        let angle = 0.25;
+
+        // Yaw to the left is rotation around the down axis in OpenCV.
+        let yaw = Frame::Body.convert_rotation(Frame::OpenCv, from_roll_pitch_yaw(0.0, 0.0, angle));
+        let expected = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), -angle);
+        assert!(yaw.angle_to(&expected) < 1e-6);
+
+        // Roll is rotation around the viewing axis.
+        let roll =
+            Frame::Body.convert_rotation(Frame::OpenGl, from_roll_pitch_yaw(angle, 0.0, 0.0));
+        let expected = na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), -angle);
+        assert!(roll.angle_to(&expected) < 1e-6);
+
+        let (r, p, y) = roll_pitch_yaw(&from_roll_pitch_yaw(0.1, -0.2, 0.3));
+        assert!((r - 0.1).abs() < 1e-6);
+        assert!((p + 0.2).abs() < 1e-6);
+        assert!((y - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn camera_view() {
+        let pos = na::Point3::new(1.0, -2.0, 0.5);
+        let rot = from_roll_pitch_yaw(0.1, 0.2, -0.3);
+
+        let expected = na::Matrix4::look_at_rh(
+            &pos,
+            &(pos + rot * na::Vector3::y()),
+            &(rot * na::Vector3::z()),
+        );
+
+        assert!((view_matrix(pos, rot) - expected).norm() < 1e-5);
+    }
+
+    #[test]
+    fn opencv_relative_pose() {
+        let rot = from_roll_pitch_yaw(0.1, 0.2, -0.3);
+
+        // Rebuild the rotation OpenCV reports for two views of the same points. It maps points
+        // from the first camera's frame to the second one's.
+        let to_cv = |rot, p: &na::Point3<f32>| {
+            let p = view_matrix(Default::default(), rot).transform_point(p);
+            Frame::OpenGl.convert_translation(Frame::OpenCv, p.coords)
+        };
+        let points = [
+            na::Point3::new(1.0, 5.0, 0.0),
+            na::Point3::new(0.0, 4.0, 1.0),
+            na::Point3::new(-1.0, 6.0, -1.0),
+        ];
+        let p1 = na::Matrix3::from_columns(&points.map(|p| to_cv(Default::default(), &p)));
+        let p2 = na::Matrix3::from_columns(&points.map(|p| to_cv(rot, &p)));
+        let r = p2 * p1.try_inverse().unwrap();
+
+        // `cv::Mat` stores the matrix row by row.
+        let data = r.transpose().iter().copied().collect::<Vec<_>>();
+
+        let read = na::Matrix3::from_iterator(data.iter().copied()).transpose();
+        assert!((read - r).norm() < 1e-5);
+        let camera = na::UnitQuaternion::from_matrix(&read).inverse();
+        assert!(
+            Frame::OpenCv
+                .convert_rotation(Frame::Body, camera)
+                .angle_to(&rot)
+                < 1e-5
+        );
+
+        // Reading the data in column-major order flips the rotation.
+        let read = na::Matrix3::from_iterator(data.iter().copied());
+        let camera = na::UnitQuaternion::from_matrix(&read).inverse();
+        let flipped = Frame::OpenCv.convert_rotation(Frame::Body, camera);
+        assert!(flipped.angle_to(&rot.inverse()) < 1e-5);
+    }
+}
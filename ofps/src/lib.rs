@@ -15,7 +15,11 @@ pub mod camera;
 pub mod decoder;
 pub mod detection;
 pub mod estimator;
+pub mod evaluation;
+pub mod frames;
 pub mod motion_field;
+pub mod motion_vectors;
+pub mod npy;
 #[cfg(feature = "plugins")]
 pub mod plugins;
 pub mod utils;
@@ -24,10 +28,12 @@ pub mod prelude {
     pub mod v1 {
         pub use crate::{
             camera::*,
-            decoder::{Decoder, MotionEntry, MotionVectors, RGBA},
+            decoder::{Decoder, FrameInfo, MotionEntry, MotionVectors, PictureType, RGBA},
             detection::Detector,
             estimator::Estimator,
-            motion_field::{MotionField, MotionFieldDensifier},
+            motion_field::{DensifyKernel, MotionField, MotionFieldDensifier, SparseMotionField},
+            motion_vectors::{MotionVector, VectorSource},
+            utils::Real,
         };
         #[cfg(feature = "plugins")]
         pub use crate::{
@@ -0,0 +1,167 @@
+//! # Middlebury `.flo` files
+//!
+//! `.flo` files store a dense optical flow field, and are used by most optical flow datasets,
+//! such as Middlebury and Sintel. The file starts with a header:
+//!
+//! | Type      | Field  | Description                      |
+//! |-----------|--------|----------------------------------|
+//! | `[u8; 4]` | magic  | `PIEH` (`202021.25` as a float)  |
+//! | `i32`     | width  | width of the field               |
+//! | `i32`     | height | height of the field              |
+//!
+//! Then, `width * height` pairs of `f32` values (`motion.x`, `motion.y`) follow in row major
+//! order. All values are little endian. Components larger than [`UNKNOWN_FLOW_THRESHOLD`] mark
+//! cells with unknown flow.
+//!
+//! Motion is stored as is - no unit conversion is performed when reading or writing.
+
+use super::*;
+use std::io::{Read, Write};
+
+/// Magic bytes at the start of `.flo` files.
+pub const FLO_MAGIC: [u8; 4] = *b"PIEH";
+
+/// Flow components above this magnitude are considered unknown.
+pub const UNKNOWN_FLOW_THRESHOLD: f32 = 1e9;
+
+/// Value written to both components of cells that have no data.
+pub const UNKNOWN_FLOW: f32 = 1e10;
+
+/// Maximum number of cells accepted in a `.flo` file.
+///
+/// This guards against allocating absurd amounts of memory when reading corrupt files.
+pub const MAX_FLO_CELLS: usize = 1 << 28;
+
+impl MotionField {
+    /// Read the dimensions of a `.flo` file.
+    ///
+    /// Returns `(width, height)`, and leaves `input` positioned at the start of the flow data.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the file.
+    pub fn read_flo_header(mut input: impl Read) -> Result<(usize, usize)> {
+        let mut buf = [0u8; 12];
+        input.read_exact(&mut buf)?;
+
+        if buf[..4] != FLO_MAGIC {
+            return Err(anyhow::anyhow!("invalid flo magic {:?}", &buf[..4]));
+        }
+
+        let width = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let height = i32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+
+        match (usize::try_from(width), usize::try_from(height)) {
+            (Ok(w), Ok(h)) if w.saturating_mul(h) <= MAX_FLO_CELLS => Ok((w, h)),
+            _ => Err(anyhow::anyhow!("invalid flo dimensions {width}x{height}")),
+        }
+    }
+
+    /// Read a motion field from a `.flo` file.
+    ///
+    /// Cells with unknown flow are marked as invalid, while all others get the weight of `1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the file.
+    pub fn read_flo(mut input: impl Read) -> Result<Self> {
+        let (width, height) = Self::read_flo_header(&mut input)?;
+
+        let mut data = vec![0u8; width * height * 8];
+        input.read_exact(&mut data)?;
+
+        let mut mf = Self::new(width, height);
+
+        for (i, cell) in data.chunks_exact(8).enumerate() {
+            let x = f32::from_le_bytes([cell[0], cell[1], cell[2], cell[3]]);
+            let y = f32::from_le_bytes([cell[4], cell[5], cell[6], cell[7]]);
+
+            let known = |v: f32| v.abs() <= UNKNOWN_FLOW_THRESHOLD;
+
+            if known(x) && known(y) {
+                mf.vf.set_column(i, &Vector2::new(x, y));
+            } else {
+                mf.weights[i] = 0.0;
+            }
+        }
+
+        Ok(mf)
+    }
+
+    /// Write the motion field to a `.flo` file.
+    ///
+    /// Invalid cells are written as [`UNKNOWN_FLOW`].
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - writer to output the file to.
+    pub fn write_flo(&self, mut out: impl Write) -> Result<()> {
+        let (width, height) = self.dim();
+
+        let mut data = Vec::with_capacity(12 + self.size() * 8);
+        data.extend_from_slice(&FLO_MAGIC);
+        data.extend_from_slice(&(width as i32).to_le_bytes());
+        data.extend_from_slice(&(height as i32).to_le_bytes());
+
+        for (motion, &weight) in self.vf.column_iter().zip(&self.weights) {
+            let motion = if weight > 0.0 {
+                motion.into_owned()
+            } else {
+                Vector2::repeat(UNKNOWN_FLOW)
+            };
+            data.extend_from_slice(&motion.x.to_le_bytes());
+            data.extend_from_slice(&motion.y.to_le_bytes());
+        }
+
+        out.write_all(&data)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flo_roundtrip() -> Result<()> {
+        let mut mf = MotionField::new(5, 3);
+        mf.set_motion(1, 2, Vector2::new(-3.5, 12.25));
+        mf.set_motion_weighted(4, 0, Vector2::new(1.0, 1.0), 0.0);
+
+        let mut buf = vec![];
+        mf.write_flo(&mut buf)?;
+        assert_eq!(buf.len(), 12 + 15 * 8);
+        assert_eq!(
+            f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            202021.25
+        );
+
+        assert_eq!(MotionField::read_flo_header(&buf[..])?, (5, 3));
+
+        let read = MotionField::read_flo(&buf[..])?;
+        assert_eq!(read.dim(), (5, 3));
+        assert_eq!(read.get_motion(1, 2), Vector2::new(-3.5, 12.25));
+        assert_eq!(read.valid_count(), 14);
+        assert!(!read.is_valid(4, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn flo_invalid() {
+        let mut buf = vec![];
+        MotionField::new(4, 4).write_flo(&mut buf).unwrap();
+
+        // Truncated data.
+        assert!(MotionField::read_flo(&buf[..buf.len() - 1]).is_err());
+
+        // Negative dimensions.
+        let mut negative = buf.clone();
+        negative[4..8].copy_from_slice(&(-4i32).to_le_bytes());
+        assert!(MotionField::read_flo(&negative[..]).is_err());
+
+        buf[0] = b'X';
+        assert!(MotionField::read_flo(&buf[..]).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! # Densification kernels
+//!
+//! Kernels describe how [`MotionFieldDensifier`](super::MotionFieldDensifier) spreads each motion
+//! vector across the cells of the field. All kernels are separable, thus they are evaluated along
+//! each axis independently.
+//!
+//! Positions are mapped to cells the same way as everywhere else in the motion field - cell `i` of
+//! an axis with `len` cells is centred at `(i + 0.5) / len`, see [`ops`](super::ops).
+
+/// Accumulation kernel of a motion field densifier.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DensifyKernel {
+    /// Add each vector to the nearest cell.
+    #[default]
+    Nearest,
+    /// Split each vector across the 4 surrounding cells.
+    Bilinear,
+    /// Spread each vector across all cells within given radius, weighted by a gaussian with
+    /// standard deviation of half the radius.
+    Gaussian {
+        /// Radius of the kernel, in cells.
+        radius: f32,
+    },
+    /// Spread each vector across all cells its footprint overlaps, weighted by the overlapping
+    /// area. Vectors without a footprint are added to the nearest cell.
+    Footprint,
+}
+
+impl DensifyKernel {
+    /// Names of all kernels, ordered by their mode index.
+    pub const NAMES: [&'static str; 4] = ["nearest", "bilinear", "gaussian", "footprint"];
+
+    /// Create a kernel from its mode index.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - index of the kernel in [`NAMES`](Self::NAMES).
+    /// * `radius` - radius of the gaussian kernel, in cells. Ignored by other kernels.
+    pub fn from_mode(mode: usize, radius: f32) -> Option<Self> {
+        match mode {
+            0 => Some(Self::Nearest),
+            1 => Some(Self::Bilinear),
+            2 => Some(Self::Gaussian { radius }),
+            3 => Some(Self::Footprint),
+            _ => None,
+        }
+    }
+
+    /// Create a kernel from its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the kernel, as listed in [`NAMES`](Self::NAMES). Case insensitive.
+    /// * `radius` - radius of the gaussian kernel, in cells. Ignored by other kernels.
+    pub fn from_name(name: &str, radius: f32) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(name))
+            .and_then(|mode| Self::from_mode(mode, radius))
+    }
+
+    /// Compute kernel weights along a single axis.
+    ///
+    /// Returned weights are not normalised. The result is never empty.
+    ///
+    /// On wrapping axes, kernels crossing one end of the axis continue on the other. Otherwise,
+    /// kernels are clipped at the ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - position along the axis, in 0-1 range.
+    /// * `extent` - footprint of the vector along the axis, in 0-1 range.
+    /// * `len` - number of cells along the axis.
+    /// * `wrap` - whether the axis wraps around.
+    /// * `taps` - output cell indices and their weights.
+    pub(super) fn axis_taps(
+        &self,
+        pos: f32,
+        extent: f32,
+        len: usize,
+        wrap: bool,
+        taps: &mut Vec<(usize, f32)>,
+    ) {
+        taps.clear();
+
+        if len == 0 {
+            return;
+        }
+
+        let scale = len as f32;
+        let pos = if wrap {
+            pos.rem_euclid(1.0)
+        } else {
+            pos.clamp(0.0, 1.0)
+        };
+        let nearest = nearest_cell(pos, len, wrap);
+        let c = pos * scale - 0.5;
+        let len = len as isize;
+
+        let mut push_range = |start: f32, end: f32, f: &dyn Fn(f32) -> f32| {
+            let (start, end) = (start as isize, end as isize);
+            let (start, end) = if wrap {
+                (start, end)
+            } else {
+                (start.max(0), end.min(len - 1))
+            };
+            for i in start..=end {
+                let k = f(i as f32);
+                if k > 0.0 {
+                    taps.push((i.rem_euclid(len) as usize, k));
+                }
+            }
+        };
+
+        match *self {
+            Self::Nearest => {}
+            Self::Bilinear => {
+                let x0 = c.floor();
+                push_range(x0, x0 + 1.0, &|i| 1.0 - (i - c).abs());
+            }
+            Self::Gaussian { radius } if radius > 0.0 => {
+                let sigma = radius * 0.5;
+                push_range((c - radius).ceil(), (c + radius).floor(), &|i| {
+                    (-(i - c).powi(2) / (2.0 * sigma * sigma)).exp()
+                });
+            }
+            Self::Gaussian { .. } => {}
+            Self::Footprint => {
+                let half = extent * scale * 0.5;
+                if half > 0.0 {
+                    let (a, b) = (c - half, c + half);
+                    push_range(a.round(), b.round(), &|i| b.min(i + 0.5) - a.max(i - 0.5));
+                }
+            }
+        }
+
+        // Fall back to the nearest cell if the kernel did not hit any cells.
+        if taps.is_empty() {
+            taps.push((nearest, 1.0));
+        }
+    }
+}
+
+/// Get the cell containing a position along an axis.
+///
+/// Positions outside the axis are clamped to the border cells, unless the axis wraps around.
+///
+/// # Arguments
+///
+/// * `pos` - position along the axis, in 0-1 range.
+/// * `len` - number of cells along the axis. Must be non-zero.
+/// * `wrap` - whether the axis wraps around.
+pub(super) fn nearest_cell(pos: f32, len: usize, wrap: bool) -> usize {
+    let cell = (pos * len as f32).floor();
+
+    if wrap {
+        (cell as isize).rem_euclid(len as isize) as usize
+    } else {
+        cell.clamp(0.0, (len - 1) as f32) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taps(kernel: DensifyKernel, pos: f32, extent: f32, len: usize) -> Vec<(usize, f32)> {
+        let mut taps = vec![];
+        kernel.axis_taps(pos, extent, len, false, &mut taps);
+        taps
+    }
+
+    #[test]
+    fn axis_taps() {
+        assert_eq!(taps(DensifyKernel::Nearest, 0.3, 0.0, 11), [(3, 1.0)]);
+
+        // Cell centres lie at `(i + 0.5) / len`.
+        let bilinear = taps(DensifyKernel::Bilinear, 0.35, 0.0, 11);
+        assert_eq!(bilinear.len(), 2);
+        assert_eq!(bilinear[0].0, 3);
+        assert!((bilinear[0].1 - 0.65).abs() < 1e-5);
+        assert_eq!(taps(DensifyKernel::Bilinear, 1.0, 0.0, 11), [(10, 0.5)]);
+
+        let gauss = taps(DensifyKernel::Gaussian { radius: 2.0 }, 0.5, 0.0, 11);
+        assert_eq!(
+            gauss.iter().map(|t| t.0).collect::<Vec<_>>(),
+            [3, 4, 5, 6, 7]
+        );
+        assert_eq!(gauss[2].1, 1.0);
+        assert_eq!(gauss[0].1, gauss[4].1);
+
+        // Block covering 3 cells, centred between two of them.
+        let fp = taps(DensifyKernel::Footprint, 5.0 / 11.0, 3.0 / 11.0, 11);
+        assert_eq!(fp.iter().map(|t| t.0).collect::<Vec<_>>(), [3, 4, 5, 6]);
+        assert!((fp.iter().map(|t| t.1).sum::<f32>() - 3.0).abs() < 1e-5);
+        assert!((fp[0].1 - 0.5).abs() < 1e-5);
+
+        // Footprints are clipped at the borders, and missing ones act like nearest.
+        let fp = taps(DensifyKernel::Footprint, 0.0, 0.2, 11);
+        assert_eq!(fp.iter().map(|t| t.0).collect::<Vec<_>>(), [0, 1]);
+        assert!((fp[0].1 - 1.0).abs() < 1e-5);
+        assert!((fp[1].1 - 0.1).abs() < 1e-5);
+        assert_eq!(taps(DensifyKernel::Footprint, 0.0, 0.0, 11), [(0, 1.0)]);
+    }
+
+    #[test]
+    fn names() {
+        for (mode, name) in DensifyKernel::NAMES.iter().enumerate() {
+            assert_eq!(
+                DensifyKernel::from_name(name, 1.0),
+                DensifyKernel::from_mode(mode, 1.0)
+            );
+        }
+        assert_eq!(
+            DensifyKernel::from_name("Gaussian", 3.0),
+            Some(DensifyKernel::Gaussian { radius: 3.0 })
+        );
+        assert_eq!(DensifyKernel::from_name("cubic", 1.0), None);
+        assert_eq!(DensifyKernel::from_mode(4, 1.0), None);
+    }
+
+    #[test]
+    fn axis_taps_wrap() {
+        let mut taps = vec![];
+        let cells = |taps: &[(usize, f32)]| taps.iter().map(|t| t.0).collect::<Vec<_>>();
+
+        // Position lies between the last and the first cell.
+        DensifyKernel::Bilinear.axis_taps(0.0, 0.0, 10, true, &mut taps);
+        assert_eq!(cells(&taps), [9, 0]);
+        assert!((taps[0].1 - 0.5).abs() < 1e-5);
+
+        DensifyKernel::Footprint.axis_taps(0.05, 0.25, 10, true, &mut taps);
+        assert_eq!(cells(&taps), [9, 0, 1]);
+        assert!((taps.iter().map(|t| t.1).sum::<f32>() - 2.5).abs() < 1e-5);
+
+        DensifyKernel::Nearest.axis_taps(0.97, 0.0, 10, true, &mut taps);
+        assert_eq!(taps, [(9, 1.0)]);
+        DensifyKernel::Nearest.axis_taps(1.0, 0.0, 10, true, &mut taps);
+        assert_eq!(taps, [(0, 1.0)]);
+    }
+}
@@ -0,0 +1,176 @@
+//! # KITTI optical flow files
+//!
+//! KITTI stores flow fields as 16-bit RGB PNG images. Red and green channels hold horizontal and
+//! vertical motion, encoded as `motion * KITTI_SCALE + KITTI_OFFSET`, while the blue channel is
+//! `1` for cells with valid flow, and `0` otherwise.
+//!
+//! Motion is stored as is - no unit conversion is performed when reading or writing. Motion
+//! outside of the representable range is clamped upon writing.
+
+use super::*;
+use png::{BitDepth, ColorType, Decoder, Encoder};
+use std::io::{Read, Write};
+
+/// Number of encoded steps per unit of motion.
+pub const KITTI_SCALE: f32 = 64.0;
+
+/// Encoded value of zero motion.
+pub const KITTI_OFFSET: f32 = 32768.0;
+
+/// Maximum number of cells accepted in a KITTI flow image.
+///
+/// This guards against allocating absurd amounts of memory when reading corrupt files.
+pub const MAX_KITTI_CELLS: usize = 1 << 28;
+
+impl MotionField {
+    /// Read the dimensions of a KITTI flow image.
+    ///
+    /// Returns `(width, height)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the image.
+    pub fn read_kitti_png_header(input: impl Read) -> Result<(usize, usize)> {
+        let reader = Decoder::new(input).read_info()?;
+        let info = reader.info();
+        Ok((info.width as usize, info.height as usize))
+    }
+
+    /// Read a motion field from a KITTI flow image.
+    ///
+    /// Cells without the validity bit are marked as invalid, while all others get the weight of
+    /// `1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - reader positioned at the start of the image.
+    pub fn read_kitti_png(input: impl Read) -> Result<Self> {
+        let mut reader = Decoder::new(input).read_info()?;
+
+        let (width, height, color, depth) = {
+            let info = reader.info();
+            (
+                info.width as usize,
+                info.height as usize,
+                info.color_type,
+                info.bit_depth,
+            )
+        };
+
+        if (color, depth) != (ColorType::Rgb, BitDepth::Sixteen) {
+            return Err(anyhow::anyhow!(
+                "KITTI flow must be 16-bit RGB, got {depth:?} bit {color:?}"
+            ));
+        }
+
+        if width.saturating_mul(height) > MAX_KITTI_CELLS {
+            return Err(anyhow::anyhow!(
+                "invalid KITTI flow dimensions {width}x{height}"
+            ));
+        }
+
+        let mut data = vec![0u8; reader.output_buffer_size()];
+        reader.next_frame(&mut data)?;
+
+        let mut mf = Self::new(width, height);
+
+        // PNG stores samples in big endian.
+        let decode =
+            |b: &[u8]| (u16::from_be_bytes([b[0], b[1]]) as f32 - KITTI_OFFSET) / KITTI_SCALE;
+
+        for (i, cell) in data.chunks_exact(6).take(mf.size()).enumerate() {
+            if cell[4] != 0 || cell[5] != 0 {
+                mf.vf
+                    .set_column(i, &Vector2::new(decode(&cell[0..2]), decode(&cell[2..4])));
+            } else {
+                mf.weights[i] = 0.0;
+            }
+        }
+
+        Ok(mf)
+    }
+
+    /// Write the motion field to a KITTI flow image.
+    ///
+    /// Invalid cells are written with zero motion and the validity bit cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - writer to output the image to.
+    pub fn write_kitti_png(&self, out: impl Write) -> Result<()> {
+        let (width, height) = self.dim();
+
+        let mut encoder = Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Sixteen);
+        let mut writer = encoder.write_header()?;
+
+        let encode = |v: f32| {
+            (v * KITTI_SCALE + KITTI_OFFSET)
+                .round()
+                .clamp(0.0, u16::MAX as f32) as u16
+        };
+
+        let mut data = Vec::with_capacity(self.size() * 6);
+
+        for (motion, &weight) in self.vf.column_iter().zip(&self.weights) {
+            let (x, y, valid) = if weight > 0.0 {
+                (encode(motion.x), encode(motion.y), 1u16)
+            } else {
+                (encode(0.0), encode(0.0), 0)
+            };
+            data.extend_from_slice(&x.to_be_bytes());
+            data.extend_from_slice(&y.to_be_bytes());
+            data.extend_from_slice(&valid.to_be_bytes());
+        }
+
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kitti_roundtrip() -> Result<()> {
+        let mut mf = MotionField::new(6, 4);
+        mf.set_motion(2, 1, Vector2::new(-3.5, 12.25));
+        mf.set_motion(5, 3, Vector2::new(1000.0, -1000.0));
+        mf.set_motion_weighted(0, 3, Vector2::new(1.0, 1.0), 0.0);
+
+        let mut buf = vec![];
+        mf.write_kitti_png(&mut buf)?;
+
+        assert_eq!(MotionField::read_kitti_png_header(&buf[..])?, (6, 4));
+
+        let read = MotionField::read_kitti_png(&buf[..])?;
+        assert_eq!(read.dim(), (6, 4));
+        assert_eq!(read.get_motion(2, 1), Vector2::new(-3.5, 12.25));
+        assert_eq!(read.valid_count(), 23);
+        assert!(!read.is_valid(0, 3));
+
+        // Out of range motion is clamped.
+        let clamped = read.get_motion(5, 3);
+        assert!((clamped.x - 512.0).abs() < 0.1 && clamped.y == -512.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn kitti_invalid() {
+        let mut buf = vec![];
+        let mut encoder = Encoder::new(&mut buf, 2, 2);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0; 12]).unwrap();
+        writer.finish().unwrap();
+
+        assert!(MotionField::read_kitti_png(&buf[..]).is_err());
+        assert!(MotionField::read_kitti_png(&b"not a png"[..]).is_err());
+    }
+}
@@ -0,0 +1,763 @@
+//! # Fixed size motion field
+//!
+//! Each cell of a motion field has a weight attached to it. Weight of `0` means the cell has no
+//! data, while positive weights tell how much data the cell's motion was computed from.
+
+use crate::camera::CameraModel;
+use crate::motion_vectors::{MotionEntry, MotionVectors};
+use crate::utils::{self, Real};
+use anyhow::Result;
+use nalgebra::*;
+
+pub mod flo;
+mod kernel;
+#[cfg(feature = "kitti")]
+pub mod kitti;
+mod ops;
+mod sparse;
+
+pub use kernel::DensifyKernel;
+pub use sparse::SparseMotionField;
+
+/// Fixed size optical flow motion field.
+///
+/// Motion and weights are stored as `T`, which is `f32` by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MotionField<T: Real = f32> {
+    vf: Matrix2xX<T>,
+    weights: Vec<T>,
+    width: usize,
+}
+
+impl MotionField {
+    /// Create a new motion field.
+    ///
+    /// All cells are valid, with zero motion and weight of `1`. Use
+    /// [`zeros`](MotionField::zeros) for other scalar types.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the field.
+    /// * `height` - height of the field.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::zeros(width, height)
+    }
+
+    /// Create a new motion field with no valid cells.
+    ///
+    /// Use [`empty`](MotionField::empty) for other scalar types.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the field.
+    /// * `height` - height of the field.
+    pub fn new_empty(width: usize, height: usize) -> Self {
+        Self::empty(width, height)
+    }
+}
+
+impl<T: Real> MotionField<T> {
+    /// Create a new motion field of any scalar type.
+    ///
+    /// All cells are valid, with zero motion and weight of `1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the field.
+    /// * `height` - height of the field.
+    pub fn zeros(width: usize, height: usize) -> Self {
+        Self {
+            vf: Matrix2xX::repeat(width * height, T::zero()),
+            weights: vec![T::one(); width * height],
+            width,
+        }
+    }
+
+    /// Create a new motion field of any scalar type with no valid cells.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the field.
+    /// * `height` - height of the field.
+    pub fn empty(width: usize, height: usize) -> Self {
+        Self {
+            weights: vec![T::zero(); width * height],
+            ..Self::zeros(width, height)
+        }
+    }
+
+    /// Get width and height of the motion field.
+    pub fn dim(&self) -> (usize, usize) {
+        if self.width == 0 {
+            (0, 0)
+        } else {
+            (self.width, self.vf.ncols() / self.width)
+        }
+    }
+
+    /// Get size of the motion field.
+    ///
+    /// This is the same as `width * height`
+    pub fn size(&self) -> usize {
+        self.vf.ncols()
+    }
+
+    /// Get the motion field in row-major order.
+    ///
+    /// The elements returned are in the following order:
+    ///
+    /// `field[0,0].x, field[0,0].y, field[0,1].x, ... field[0,N].y, field[1,0].x, ... field[N,N].y`
+    pub fn as_slice(&self) -> &[T] {
+        self.vf.as_slice()
+    }
+
+    /// Get weights of all cells in row-major order.
+    pub fn weights(&self) -> &[T] {
+        &self.weights
+    }
+
+    /// Set motion at given position.
+    ///
+    /// The cell is marked valid, with weight of `1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate to set at.
+    /// * `y` - vertical coordinate to set at.
+    /// * `motion` - motion to set.
+    pub fn set_motion(&mut self, x: usize, y: usize, motion: Vector2<T>) {
+        self.set_motion_weighted(x, y, motion, T::one());
+    }
+
+    /// Set motion and weight at given position.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate to set at.
+    /// * `y` - vertical coordinate to set at.
+    /// * `motion` - motion to set.
+    /// * `weight` - weight of the cell. `0` marks the cell as invalid.
+    pub fn set_motion_weighted(&mut self, x: usize, y: usize, motion: Vector2<T>, weight: T) {
+        let idx = self.width * y + x;
+        self.vf.set_column(idx, &motion);
+        self.weights[idx] = weight;
+    }
+
+    /// Divide accumulated motion by cell weights.
+    fn normalize(&mut self) {
+        for (mut motion, &weight) in self.vf.column_iter_mut().zip(self.weights.iter()) {
+            if weight > T::zero() {
+                motion /= weight;
+            } else {
+                motion.fill(T::zero());
+            }
+        }
+    }
+
+    /// Get motion at coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate.
+    /// * `y` - vertical coordinate.
+    pub fn get_motion(&self, x: usize, y: usize) -> Vector2<T> {
+        self.vf.column(self.width * y + x).into()
+    }
+
+    /// Get weight at coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate.
+    /// * `y` - vertical coordinate.
+    pub fn get_weight(&self, x: usize, y: usize) -> T {
+        self.weights[self.width * y + x]
+    }
+
+    /// Check whether the cell at coordinates has data.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate.
+    /// * `y` - vertical coordinate.
+    pub fn is_valid(&self, x: usize, y: usize) -> bool {
+        self.get_weight(x, y) > T::zero()
+    }
+
+    /// Get the number of cells with data.
+    pub fn valid_count(&self) -> usize {
+        self.weights.iter().filter(|&&w| w > T::zero()).count()
+    }
+
+    /// Iterate every element of the motion field.
+    ///
+    /// The resulting iterator yields `(x, y, motion)` entries.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, Vector2<T>)> + '_ {
+        let (width, height) = self.dim();
+        (0..height).into_iter().flat_map(move |y| {
+            (0..width)
+                .into_iter()
+                .map(move |x| (x, y, self.get_motion(x, y)))
+        })
+    }
+
+    /// Iterate every valid element of the motion field.
+    ///
+    /// The resulting iterator yields `(x, y, motion)` entries.
+    pub fn iter_valid(&self) -> impl Iterator<Item = (usize, usize, Vector2<T>)> + '_ {
+        self.iter().filter(move |&(x, y, _)| self.is_valid(x, y))
+    }
+
+    /// Iterate every element of the motion field along with its weight.
+    ///
+    /// The resulting iterator yields `(x, y, motion, weight)` entries.
+    pub fn iter_weighted(&self) -> impl Iterator<Item = (usize, usize, Vector2<T>, T)> + '_ {
+        self.iter()
+            .map(move |(x, y, motion)| (x, y, motion, self.get_weight(x, y)))
+    }
+
+    /// Iterate every element of the motion field.
+    ///
+    /// The resulting iterator yields `MotionEntry` elements.
+    pub fn motion_iter(&self) -> impl Iterator<Item = MotionEntry<T>> + '_ {
+        self.iter()
+            .map(move |(x, y, motion)| (self.cell_pos(x, y), motion))
+    }
+
+    /// Iterate every valid element of the motion field.
+    ///
+    /// The resulting iterator yields `MotionEntry` elements.
+    pub fn motion_iter_valid(&self) -> impl Iterator<Item = MotionEntry<T>> + '_ {
+        self.iter_valid()
+            .map(move |(x, y, motion)| (self.cell_pos(x, y), motion))
+    }
+
+    /// Get position of the centre of a cell, as used by `motion_iter`.
+    fn cell_pos(&self, x: usize, y: usize) -> Point2<T> {
+        let (width, height) = self.dim();
+        Point2::new(
+            convert((x as f64 + 0.5) / width as f64),
+            convert((y as f64 + 0.5) / height as f64),
+        )
+    }
+
+    /// Convert the motion field to a different scalar type.
+    pub fn cast<U: Real>(&self) -> MotionField<U> {
+        MotionField {
+            vf: self.vf.map(utils::cast),
+            weights: self.weights.iter().copied().map(utils::cast).collect(),
+            width: self.width,
+        }
+    }
+}
+
+impl MotionField {
+    /// Create a new densification structure.
+    ///
+    /// `MotionFieldDensifier` takes arbitrary amount of motion vectors and densifies them to a
+    /// fixed size motion field.
+    pub fn new_densifier(&self) -> MotionFieldDensifier {
+        let (w, h) = self.dim();
+        MotionFieldDensifier::new(w, h)
+    }
+
+    /// Finalise the motion field densifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `densifier` - motion field densifier.
+    pub fn from_densifier(&mut self, densifier: &MotionFieldDensifier) {
+        assert_eq!(densifier.mf.dim(), self.dim());
+        self.vf.copy_from(&densifier.mf.vf);
+        self.weights.copy_from_slice(&densifier.counts);
+        self.normalize();
+    }
+}
+
+/// Densify arbitrary number of motion vectors.
+///
+/// This structure provides facilities to add an arbitrary amount of motion vectors together and
+/// then convert them to a fixed size motion field.
+#[derive(Clone, Debug)]
+pub struct MotionFieldDensifier {
+    mf: MotionField,
+    counts: Vec<f32>,
+    kernel: DensifyKernel,
+    taps_x: Vec<(usize, f32)>,
+    taps_y: Vec<(usize, f32)>,
+    wrap: bool,
+}
+
+impl MotionFieldDensifier {
+    /// Create a new densifier
+    ///
+    /// The densifier uses [`DensifyKernel::Nearest`] by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the output motion field.
+    /// * `heighy` - height of the output motion field.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            mf: MotionField::new(width, height),
+            counts: vec![0.0; width * height],
+            kernel: DensifyKernel::default(),
+            taps_x: vec![],
+            taps_y: vec![],
+            wrap: false,
+        }
+    }
+
+    /// Set the accumulation kernel of the densifier.
+    pub fn kernel(self, kernel: DensifyKernel) -> Self {
+        Self { kernel, ..self }
+    }
+
+    /// Set whether the field wraps around horizontally.
+    ///
+    /// This is the case with 360° footage, see
+    /// [`CameraModel::wraps_horizontally`](crate::camera::CameraModel::wraps_horizontally).
+    /// Vectors near one side of a wrapping field also contribute to the other, and interpolation
+    /// of empty cells wraps around as well.
+    pub fn wrap_horizontally(self, wrap: bool) -> Self {
+        Self { wrap, ..self }
+    }
+
+    /// Set up the densifier for the given camera.
+    ///
+    /// The field wraps around horizontally if the camera's screen does.
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - camera the motion vectors were captured with.
+    pub fn camera(self, camera: &dyn CameraModel) -> Self {
+        self.wrap_horizontally(camera.wraps_horizontally())
+    }
+
+    /// Add a motion vector at specified index.
+    fn add_vector_idx(&mut self, idx: usize, motion: Vector2<f32>, weight: f32) {
+        self.counts[idx] += weight;
+        self.mf
+            .vf
+            .set_column(idx, &(motion * weight + self.mf.vf.column(idx)));
+    }
+
+    /// Add a motion vector at specified motion field coordinates
+    fn add_vector_pos(&mut self, x: usize, y: usize, motion: Vector2<f32>, weight: f32) {
+        let idx = y * self.mf.width + x;
+        self.add_vector_idx(idx, motion, weight);
+    }
+
+    /// Add a motion vector with custom weight.
+    ///
+    /// Returns the cell containing the vector's position.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - starting position of the motion vector, in 0-1 range.
+    /// * `motion` - motion of the vector.
+    /// * `weight` - weight of the motion vector.
+    pub fn add_vector_weighted(
+        &mut self,
+        pos: Point2<f32>,
+        motion: Vector2<f32>,
+        weight: f32,
+    ) -> (usize, usize) {
+        self.add_vector_footprint(pos, motion, Vector2::zeros(), weight)
+    }
+
+    /// Add a motion vector covering an area of the frame.
+    ///
+    /// The footprint is only used by [`DensifyKernel::Footprint`]. Returns the cell containing the
+    /// vector's position.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - starting position of the motion vector, in 0-1 range.
+    /// * `motion` - motion of the vector.
+    /// * `footprint` - width and height of the area described by the vector, in 0-1 range.
+    /// * `weight` - weight of the motion vector.
+    pub fn add_vector_footprint(
+        &mut self,
+        pos: Point2<f32>,
+        motion: Vector2<f32>,
+        footprint: Vector2<f32>,
+        weight: f32,
+    ) -> (usize, usize) {
+        let (w, h) = self.mf.dim();
+        let x = kernel::nearest_cell(pos.x, w, self.wrap);
+        let y = kernel::nearest_cell(pos.y, h, false);
+
+        if self.kernel == DensifyKernel::Nearest {
+            self.add_vector_pos(x, y, motion, weight);
+            return (x, y);
+        }
+
+        let mut taps_x = std::mem::take(&mut self.taps_x);
+        let mut taps_y = std::mem::take(&mut self.taps_y);
+        self.kernel
+            .axis_taps(pos.x, footprint.x, w, self.wrap, &mut taps_x);
+        self.kernel
+            .axis_taps(pos.y, footprint.y, h, false, &mut taps_y);
+
+        // Normalise the kernel so that the total weight of the vector stays unchanged.
+        let sum = |taps: &[(usize, f32)]| taps.iter().map(|t| t.1).sum::<f32>();
+        let norm = 1.0 / (sum(&taps_x) * sum(&taps_y));
+
+        for &(ty, wy) in &taps_y {
+            for &(tx, wx) in &taps_x {
+                self.add_vector_pos(tx, ty, motion, weight * wx * wy * norm);
+            }
+        }
+
+        self.taps_x = taps_x;
+        self.taps_y = taps_y;
+
+        (x, y)
+    }
+
+    /// Add a motion vector to the field.
+    ///
+    /// Returns cell of insertion.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - starting position of the motion vector, in 0-1 range.
+    /// * `motion` - motion of the vector.
+    pub fn add_vector(&mut self, pos: Point2<f32>, motion: Vector2<f32>) -> (usize, usize) {
+        self.add_vector_weighted(pos, motion, 1.0)
+    }
+
+    /// Add a collection of motion vectors to the field.
+    ///
+    /// Each vector is weighted by its confidence and area, see [`MotionVectors::weights`].
+    ///
+    /// # Arguments
+    ///
+    /// * `vectors` - motion vectors to add.
+    pub fn add_vectors(&mut self, vectors: &MotionVectors) {
+        for (((pos, motion), weight), &footprint) in vectors
+            .entries()
+            .zip(vectors.weights())
+            .zip(vectors.footprints())
+        {
+            self.add_vector_footprint(pos, motion, footprint, weight);
+        }
+    }
+
+    /// Calculate empty cells from non-empty neighbors.
+    ///
+    /// Equivalent to [`interpolate_empty_cells_within`](Self::interpolate_empty_cells_within)
+    /// with no distance limit. All cells are filled, unless the field has no data at all.
+    pub fn interpolate_empty_cells(&mut self) -> Result<()> {
+        self.interpolate_empty_cells_within(usize::MAX)
+    }
+
+    /// Calculate empty cells from non-empty neighbors, up to given distance away from data.
+    ///
+    /// Empty cells are filled in order of their distance to the nearest non-empty cell, measured
+    /// in cells along any of the 8 directions. Each cell takes the average motion of its
+    /// neighbors that are closer to data, with diagonal neighbors having a lower weight. Weight of
+    /// a filled cell is half of the average weight of the neighbors it was computed from, thus
+    /// extrapolated data becomes less valid the further away it is from measurements. The weight
+    /// never drops below a small positive value, so far away cells remain valid.
+    ///
+    /// This runs in time linear to the size of the field.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_distance` - maximum distance from data to fill cells at. Cells further away are left
+    ///   empty.
+    pub fn interpolate_empty_cells_within(&mut self, max_distance: usize) -> Result<()> {
+        const NEIGHBORS: [(isize, isize, f32); 8] = [
+            (-1, 0, 1.0),
+            (1, 0, 1.0),
+            (0, -1, 1.0),
+            (0, 1, 1.0),
+            (-1, -1, std::f32::consts::FRAC_1_SQRT_2),
+            (1, -1, std::f32::consts::FRAC_1_SQRT_2),
+            (-1, 1, std::f32::consts::FRAC_1_SQRT_2),
+            (1, 1, std::f32::consts::FRAC_1_SQRT_2),
+        ];
+
+        // Weight decays exponentially with distance, so it has to be kept from underflowing to
+        // zero. This floor also keeps `motion * weight` within the normal float range.
+        const MIN_WEIGHT: f32 = f32::MIN_POSITIVE / f32::EPSILON;
+
+        let (width, height) = self.mf.dim();
+        let wrap = self.wrap;
+
+        let neighbors = move |idx: usize| {
+            let (x, y) = ((idx % width) as isize, (idx / width) as isize);
+            NEIGHBORS.into_iter().filter_map(move |(ox, oy, scale)| {
+                let (mut x, y) = (x + ox, y + oy);
+                if wrap {
+                    x = x.rem_euclid(width as isize);
+                }
+                if x >= 0 && x < width as isize && y >= 0 && y < height as isize {
+                    Some((x as usize + y as usize * width, scale))
+                } else {
+                    None
+                }
+            })
+        };
+
+        // Distance of each cell to the nearest cell with data.
+        let mut dist = self
+            .counts
+            .iter()
+            .map(|&c| if c > 0.0 { 0 } else { usize::MAX })
+            .collect::<Vec<_>>();
+
+        // Average motion of each cell, kept apart from the weights that decay with distance.
+        let mut motions = self
+            .mf
+            .vf
+            .column_iter()
+            .zip(&self.counts)
+            .map(|(m, &c)| if c > 0.0 { m / c } else { Vector2::zeros() })
+            .collect::<Vec<_>>();
+
+        // Only cells next to empty ones need to be in the first frontier.
+        let mut frontier = (0..dist.len())
+            .filter(|&i| dist[i] == 0 && neighbors(i).any(|(n, _)| dist[n] != 0))
+            .collect::<Vec<_>>();
+        let mut next = vec![];
+
+        for d in 1..=max_distance {
+            if frontier.is_empty() {
+                break;
+            }
+
+            // Find all cells at the current distance.
+            for &i in &frontier {
+                for (n, _) in neighbors(i) {
+                    if dist[n] == usize::MAX {
+                        dist[n] = d;
+                        next.push(n);
+                    }
+                }
+            }
+
+            // Fill them in from the cells closer to data. These are never modified in this pass.
+            for &i in &next {
+                let (motion, weight, total) = neighbors(i).filter(|&(n, _)| dist[n] < d).fold(
+                    (Vector2::zeros(), 0.0, 0.0),
+                    |(m, w, t), (n, scale)| {
+                        (
+                            m + motions[n] * scale,
+                            w + self.counts[n] * scale,
+                            t + scale,
+                        )
+                    },
+                );
+
+                let motion = motion / total;
+                let weight = (0.5 * weight / total).max(MIN_WEIGHT);
+                motions[i] = motion;
+                self.mf.vf.set_column(i, &(motion * weight));
+                self.counts[i] = weight;
+            }
+
+            std::mem::swap(&mut frontier, &mut next);
+            next.clear();
+        }
+
+        Ok(())
+    }
+}
+
+impl From<MotionFieldDensifier> for MotionField {
+    fn from(MotionFieldDensifier { mf, counts, .. }: MotionFieldDensifier) -> Self {
+        let mut mf = MotionField {
+            weights: counts,
+            ..mf
+        };
+        mf.normalize();
+        mf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{EquirectangularCamera, StandardCamera};
+    use crate::motion_vectors::MotionVector;
+
+    #[test]
+    fn interpolate_fills_all() {
+        let mut densifier = MotionFieldDensifier::new(16, 9);
+        densifier.add_vector(Point2::new(0.0, 0.0), Vector2::new(1.0, 0.0));
+        densifier.add_vector(Point2::new(1.0, 1.0), Vector2::new(0.0, 1.0));
+        densifier.interpolate_empty_cells().unwrap();
+
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), mf.size());
+        assert_eq!(mf.get_motion(0, 0), Vector2::new(1.0, 0.0));
+        assert_eq!(mf.get_weight(0, 0), 1.0);
+
+        // Cells closer to a vector take its motion, and lose weight with distance.
+        let near = mf.get_motion(1, 1);
+        assert!((near - Vector2::new(1.0, 0.0)).magnitude() < 1e-5);
+        assert_eq!(mf.get_weight(1, 1), 0.5);
+        assert!(mf.get_weight(4, 4) < mf.get_weight(2, 2));
+        let far = mf.get_motion(14, 7);
+        assert!((far - Vector2::new(0.0, 1.0)).magnitude() < 1e-5);
+
+        // Motion stays within the range of the inputs.
+        for (_, _, m) in mf.iter() {
+            assert!(m.x >= -1e-5 && m.y >= -1e-5 && m.x + m.y <= 1.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn interpolate_max_distance() {
+        let mut densifier = MotionFieldDensifier::new(9, 9);
+        densifier.add_vector(Point2::new(0.5, 0.5), Vector2::new(1.0, 1.0));
+        densifier.interpolate_empty_cells_within(2).unwrap();
+
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 25);
+        assert!(mf.is_valid(2, 6));
+        assert!(!mf.is_valid(1, 4));
+
+        // Empty fields stay empty.
+        let mut densifier = MotionFieldDensifier::new(4, 4);
+        densifier.interpolate_empty_cells().unwrap();
+        assert_eq!(MotionField::from(densifier).valid_count(), 0);
+
+        let mut densifier = MotionFieldDensifier::new(0, 0);
+        densifier.interpolate_empty_cells().unwrap();
+    }
+
+    #[test]
+    fn interpolate_long_distance() {
+        // Weight halves every cell, thus it would underflow long before reaching the end.
+        let motion = Vector2::new(0.25, -0.5);
+        let mut densifier = MotionFieldDensifier::new(400, 1);
+        densifier.add_vector(Point2::new(0.0, 0.0), motion);
+        densifier.interpolate_empty_cells().unwrap();
+
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), mf.size());
+        assert!(mf.get_weight(150, 0) <= mf.get_weight(149, 0));
+        for (_, _, m) in mf.iter() {
+            assert!((m - motion).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn densify_kernels() {
+        let pos = Point2::new(0.35, 0.5);
+        let motion = Vector2::new(1.0, -1.0);
+
+        let mut densifier = MotionFieldDensifier::new(11, 11).kernel(DensifyKernel::Bilinear);
+        assert_eq!(densifier.add_vector_weighted(pos, motion, 2.0), (3, 5));
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 2);
+        assert!((mf.get_weight(3, 5) - 1.3).abs() < 1e-5);
+        assert!((mf.get_weight(4, 5) - 0.7).abs() < 1e-5);
+        assert!((mf.get_motion(3, 5) - motion).magnitude() < 1e-5);
+
+        // Total weight of the vector is kept, no matter how far it is spread.
+        let mut densifier =
+            MotionFieldDensifier::new(11, 11).kernel(DensifyKernel::Gaussian { radius: 3.0 });
+        densifier.add_vector_weighted(pos, motion, 2.0);
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 42);
+        assert!((mf.weights().iter().sum::<f32>() - 2.0).abs() < 1e-4);
+
+        // Footprints cover all the cells of the block.
+        let mut vectors = MotionVectors::new();
+        vectors.push(
+            MotionVector::new(Point2::new(0.5, 0.5), motion).footprint(Vector2::new(0.2, 0.2)),
+        );
+        let mut densifier = MotionFieldDensifier::new(11, 11).kernel(DensifyKernel::Footprint);
+        densifier.add_vectors(&vectors);
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 9);
+        assert!(mf.is_valid(4, 6) && !mf.is_valid(3, 5));
+    }
+
+    #[test]
+    fn densify_wrap() {
+        let motion = Vector2::new(1.0, -1.0);
+
+        let mut densifier = MotionFieldDensifier::new(10, 5)
+            .kernel(DensifyKernel::Bilinear)
+            .wrap_horizontally(true);
+        assert_eq!(densifier.add_vector(Point2::new(0.97, 0.5), motion), (9, 2));
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 2);
+        assert!(mf.is_valid(9, 2) && mf.is_valid(0, 2));
+        assert!((mf.get_weight(0, 2) - 0.2).abs() < 1e-5);
+
+        // Interpolation reaches across the seam first.
+        let mut densifier = MotionFieldDensifier::new(10, 1).wrap_horizontally(true);
+        densifier.add_vector(Point2::new(0.0, 0.0), motion);
+        densifier.interpolate_empty_cells_within(1).unwrap();
+        let mf = MotionField::from(densifier);
+        assert_eq!(mf.valid_count(), 3);
+        assert!(mf.is_valid(9, 0) && mf.is_valid(1, 0));
+
+        // Wrapping follows the camera.
+        let densifier = MotionFieldDensifier::new(10, 1).camera(&EquirectangularCamera::new());
+        assert!(densifier.wrap);
+        let densifier = densifier.camera(&StandardCamera::new(1.0, 90.0));
+        assert!(!densifier.wrap);
+    }
+
+    #[test]
+    fn densify_sample_roundtrip() {
+        let (w, h) = (12, 7);
+        let motion = |p: Point2<f32>| Vector2::new(p.x - 0.5, 0.25 * p.y);
+
+        // One vector at the centre of every cell.
+        let vectors = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| Point2::new((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32))
+            .map(|p| (p, motion(p)))
+            .collect::<MotionVectors>();
+
+        for kernel in [DensifyKernel::Nearest, DensifyKernel::Bilinear] {
+            let mut densifier = MotionFieldDensifier::new(w, h).kernel(kernel);
+            densifier.add_vectors(&vectors);
+            let mf = MotionField::from(densifier);
+
+            assert_eq!(mf.valid_count(), mf.size());
+
+            for (pos, m) in vectors.entries() {
+                assert!((mf.sample(pos) - m).magnitude() < 1e-5, "{kernel:?} {pos}");
+            }
+
+            // Cell positions of the field match the positions it was built from.
+            for ((pos, m), (cell_pos, cell_m)) in vectors.entries().zip(mf.motion_iter()) {
+                assert!((pos - cell_pos).magnitude() < 1e-6);
+                assert!((m - cell_m).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn cast_field() {
+        let mut mf = MotionField::<f64>::zeros(3, 2);
+        mf.set_motion_weighted(1, 1, Vector2::new(0.1, -0.2), 0.5);
+        mf.set_motion_weighted(2, 0, Vector2::zeros(), 0.0);
+
+        let mf32 = mf.cast::<f32>();
+        assert_eq!(mf32.dim(), (3, 2));
+        assert_eq!(mf32.get_motion(1, 1), Vector2::new(0.1, -0.2));
+        assert_eq!(mf32.get_weight(1, 1), 0.5);
+        assert_eq!(mf32.valid_count(), 5);
+        assert_eq!(mf32.cast::<f64>().get_motion(0, 0), mf.get_motion(0, 0));
+
+        let entries = mf.motion_iter_valid().collect::<Vec<MotionEntry<f64>>>();
+        assert_eq!(
+            entries[3],
+            (Point2::new(0.5, 0.75), Vector2::new(0.1, -0.2))
+        );
+
+        let doubled = -(&mf + &mf);
+        assert_eq!(doubled.get_motion(1, 1), Vector2::new(-0.2, 0.4));
+    }
+}
@@ -0,0 +1,651 @@
+//! # Motion field operations
+//!
+//! Filtering, sampling and differential operators on [`MotionField`].
+//!
+//! Cell `(x, y)` of a `width` by `height` field is centred at normalised coordinates
+//! `((x + 0.5) / width, (y + 0.5) / height)`. Cells outside the field are treated as copies of the
+//! nearest border cell.
+//!
+//! Filtering and sampling take cell weights into account, so that cells without data do not pull
+//! motion of their neighbours towards zero. Resulting weights tell how much data went into each
+//! output cell.
+//!
+//! Filtering, sampling and differential operators are only implemented for `f32` fields, which is
+//! what densification and all estimators work with. Arithmetic operators are implemented for
+//! fields of any [`Real`] scalar, so that higher precision fields can be accumulated and compared.
+//! Use [`MotionField::cast`] to move between the two.
+//!
+//! Adding or subtracting fields is done cell by cell, thus both fields must have the same
+//! dimensions, otherwise the operation panics. [`MotionField::resample`] can be used to bring
+//! fields to the same size first.
+
+use super::*;
+use std::ops::{Add, AddAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+impl MotionField {
+    /// Get motion and weight at signed coordinates, replicating border cells.
+    fn get_clamped(&self, x: isize, y: isize) -> (Vector2<f32>, f32) {
+        let (w, h) = self.dim();
+        let x = x.clamp(0, w as isize - 1) as usize;
+        let y = y.clamp(0, h as isize - 1) as usize;
+        (self.get_motion(x, y), self.get_weight(x, y))
+    }
+
+    /// Compute weighted average of motion samples.
+    ///
+    /// Returns the average along with the total weight. Motion is zero if the total weight is.
+    fn weighted_average(samples: impl Iterator<Item = (Vector2<f32>, f32)>) -> (Vector2<f32>, f32) {
+        let (sum, weight) = samples.fold((Vector2::zeros(), 0.0), |(sum, weight), (m, w)| {
+            (sum + m * w, weight + w)
+        });
+
+        if weight > 0.0 {
+            (sum / weight, weight)
+        } else {
+            (Vector2::zeros(), 0.0)
+        }
+    }
+
+    /// Build a normalised 1D gaussian kernel covering 3 standard deviations.
+    fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+        let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+
+        let kernel = (-radius..=radius)
+            .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+
+        let sum = kernel.iter().sum::<f32>();
+
+        kernel.into_iter().map(|v| v / sum).collect()
+    }
+
+    /// Convolve the field with a 1D kernel along one of the axis.
+    ///
+    /// This is a normalised convolution - motion is averaged using kernel values multiplied by
+    /// cell weights, and the output weight is the kernel-weighted sum of input weights.
+    fn convolve_axis(&self, kernel: &[f32], horizontal: bool) -> Self {
+        let (w, h) = self.dim();
+        let radius = (kernel.len() / 2) as isize;
+        let mut out = Self::new(w, h);
+
+        for y in 0..h {
+            for x in 0..w {
+                let (motion, weight) =
+                    Self::weighted_average(kernel.iter().zip(-radius..=radius).map(|(k, o)| {
+                        let (x, y) = (x as isize, y as isize);
+                        let (m, w) = if horizontal {
+                            self.get_clamped(x + o, y)
+                        } else {
+                            self.get_clamped(x, y + o)
+                        };
+                        (m, w * *k)
+                    }));
+                out.set_motion_weighted(x, y, motion, weight);
+            }
+        }
+
+        out
+    }
+
+    /// Blur the field with a gaussian kernel.
+    ///
+    /// Cells without data are filled in from their neighbours, if there are any within the
+    /// kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `sigma` - standard deviation of the kernel, in cells.
+    pub fn gaussian_blur(&mut self, sigma: f32) {
+        if sigma <= 0.0 || self.size() == 0 {
+            return;
+        }
+
+        let kernel = Self::gaussian_kernel(sigma);
+        *self = self
+            .convolve_axis(&kernel, true)
+            .convolve_axis(&kernel, false);
+    }
+
+    /// Filter the field by taking the median of each motion component within a square window.
+    ///
+    /// Only valid cells take part in the median, and the weight of each output cell is the median
+    /// weight of those cells. Cells with no valid cells in their window stay invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - radius of the window. Window size is `2 * radius + 1` cells.
+    pub fn median_blur(&mut self, radius: usize) {
+        let (w, h) = self.dim();
+        let radius = radius as isize;
+        let mut out = Self::new(w, h);
+        let mut xs = vec![];
+        let mut ys = vec![];
+        let mut ws = vec![];
+
+        let median = |v: &mut Vec<f32>| {
+            let mid = v.len() / 2;
+            *v.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+        };
+
+        for y in 0..h {
+            for x in 0..w {
+                xs.clear();
+                ys.clear();
+                ws.clear();
+
+                for oy in -radius..=radius {
+                    for ox in -radius..=radius {
+                        let (m, w) = self.get_clamped(x as isize + ox, y as isize + oy);
+                        if w > 0.0 {
+                            xs.push(m.x);
+                            ys.push(m.y);
+                            ws.push(w);
+                        }
+                    }
+                }
+
+                if ws.is_empty() {
+                    out.set_motion_weighted(x, y, Vector2::zeros(), 0.0);
+                } else {
+                    let motion = Vector2::new(median(&mut xs), median(&mut ys));
+                    out.set_motion_weighted(x, y, motion, median(&mut ws));
+                }
+            }
+        }
+
+        *self = out;
+    }
+
+    /// Sample motion at arbitrary coordinates using bilinear interpolation.
+    ///
+    /// Cells without data are ignored, see [`sample_weighted`](Self::sample_weighted).
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - coordinates to sample at, in 0-1 range.
+    pub fn sample(&self, pos: Point2<f32>) -> Vector2<f32> {
+        self.sample_weighted(pos).0
+    }
+
+    /// Sample motion and weight at arbitrary coordinates using bilinear interpolation.
+    ///
+    /// Bilinear coefficients are multiplied by cell weights, thus only valid cells contribute
+    /// to the motion. The returned weight is the interpolated weight of the surrounding cells,
+    /// and is zero if none of them are valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - coordinates to sample at, in 0-1 range.
+    pub fn sample_weighted(&self, pos: Point2<f32>) -> (Vector2<f32>, f32) {
+        let (w, h) = self.dim();
+
+        if self.size() == 0 {
+            return (Vector2::zeros(), 0.0);
+        }
+
+        let fx = pos.x * w as f32 - 0.5;
+        let fy = pos.y * h as f32 - 0.5;
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        Self::weighted_average(
+            [
+                (0, 0, (1.0 - tx) * (1.0 - ty)),
+                (1, 0, tx * (1.0 - ty)),
+                (0, 1, (1.0 - tx) * ty),
+                (1, 1, tx * ty),
+            ]
+            .into_iter()
+            .map(|(ox, oy, k)| {
+                let (m, w) = self.get_clamped(x0 + ox, y0 + oy);
+                (m, w * k)
+            }),
+        )
+    }
+
+    /// Resample the field to a different size using bilinear interpolation.
+    ///
+    /// Motion is stored in normalised units, thus the values are not rescaled. When shrinking
+    /// the field by a large factor, consider blurring it first to avoid aliasing.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the new field.
+    /// * `height` - height of the new field.
+    pub fn resample(&self, width: usize, height: usize) -> Self {
+        let mut out = Self::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let pos = Point2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                let (motion, weight) = self.sample_weighted(pos);
+                out.set_motion_weighted(x, y, motion, weight);
+            }
+        }
+
+        out
+    }
+
+    /// Build a multi-resolution pyramid of the field.
+    ///
+    /// The first level is a copy of this field, and each following level is blurred and half the
+    /// size of the previous one. Fewer levels are returned if the field can not be shrunk further.
+    ///
+    /// # Arguments
+    ///
+    /// * `levels` - maximum number of levels in the pyramid.
+    pub fn pyramid(&self, levels: usize) -> Vec<Self> {
+        let mut out = Vec::with_capacity(levels);
+
+        if levels == 0 {
+            return out;
+        }
+
+        out.push(self.clone());
+
+        while out.len() < levels {
+            let last = out.last().unwrap();
+            let (w, h) = last.dim();
+
+            if w <= 1 && h <= 1 {
+                break;
+            }
+
+            let mut blurred = last.clone();
+            blurred.gaussian_blur(1.0);
+            out.push(blurred.resample(w.div_ceil(2), h.div_ceil(2)));
+        }
+
+        out
+    }
+
+    /// Compute a scalar value for each cell.
+    ///
+    /// The result is a `height` by `width` matrix.
+    fn map_cells(&self, f: impl Fn(usize, usize) -> f32) -> DMatrix<f32> {
+        let (w, h) = self.dim();
+        DMatrix::from_fn(h, w, |y, x| f(x, y))
+    }
+
+    /// Get motion magnitude of each cell.
+    ///
+    /// The result is a `height` by `width` matrix.
+    pub fn magnitude(&self) -> DMatrix<f32> {
+        self.map_cells(|x, y| self.get_motion(x, y).magnitude())
+    }
+
+    /// Get motion angle of each cell, in radians.
+    ///
+    /// Angle is measured from the positive X axis towards the positive Y axis. The result is a
+    /// `height` by `width` matrix.
+    pub fn angle(&self) -> DMatrix<f32> {
+        self.map_cells(|x, y| {
+            let m = self.get_motion(x, y);
+            m.y.atan2(m.x)
+        })
+    }
+
+    /// Compute partial derivatives of motion at a cell, with respect to normalised coordinates.
+    ///
+    /// Central differences are used within the field, and one-sided differences at borders.
+    fn gradient(&self, x: usize, y: usize) -> (Vector2<f32>, Vector2<f32>) {
+        let (w, h) = self.dim();
+
+        let diff = |len: usize, c: usize, get: &dyn Fn(usize) -> Vector2<f32>| {
+            if len < 2 {
+                return Vector2::zeros();
+            }
+            let (a, b) = (c.saturating_sub(1), (c + 1).min(len - 1));
+            (get(b) - get(a)) * len as f32 / (b - a) as f32
+        };
+
+        (
+            diff(w, x, &|x| self.get_motion(x, y)),
+            diff(h, y, &|y| self.get_motion(x, y)),
+        )
+    }
+
+    /// Get divergence of the field at each cell.
+    ///
+    /// Positive values indicate expansion, such as when moving forward. Derivatives are taken
+    /// with respect to normalised coordinates. The result is a `height` by `width` matrix.
+    pub fn divergence(&self) -> DMatrix<f32> {
+        self.map_cells(|x, y| {
+            let (dx, dy) = self.gradient(x, y);
+            dx.x + dy.y
+        })
+    }
+
+    /// Get curl of the field at each cell.
+    ///
+    /// This is `dv/dx - du/dy` in image coordinates, where Y points down, thus clockwise
+    /// rotation on screen produces positive values. Derivatives are taken with respect to
+    /// normalised coordinates. The result is a `height` by `width` matrix.
+    pub fn curl(&self) -> DMatrix<f32> {
+        self.map_cells(|x, y| {
+            let (dx, dy) = self.gradient(x, y);
+            dx.y - dy.x
+        })
+    }
+
+    /// Convert motion from normalised units to cells of the field.
+    ///
+    /// Dense flow files, such as `.flo` and KITTI images, store motion in pixels. When the field
+    /// has one cell per pixel, this produces motion in the units these files expect.
+    pub fn to_pixels(&self) -> Self {
+        let (w, h) = self.dim();
+        self.scale_motion(w as f32, h as f32)
+    }
+
+    /// Convert motion from cells of the field to normalised units.
+    ///
+    /// This is the inverse of [`to_pixels`](Self::to_pixels).
+    pub fn from_pixels(&self) -> Self {
+        let (w, h) = self.dim();
+        self.scale_motion(1.0 / w as f32, 1.0 / h as f32)
+    }
+
+    /// Scale horizontal and vertical motion components separately.
+    fn scale_motion(&self, x: f32, y: f32) -> Self {
+        let mut out = self.clone();
+        out.vf.row_mut(0).scale_mut(x);
+        out.vf.row_mut(1).scale_mut(y);
+        out
+    }
+}
+
+impl<T: Real> MotionField<T> {
+    /// Take the smaller weight of each cell pair.
+    ///
+    /// A result of an operation on two cells is only as valid as the least valid input.
+    fn min_weights(&mut self, rhs: &MotionField<T>) {
+        for (a, b) in self.weights.iter_mut().zip(rhs.weights.iter()) {
+            *a = a.min(*b);
+        }
+    }
+}
+
+impl<T: Real> AddAssign<&MotionField<T>> for MotionField<T> {
+    /// Add motion of each cell of `rhs`, keeping the smaller weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions.
+    fn add_assign(&mut self, rhs: &MotionField<T>) {
+        assert_eq!(self.dim(), rhs.dim());
+        self.vf += &rhs.vf;
+        self.min_weights(rhs);
+    }
+}
+
+impl<T: Real> SubAssign<&MotionField<T>> for MotionField<T> {
+    /// Subtract motion of each cell of `rhs`, keeping the smaller weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions.
+    fn sub_assign(&mut self, rhs: &MotionField<T>) {
+        assert_eq!(self.dim(), rhs.dim());
+        self.vf -= &rhs.vf;
+        self.min_weights(rhs);
+    }
+}
+
+impl<T: Real> MulAssign<T> for MotionField<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        self.vf *= rhs;
+    }
+}
+
+impl<T: Real> Add<&MotionField<T>> for MotionField<T> {
+    type Output = MotionField<T>;
+
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions, see [`AddAssign`].
+    fn add(mut self, rhs: &MotionField<T>) -> MotionField<T> {
+        self += rhs;
+        self
+    }
+}
+
+impl<T: Real> Add for &MotionField<T> {
+    type Output = MotionField<T>;
+
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions, see [`AddAssign`].
+    fn add(self, rhs: &MotionField<T>) -> MotionField<T> {
+        self.clone() + rhs
+    }
+}
+
+impl<T: Real> Sub<&MotionField<T>> for MotionField<T> {
+    type Output = MotionField<T>;
+
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions, see [`SubAssign`].
+    fn sub(mut self, rhs: &MotionField<T>) -> MotionField<T> {
+        self -= rhs;
+        self
+    }
+}
+
+impl<T: Real> Sub for &MotionField<T> {
+    type Output = MotionField<T>;
+
+    /// # Panics
+    ///
+    /// Panics if the fields have different dimensions, see [`SubAssign`].
+    fn sub(self, rhs: &MotionField<T>) -> MotionField<T> {
+        self.clone() - rhs
+    }
+}
+
+impl<T: Real> Mul<T> for MotionField<T> {
+    type Output = MotionField<T>;
+
+    fn mul(mut self, rhs: T) -> MotionField<T> {
+        self *= rhs;
+        self
+    }
+}
+
+impl<T: Real> Neg for MotionField<T> {
+    type Output = MotionField<T>;
+
+    fn neg(self) -> MotionField<T> {
+        self * -T::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_fn(w: usize, h: usize, f: impl Fn(f32, f32) -> Vector2<f32>) -> MotionField {
+        let mut mf = MotionField::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                let pos = ((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+                mf.set_motion(x, y, f(pos.0, pos.1));
+            }
+        }
+        mf
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn blur_preserves_constant() {
+        let mut mf = from_fn(7, 5, |_, _| Vector2::new(0.5, -0.25));
+        let orig = mf.clone();
+
+        mf.gaussian_blur(2.0);
+        for ((_, _, a), (_, _, b)) in mf.iter().zip(orig.iter()) {
+            assert_close(a.x, b.x);
+            assert_close(a.y, b.y);
+        }
+
+        mf.median_blur(2);
+        assert_eq!(mf.dim(), orig.dim());
+        assert_close(mf.get_motion(3, 2).x, 0.5);
+    }
+
+    #[test]
+    fn blur_smooths_impulse() {
+        let mut mf = MotionField::new(9, 9);
+        mf.set_motion(4, 4, Vector2::new(1.0, 0.0));
+
+        let mut gauss = mf.clone();
+        gauss.gaussian_blur(1.0);
+        // Energy is preserved, but spread around.
+        assert_close(gauss.as_slice().iter().sum::<f32>(), 1.0);
+        assert!(gauss.get_motion(4, 4).x < 0.5);
+        assert!(gauss.get_motion(5, 4).x > 0.0);
+
+        // Median filter removes the outlier entirely.
+        mf.median_blur(1);
+        assert!(mf.as_slice().iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn bilinear_sampling() {
+        let mf = from_fn(8, 6, |x, y| Vector2::new(x, 2.0 * y));
+
+        // Exact at cell centres.
+        let m = mf.sample(Point2::new(0.5 / 8.0, 1.5 / 6.0));
+        assert_close(m.x, 0.5 / 8.0);
+        assert_close(m.y, 3.0 / 6.0);
+
+        // Linear fields are reproduced exactly inside the field.
+        for &(x, y) in &[(0.3, 0.4), (0.51, 0.77), (0.2, 0.2)] {
+            let m = mf.sample(Point2::new(x, y));
+            assert_close(m.x, x);
+            assert_close(m.y, 2.0 * y);
+        }
+
+        // Border cells are replicated.
+        let m = mf.sample(Point2::new(0.0, 1.0));
+        assert_close(m.x, 0.5 / 8.0);
+        assert_close(m.y, 2.0 * 5.5 / 6.0);
+    }
+
+    #[test]
+    fn resample_and_pyramid() {
+        let mf = from_fn(16, 8, Vector2::new);
+
+        let up = mf.resample(32, 16);
+        assert_eq!(up.dim(), (32, 16));
+        let m = up.get_motion(10, 5);
+        assert_close(m.x, 10.5 / 32.0);
+        assert_close(m.y, 5.5 / 16.0);
+
+        let pyramid = mf.pyramid(10);
+        let dims = pyramid.iter().map(MotionField::dim).collect::<Vec<_>>();
+        assert_eq!(dims, [(16, 8), (8, 4), (4, 2), (2, 1), (1, 1)]);
+        assert_eq!(pyramid[0], mf);
+        assert!(mf.pyramid(0).is_empty());
+
+        // Motion at each cell points to its own pixel coordinates.
+        let px = mf.to_pixels();
+        assert_close(px.get_motion(10, 5).x, 10.5);
+        assert_close(px.get_motion(10, 5).y, 5.5);
+        assert_eq!(px.weights(), mf.weights());
+        assert!((px.from_pixels().get_motion(3, 7) - mf.get_motion(3, 7)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn differential_operators() {
+        // Expansion around the centre.
+        let radial = from_fn(9, 9, |x, y| Vector2::new(x - 0.5, y - 0.5));
+        let div = radial.divergence();
+        let curl = radial.curl();
+        assert_eq!(div.shape(), (9, 9));
+        for (d, c) in div.iter().zip(curl.iter()) {
+            assert_close(*d, 2.0);
+            assert_close(*c, 0.0);
+        }
+
+        // Clockwise rotation on screen.
+        let rot = from_fn(9, 9, |x, y| Vector2::new(-(y - 0.5), x - 0.5));
+        for (d, c) in rot.divergence().iter().zip(rot.curl().iter()) {
+            assert_close(*d, 0.0);
+            assert_close(*c, 2.0);
+        }
+
+        let mag = rot.magnitude();
+        let angle = rot.angle();
+        assert_close(mag[(4, 8)], 4.0 / 9.0);
+        assert_close(angle[(4, 8)], std::f32::consts::FRAC_PI_2);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = from_fn(4, 3, Vector2::new);
+        let b = from_fn(4, 3, |x, _| Vector2::new(x, 1.0));
+
+        let sum = &a + &b;
+        let diff = a.clone() - &b;
+        let scaled = -(a.clone() * 2.0);
+
+        for (x, y, m) in sum.iter() {
+            let (pa, pb) = (a.get_motion(x, y), b.get_motion(x, y));
+            assert_eq!(m, pa + pb);
+            assert_eq!(diff.get_motion(x, y), pa - pb);
+            assert_eq!(scaled.get_motion(x, y), pa * -2.0);
+        }
+    }
+
+    #[test]
+    fn weighted_filtering() {
+        // Left half has data, right half does not.
+        let mut mf = MotionField::new_empty(8, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                mf.set_motion(x, y, Vector2::new(1.0, -1.0));
+            }
+        }
+
+        // Missing cells do not pull the motion towards zero.
+        let mut gauss = mf.clone();
+        gauss.gaussian_blur(1.0);
+        let m = gauss.get_motion(3, 1);
+        assert_close(m.x, 1.0);
+        assert_close(m.y, -1.0);
+        assert!(gauss.get_weight(3, 1) < 1.0);
+        assert!(gauss.is_valid(4, 1));
+        assert_close(gauss.get_motion(4, 1).x, 1.0);
+
+        let mut median = mf.clone();
+        median.median_blur(1);
+        assert_close(median.get_motion(4, 2).x, 1.0);
+        assert_close(median.get_weight(4, 2), 1.0);
+        assert!(!median.is_valid(6, 2));
+
+        let (m, w) = mf.sample_weighted(Point2::new(0.5, 0.5));
+        assert_close(m.x, 1.0);
+        assert_close(w, 0.5);
+        assert_eq!(mf.sample_weighted(Point2::new(0.9, 0.5)).1, 0.0);
+
+        let half = mf.resample(4, 2);
+        assert_eq!(half.valid_count(), 4);
+
+        let sum = &mf + &MotionField::new(8, 4);
+        assert_eq!(sum.valid_count(), mf.valid_count());
+        assert_eq!((-mf.clone()).weights(), mf.weights());
+    }
+
+    #[test]
+    #[should_panic]
+    fn arithmetic_dim_mismatch() {
+        let _ = MotionField::new(2, 2) + &MotionField::new(3, 2);
+    }
+}
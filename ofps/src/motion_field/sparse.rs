@@ -0,0 +1,176 @@
+//! # Sparse motion field
+//!
+//! Fields built from a handful of motion vectors leave most cells empty. [`SparseMotionField`]
+//! only stores cells that have data, and converts to and from the dense [`MotionField`].
+
+use super::*;
+use std::collections::BTreeMap;
+
+/// Fixed size motion field that only stores valid cells.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SparseMotionField {
+    cells: BTreeMap<usize, (Vector2<f32>, f32)>,
+    width: usize,
+    height: usize,
+}
+
+impl SparseMotionField {
+    /// Create a new, empty sparse motion field.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - width of the field.
+    /// * `height` - height of the field.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: Default::default(),
+            width,
+            height,
+        }
+    }
+
+    /// Get the width and height of the field.
+    pub fn dim(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Get the number of cells with data.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Check whether no cells have data.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Set motion and weight of a cell.
+    ///
+    /// Cells with non-positive weight are removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate to set at.
+    /// * `y` - vertical coordinate to set at.
+    /// * `motion` - motion to set.
+    /// * `weight` - weight of the cell.
+    pub fn insert(&mut self, x: usize, y: usize, motion: Vector2<f32>, weight: f32) {
+        assert!(x < self.width && y < self.height);
+        let idx = self.width * y + x;
+        if weight > 0.0 {
+            self.cells.insert(idx, (motion, weight));
+        } else {
+            self.cells.remove(&idx);
+        }
+    }
+
+    /// Get motion and weight of a cell, if it has data.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - horizontal coordinate.
+    /// * `y` - vertical coordinate.
+    pub fn get(&self, x: usize, y: usize) -> Option<(Vector2<f32>, f32)> {
+        self.cells.get(&(self.width * y + x)).copied()
+    }
+
+    /// Iterate every cell with data, in row-major order.
+    ///
+    /// The resulting iterator yields `(x, y, motion, weight)` entries.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, Vector2<f32>, f32)> + '_ {
+        self.cells.iter().map(move |(&idx, &(motion, weight))| {
+            (idx % self.width, idx / self.width, motion, weight)
+        })
+    }
+}
+
+impl From<&MotionField> for SparseMotionField {
+    fn from(mf: &MotionField) -> Self {
+        let (width, height) = mf.dim();
+        let mut ret = Self::new(width, height);
+
+        for (x, y, motion, weight) in mf.iter_weighted() {
+            ret.insert(x, y, motion, weight);
+        }
+
+        ret
+    }
+}
+
+impl From<MotionFieldDensifier> for SparseMotionField {
+    fn from(densifier: MotionFieldDensifier) -> Self {
+        Self::from(&MotionField::from(densifier))
+    }
+}
+
+impl From<&SparseMotionField> for MotionField {
+    fn from(sparse: &SparseMotionField) -> Self {
+        let (width, height) = sparse.dim();
+        let mut ret = Self::new_empty(width, height);
+
+        for (x, y, motion, weight) in sparse.iter() {
+            ret.set_motion_weighted(x, y, motion, weight);
+        }
+
+        ret
+    }
+}
+
+impl From<SparseMotionField> for MotionField {
+    fn from(sparse: SparseMotionField) -> Self {
+        Self::from(&sparse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn densifier_validity() {
+        let mut densifier = MotionFieldDensifier::new(4, 4);
+        densifier.add_vector(Point2::new(0.1, 0.1), Vector2::zeros());
+        densifier.add_vector(Point2::new(0.9, 0.6), Vector2::new(1.0, 2.0));
+        densifier.add_vector(Point2::new(0.9, 0.6), Vector2::new(3.0, 2.0));
+
+        let mut mf = MotionField::new(4, 4);
+        mf.from_densifier(&densifier);
+        assert_eq!(mf, MotionField::from(densifier));
+
+        // Zero motion is told apart from no data.
+        assert!(mf.is_valid(0, 0));
+        assert!(!mf.is_valid(1, 0));
+        assert_eq!(mf.get_motion(3, 2), Vector2::new(2.0, 2.0));
+        assert_eq!(mf.get_weight(3, 2), 2.0);
+        assert_eq!(mf.valid_count(), 2);
+
+        let valid = mf.iter_valid().map(|(x, y, _)| (x, y)).collect::<Vec<_>>();
+        assert_eq!(valid, [(0, 0), (3, 2)]);
+        assert_eq!(mf.motion_iter_valid().count(), 2);
+        assert_eq!(mf.iter().count(), 16);
+
+        let sparse = SparseMotionField::from(&mf);
+        assert_eq!(sparse.len(), 2);
+        assert_eq!(sparse.get(3, 2), Some((Vector2::new(2.0, 2.0), 2.0)));
+        assert_eq!(sparse.get(1, 0), None);
+        assert_eq!(MotionField::from(&sparse), mf);
+    }
+
+    #[test]
+    fn sparse_insert_remove() {
+        let mut sparse = SparseMotionField::new(3, 2);
+        assert!(sparse.is_empty());
+
+        sparse.insert(2, 1, Vector2::new(1.0, 0.0), 0.5);
+        sparse.insert(0, 1, Vector2::new(0.0, 1.0), 1.0);
+        let cells = sparse.iter().map(|(x, y, _, _)| (x, y)).collect::<Vec<_>>();
+        assert_eq!(cells, [(0, 1), (2, 1)]);
+
+        sparse.insert(2, 1, Vector2::zeros(), 0.0);
+        assert_eq!(sparse.len(), 1);
+
+        let mf = MotionField::from(sparse);
+        assert_eq!(mf.valid_count(), 1);
+        assert_eq!(mf.get_motion(0, 1), Vector2::new(0.0, 1.0));
+    }
+}
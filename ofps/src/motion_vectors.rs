@@ -0,0 +1,349 @@
+//! # Motion vector storage
+//!
+//! Decoders produce motion vectors with varying amounts of metadata attached. Codecs know the
+//! size of the block each vector describes, which frame it references and their own flags, while
+//! dense optical flow methods may know how confident they are in each vector. [`MotionVectors`]
+//! keeps all of this information in a struct-of-arrays layout, so that the hot loops only touch
+//! the data they need.
+
+use nalgebra as na;
+
+/// Pair containing coordinates and motion at them.
+pub type MotionEntry<T = f32> = (na::Point2<T>, na::Vector2<T>);
+
+/// Source of a motion vector.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VectorSource {
+    /// Source is not known.
+    #[default]
+    Unknown,
+    /// Vector references a past frame.
+    Forward,
+    /// Vector references a future frame.
+    Backward,
+}
+
+/// Single motion vector with metadata.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotionVector {
+    /// Starting position of the vector, in 0-1 range.
+    pub pos: na::Point2<f32>,
+    /// Motion of the vector.
+    pub motion: na::Vector2<f32>,
+    /// Confidence of the vector, `1.0` if unknown.
+    pub confidence: f32,
+    /// Width and height of the area described by the vector, in 0-1 range.
+    ///
+    /// Zero if the vector describes a single point, or the area is unknown.
+    pub footprint: na::Vector2<f32>,
+    /// Source of the vector.
+    pub source: VectorSource,
+    /// Decoder specific flags of the vector, such as `AVMotionVector::flags`. Zero if unknown.
+    pub flags: u64,
+}
+
+impl MotionVector {
+    /// Create a new motion vector with no metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - starting position of the vector.
+    /// * `motion` - motion of the vector.
+    pub fn new(pos: na::Point2<f32>, motion: na::Vector2<f32>) -> Self {
+        Self {
+            pos,
+            motion,
+            confidence: 1.0,
+            footprint: na::Vector2::zeros(),
+            source: VectorSource::Unknown,
+            flags: 0,
+        }
+    }
+
+    /// Set the confidence of the vector.
+    pub fn confidence(self, confidence: f32) -> Self {
+        Self { confidence, ..self }
+    }
+
+    /// Set the footprint of the vector.
+    pub fn footprint(self, footprint: na::Vector2<f32>) -> Self {
+        Self { footprint, ..self }
+    }
+
+    /// Set the source of the vector.
+    pub fn source(self, source: VectorSource) -> Self {
+        Self { source, ..self }
+    }
+
+    /// Set the decoder specific flags of the vector.
+    pub fn flags(self, flags: u64) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Get the area covered by the vector.
+    pub fn area(&self) -> f32 {
+        self.footprint.x * self.footprint.y
+    }
+}
+
+impl From<MotionEntry> for MotionVector {
+    fn from((pos, motion): MotionEntry) -> Self {
+        Self::new(pos, motion)
+    }
+}
+
+impl From<MotionVector> for MotionEntry {
+    fn from(vector: MotionVector) -> Self {
+        (vector.pos, vector.motion)
+    }
+}
+
+/// Collection of motion vectors, stored as a struct-of-arrays.
+///
+/// Vectors can be pushed either as [`MotionVector`] elements, or as plain [`MotionEntry`] tuples,
+/// in which case they get no metadata.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MotionVectors {
+    pos: Vec<na::Point2<f32>>,
+    motion: Vec<na::Vector2<f32>>,
+    confidence: Vec<f32>,
+    footprint: Vec<na::Vector2<f32>>,
+    source: Vec<VectorSource>,
+    flags: Vec<u64>,
+}
+
+impl MotionVectors {
+    /// Create an empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty collection with space for `capacity` vectors.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut ret = Self::default();
+        ret.reserve(capacity);
+        ret
+    }
+
+    /// Get the number of vectors.
+    pub fn len(&self) -> usize {
+        self.pos.len()
+    }
+
+    /// Check whether there are no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.pos.is_empty()
+    }
+
+    /// Remove all vectors, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.pos.clear();
+        self.motion.clear();
+        self.confidence.clear();
+        self.footprint.clear();
+        self.source.clear();
+        self.flags.clear();
+    }
+
+    /// Reserve space for at least `additional` more vectors.
+    pub fn reserve(&mut self, additional: usize) {
+        self.pos.reserve(additional);
+        self.motion.reserve(additional);
+        self.confidence.reserve(additional);
+        self.footprint.reserve(additional);
+        self.source.reserve(additional);
+        self.flags.reserve(additional);
+    }
+
+    /// Add a vector to the collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `vector` - either a `MotionVector`, or a `MotionEntry`.
+    pub fn push(&mut self, vector: impl Into<MotionVector>) {
+        let vector = vector.into();
+        self.pos.push(vector.pos);
+        self.motion.push(vector.motion);
+        self.confidence.push(vector.confidence);
+        self.footprint.push(vector.footprint);
+        self.source.push(vector.source);
+        self.flags.push(vector.flags);
+    }
+
+    /// Get the vector at given index.
+    pub fn get(&self, idx: usize) -> Option<MotionVector> {
+        Some(MotionVector {
+            pos: *self.pos.get(idx)?,
+            motion: self.motion[idx],
+            confidence: self.confidence[idx],
+            footprint: self.footprint[idx],
+            source: self.source[idx],
+            flags: self.flags[idx],
+        })
+    }
+
+    /// Get starting positions of all vectors.
+    pub fn positions(&self) -> &[na::Point2<f32>] {
+        &self.pos
+    }
+
+    /// Get motion of all vectors.
+    pub fn motions(&self) -> &[na::Vector2<f32>] {
+        &self.motion
+    }
+
+    /// Get confidence of all vectors.
+    pub fn confidences(&self) -> &[f32] {
+        &self.confidence
+    }
+
+    /// Get footprints of all vectors.
+    pub fn footprints(&self) -> &[na::Vector2<f32>] {
+        &self.footprint
+    }
+
+    /// Get sources of all vectors.
+    pub fn sources(&self) -> &[VectorSource] {
+        &self.source
+    }
+
+    /// Get decoder specific flags of all vectors.
+    pub fn flags(&self) -> &[u64] {
+        &self.flags
+    }
+
+    /// Iterate over all vectors.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = MotionVector> + '_ {
+        (0..self.len()).map(move |i| MotionVector {
+            pos: self.pos[i],
+            motion: self.motion[i],
+            confidence: self.confidence[i],
+            footprint: self.footprint[i],
+            source: self.source[i],
+            flags: self.flags[i],
+        })
+    }
+
+    /// Iterate over all vectors as `MotionEntry` tuples.
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = MotionEntry> + '_ {
+        self.pos.iter().copied().zip(self.motion.iter().copied())
+    }
+
+    /// Iterate over weights of all vectors.
+    ///
+    /// Weight of a vector is its confidence multiplied by its area, relative to the average area
+    /// of all vectors with a known footprint. Vectors without a footprint are treated as having
+    /// the average area. This keeps weights around `1.0`, no matter the units of the footprint.
+    pub fn weights(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
+        let (sum, cnt) = self
+            .footprint
+            .iter()
+            .map(|f| f.x * f.y)
+            .filter(|&a| a > 0.0)
+            .fold((0.0, 0), |(sum, cnt), a| (sum + a, cnt + 1));
+
+        let inv_mean = if cnt > 0 { cnt as f32 / sum } else { 1.0 };
+
+        self.confidence
+            .iter()
+            .zip(self.footprint.iter())
+            .map(move |(&c, f)| {
+                let area = f.x * f.y;
+                if area > 0.0 {
+                    c * area * inv_mean
+                } else {
+                    c
+                }
+            })
+    }
+}
+
+impl<T: Into<MotionVector>> Extend<T> for MotionVectors {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        iter.for_each(|v| self.push(v));
+    }
+}
+
+impl<T: Into<MotionVector>> FromIterator<T> for MotionVectors {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut ret = Self::default();
+        ret.extend(iter);
+        ret
+    }
+}
+
+impl From<Vec<MotionEntry>> for MotionVectors {
+    fn from(entries: Vec<MotionEntry>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl From<&[MotionEntry]> for MotionVectors {
+    fn from(entries: &[MotionEntry]) -> Self {
+        entries.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soa_roundtrip() {
+        let entries = (0..10)
+            .map(|i| {
+                let i = i as f32;
+                (na::Point2::new(i, -i), na::Vector2::new(i * 2.0, i * 3.0))
+            })
+            .collect::<Vec<_>>();
+
+        let mut vectors = MotionVectors::from(entries.clone());
+        assert_eq!(vectors.len(), entries.len());
+        assert_eq!(vectors.entries().collect::<Vec<_>>(), entries);
+        assert!(vectors.weights().all(|w| w == 1.0));
+
+        vectors.push(
+            MotionVector::new(na::Point2::new(1.0, 2.0), na::Vector2::new(3.0, 4.0))
+                .confidence(0.5)
+                .source(VectorSource::Backward)
+                .flags(0x1),
+        );
+
+        let last = vectors.get(entries.len()).unwrap();
+        assert_eq!(last.confidence, 0.5);
+        assert_eq!(last.source, VectorSource::Backward);
+        assert_eq!(last.flags, 0x1);
+        assert_eq!(vectors.flags()[0], 0);
+        assert_eq!(vectors.iter().count(), entries.len() + 1);
+        assert!(vectors.get(entries.len() + 1).is_none());
+
+        vectors.clear();
+        assert!(vectors.is_empty());
+    }
+
+    #[test]
+    fn area_weights() {
+        let vectors = [
+            (na::Vector2::new(0.1, 0.1), 1.0),
+            (na::Vector2::new(0.2, 0.2), 1.0),
+            (na::Vector2::new(0.2, 0.2), 0.5),
+            (na::Vector2::zeros(), 0.5),
+        ]
+        .into_iter()
+        .map(|(footprint, confidence)| {
+            MotionVector::new(na::Point2::origin(), na::Vector2::zeros())
+                .footprint(footprint)
+                .confidence(confidence)
+        })
+        .collect::<MotionVectors>();
+
+        // Mean area of vectors with footprints is 0.03.
+        let expected = [1.0 / 3.0, 4.0 / 3.0, 2.0 / 3.0, 0.5];
+
+        for (w, e) in vectors.weights().zip(expected) {
+            assert!((w - e).abs() < 1e-5, "{w} {e}");
+        }
+    }
+}
@@ -0,0 +1,567 @@
+//! # NumPy array export
+//!
+//! This module writes motion data to NumPy `.npy` arrays, and `.npz` archives of them, so that it
+//! can be loaded with `numpy.load` without parsing any of the OFPS specific formats.
+//!
+//! [`NpyDump`] collects per-frame motion vectors, motion fields and camera poses, and writes them
+//! out as the following arrays. Groups that have no data are omitted.
+//!
+//! | Name                  | Type  | Shape          | Description                              |
+//! |-----------------------|-------|----------------|------------------------------------------|
+//! | `vectors_frame`       | `u64` | `(F,)`         | frame index of each vector frame         |
+//! | `vectors_timestamp`   | `f64` | `(F,)`         | timestamp in seconds (`NaN` if unknown)  |
+//! | `vectors_offset`      | `u64` | `(F + 1,)`     | start of each frame in the vector arrays |
+//! | `vectors_pos`         | `f32` | `(N, 2)`       | starting positions, in 0-1 range         |
+//! | `vectors_motion`      | `f32` | `(N, 2)`       | motion of the vectors                    |
+//! | `vectors_confidence`  | `f32` | `(N,)`         | confidence of the vectors                |
+//! | `vectors_footprint`   | `f32` | `(N, 2)`       | footprint of the vectors, in 0-1 range   |
+//! | `fields_frame`        | `u64` | `(F,)`         | frame index of each field                |
+//! | `fields_timestamp`    | `f64` | `(F,)`         | timestamp in seconds (`NaN` if unknown)  |
+//! | `fields_motion`       | `f32` | `(F, H, W, 2)` | motion of each cell                      |
+//! | `fields_weight`       | `f32` | `(F, H, W)`    | weight of each cell, `0` if invalid      |
+//! | `trajectory_frame`    | `u64` | `(F,)`         | frame index of each pose                 |
+//! | `trajectory_timestamp`| `f64` | `(F,)`         | timestamp in seconds (`NaN` if unknown)  |
+//! | `trajectory_position` | `f32` | `(F, 3)`       | camera position                          |
+//! | `trajectory_rotation` | `f32` | `(F, 4)`       | camera rotation quaternion `(x, y, z, w)`|
+//!
+//! Vectors of frame `i` are at `vectors_offset[i]..vectors_offset[i + 1]`.
+
+use crate::prelude::v1::*;
+use nalgebra as na;
+use std::io::Write;
+use std::path::Path;
+
+/// Element type of a NumPy array.
+pub trait NpyElement: Copy {
+    /// NumPy type descriptor of the element.
+    const DESCR: &'static str;
+
+    /// Append the little endian representation of the element.
+    fn extend_le(self, out: &mut Vec<u8>);
+}
+
+macro_rules! npy_element {
+    ($($ty:ty => $descr:literal),*) => {
+        $(
+            impl NpyElement for $ty {
+                const DESCR: &'static str = $descr;
+
+                fn extend_le(self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+npy_element!(u8 => "|u1", u32 => "<u4", u64 => "<u8", i32 => "<i4", i64 => "<i8", f32 => "<f4", f64 => "<f8");
+
+/// Serialize an array in `.npy` format.
+///
+/// # Arguments
+///
+/// * `shape` - dimensions of the array, in C order. Their product must equal length of `data`.
+/// * `data` - elements of the array.
+pub fn npy_bytes<T: NpyElement>(shape: &[usize], data: &[T]) -> Result<Vec<u8>> {
+    if shape.iter().product::<usize>() != data.len() {
+        return Err(anyhow!(
+            "shape {shape:?} does not match {} elements",
+            data.len()
+        ));
+    }
+
+    let shape = match shape {
+        [dim] => format!("({dim},)"),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        T::DESCR
+    );
+
+    // Magic, version and header length take 10 bytes. Pad the header with spaces, so that the data
+    // is 64 byte aligned, and terminate it with a newline.
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let header_len = u16::try_from(header.len()).map_err(|_| anyhow!("npy header is too long"))?;
+
+    let mut out = Vec::with_capacity(10 + header.len() + std::mem::size_of_val(data));
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&header_len.to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+
+    for &v in data {
+        v.extend_le(&mut out);
+    }
+
+    Ok(out)
+}
+
+/// Write an array in `.npy` format.
+///
+/// # Arguments
+///
+/// * `out` - writer to output the array to.
+/// * `shape` - dimensions of the array, in C order. Their product must equal length of `data`.
+/// * `data` - elements of the array.
+pub fn write_npy<T: NpyElement>(mut out: impl Write, shape: &[usize], data: &[T]) -> Result<()> {
+    out.write_all(&npy_bytes(shape, data)?)?;
+    Ok(())
+}
+
+/// Entry of the central directory of a zip archive.
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writer of `.npz` archives.
+///
+/// Arrays are stored uncompressed. Archives larger than 4 GiB are not supported.
+pub struct NpzWriter<W> {
+    out: W,
+    entries: Vec<ZipEntry>,
+    offset: u64,
+}
+
+/// Date of 1980-01-01 in DOS format, used as modification date of all archive entries.
+const DOS_EPOCH: u16 = (1 << 5) | 1;
+
+impl<W: Write> NpzWriter<W> {
+    /// Create a new archive writer.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - writer to output the archive to.
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            entries: vec![],
+            offset: 0,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Add an array to the archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the array, without the `.npy` extension.
+    /// * `shape` - dimensions of the array, in C order. Their product must equal length of `data`.
+    /// * `data` - elements of the array.
+    pub fn add_array<T: NpyElement>(
+        &mut self,
+        name: &str,
+        shape: &[usize],
+        data: &[T],
+    ) -> Result<()> {
+        let data = npy_bytes(shape, data)?;
+        let name = format!("{name}.npy");
+
+        let too_large = || anyhow!("npz archive is too large");
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let crc = crc32fast::hash(&data);
+
+        let mut header = 0x04034b50u32.to_le_bytes().to_vec();
+        // Version needed, flags, stored method, modification time and date.
+        for v in [20u16, 0, 0, 0, DOS_EPOCH] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [crc, size, size] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [name.len() as u16, 0] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        header.extend_from_slice(name.as_bytes());
+
+        self.write(&header)?;
+        self.write(&data)?;
+
+        self.entries.push(ZipEntry {
+            name,
+            crc,
+            size,
+            offset,
+        });
+
+        Ok(())
+    }
+
+    /// Write the central directory and finish the archive.
+    ///
+    /// Returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let too_large = || anyhow!("npz archive is too large");
+        let dir_offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let count = u16::try_from(self.entries.len()).map_err(|_| too_large())?;
+
+        let mut dir = vec![];
+
+        for entry in &self.entries {
+            dir.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            // Version made by, version needed, flags, stored method, modification time and date.
+            for v in [20u16, 20, 0, 0, 0, DOS_EPOCH] {
+                dir.extend_from_slice(&v.to_le_bytes());
+            }
+            for v in [entry.crc, entry.size, entry.size] {
+                dir.extend_from_slice(&v.to_le_bytes());
+            }
+            // Name, extra field and comment lengths, disk number and internal attributes.
+            for v in [entry.name.len() as u16, 0, 0, 0, 0] {
+                dir.extend_from_slice(&v.to_le_bytes());
+            }
+            // External attributes and offset of the local header.
+            for v in [0u32, entry.offset] {
+                dir.extend_from_slice(&v.to_le_bytes());
+            }
+            dir.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let dir_size = u32::try_from(dir.len()).map_err(|_| too_large())?;
+
+        // End of central directory record.
+        dir.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        for v in [0u16, 0, count, count] {
+            dir.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [dir_size, dir_offset] {
+            dir.extend_from_slice(&v.to_le_bytes());
+        }
+        dir.extend_from_slice(&0u16.to_le_bytes());
+
+        self.write(&dir)?;
+
+        Ok(self.out)
+    }
+}
+
+/// Frame indices and timestamps of a group of arrays.
+#[derive(Default)]
+struct FrameIndex {
+    frames: Vec<u64>,
+    timestamps: Vec<f64>,
+}
+
+impl FrameIndex {
+    fn push(&mut self, frame: usize, timestamp: Option<f64>) {
+        self.frames.push(frame as u64);
+        self.timestamps.push(timestamp.unwrap_or(f64::NAN));
+    }
+
+    fn len(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// Collection of per-frame motion data to be exported as NumPy arrays.
+///
+/// See [module documentation](self) for the layout of the arrays.
+#[derive(Default)]
+pub struct NpyDump {
+    vectors: FrameIndex,
+    vector_offsets: Vec<u64>,
+    positions: Vec<f32>,
+    motions: Vec<f32>,
+    confidences: Vec<f32>,
+    footprints: Vec<f32>,
+    fields: FrameIndex,
+    field_dim: Option<(usize, usize)>,
+    field_motions: Vec<f32>,
+    field_weights: Vec<f32>,
+    trajectory: FrameIndex,
+    positions_3d: Vec<f32>,
+    rotations: Vec<f32>,
+}
+
+impl NpyDump {
+    /// Create a new, empty dump.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add motion vectors of a frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - index of the frame.
+    /// * `timestamp` - timestamp of the frame in seconds, if known.
+    /// * `vectors` - motion vectors of the frame.
+    pub fn add_vectors(&mut self, frame: usize, timestamp: Option<f64>, vectors: &MotionVectors) {
+        if self.vector_offsets.is_empty() {
+            self.vector_offsets.push(0);
+        }
+
+        self.vectors.push(frame, timestamp);
+
+        for v in vectors.iter() {
+            self.positions.extend([v.pos.x, v.pos.y]);
+            self.motions.extend([v.motion.x, v.motion.y]);
+            self.confidences.push(v.confidence);
+            self.footprints.extend([v.footprint.x, v.footprint.y]);
+        }
+
+        self.vector_offsets.push(self.confidences.len() as u64);
+    }
+
+    /// Add a motion field of a frame.
+    ///
+    /// All fields must have the same dimensions.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - index of the frame.
+    /// * `timestamp` - timestamp of the frame in seconds, if known.
+    /// * `field` - motion field of the frame.
+    pub fn add_field(
+        &mut self,
+        frame: usize,
+        timestamp: Option<f64>,
+        field: &MotionField,
+    ) -> Result<()> {
+        let dim = field.dim();
+
+        if *self.field_dim.get_or_insert(dim) != dim {
+            return Err(anyhow!(
+                "field dimensions {dim:?} differ from {:?}",
+                self.field_dim
+            ));
+        }
+
+        self.fields.push(frame, timestamp);
+
+        for (_, _, motion, weight) in field.iter_weighted() {
+            self.field_motions.extend([motion.x, motion.y]);
+            self.field_weights.push(weight);
+        }
+
+        Ok(())
+    }
+
+    /// Add a camera pose of a frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - index of the frame.
+    /// * `timestamp` - timestamp of the frame in seconds, if known.
+    /// * `rot` - rotation of the camera.
+    /// * `pos` - position of the camera.
+    pub fn add_pose(
+        &mut self,
+        frame: usize,
+        timestamp: Option<f64>,
+        rot: na::UnitQuaternion<f32>,
+        pos: na::Point3<f32>,
+    ) {
+        self.trajectory.push(frame, timestamp);
+        self.positions_3d.extend(pos.coords.iter());
+        self.rotations.extend(rot.coords.iter());
+    }
+
+    /// Call `f` with name, shape and data of each non-empty array.
+    fn for_each_array(
+        &self,
+        mut f: impl FnMut(&str, &[usize], NpyData) -> Result<()>,
+    ) -> Result<()> {
+        let mut index = |prefix: &str, index: &FrameIndex| -> Result<()> {
+            let len = [index.len()];
+            f(
+                &format!("{prefix}_frame"),
+                &len,
+                NpyData::U64(&index.frames),
+            )?;
+            f(
+                &format!("{prefix}_timestamp"),
+                &len,
+                NpyData::F64(&index.timestamps),
+            )
+        };
+
+        let mut arrays = vec![];
+
+        if self.vectors.len() > 0 {
+            index("vectors", &self.vectors)?;
+            let n = self.confidences.len();
+            arrays.extend([
+                (
+                    "vectors_offset",
+                    vec![self.vector_offsets.len()],
+                    NpyData::U64(&self.vector_offsets),
+                ),
+                ("vectors_pos", vec![n, 2], NpyData::F32(&self.positions)),
+                ("vectors_motion", vec![n, 2], NpyData::F32(&self.motions)),
+                (
+                    "vectors_confidence",
+                    vec![n],
+                    NpyData::F32(&self.confidences),
+                ),
+                (
+                    "vectors_footprint",
+                    vec![n, 2],
+                    NpyData::F32(&self.footprints),
+                ),
+            ]);
+        }
+
+        if let Some((w, h)) = self.field_dim.filter(|_| self.fields.len() > 0) {
+            index("fields", &self.fields)?;
+            let n = self.fields.len();
+            arrays.extend([
+                (
+                    "fields_motion",
+                    vec![n, h, w, 2],
+                    NpyData::F32(&self.field_motions),
+                ),
+                (
+                    "fields_weight",
+                    vec![n, h, w],
+                    NpyData::F32(&self.field_weights),
+                ),
+            ]);
+        }
+
+        if self.trajectory.len() > 0 {
+            index("trajectory", &self.trajectory)?;
+            let n = self.trajectory.len();
+            arrays.extend([
+                (
+                    "trajectory_position",
+                    vec![n, 3],
+                    NpyData::F32(&self.positions_3d),
+                ),
+                (
+                    "trajectory_rotation",
+                    vec![n, 4],
+                    NpyData::F32(&self.rotations),
+                ),
+            ]);
+        }
+
+        for (name, shape, data) in arrays {
+            f(name, &shape, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write all arrays to a `.npz` archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - writer to output the archive to.
+    pub fn write_npz<W: Write>(&self, out: W) -> Result<W> {
+        let mut npz = NpzWriter::new(out);
+        self.for_each_array(|name, shape, data| match data {
+            NpyData::U64(data) => npz.add_array(name, shape, data),
+            NpyData::F32(data) => npz.add_array(name, shape, data),
+            NpyData::F64(data) => npz.add_array(name, shape, data),
+        })?;
+        npz.finish()
+    }
+
+    /// Write all arrays as separate `.npy` files.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - directory to write the files to. It gets created if it does not exist.
+    pub fn write_npy_dir(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        self.for_each_array(|name, shape, data| {
+            let out =
+                std::io::BufWriter::new(std::fs::File::create(dir.join(format!("{name}.npy")))?);
+            match data {
+                NpyData::U64(data) => write_npy(out, shape, data),
+                NpyData::F32(data) => write_npy(out, shape, data),
+                NpyData::F64(data) => write_npy(out, shape, data),
+            }
+        })
+    }
+}
+
+/// Typed reference to array data.
+enum NpyData<'a> {
+    U64(&'a [u64]),
+    F32(&'a [f32]),
+    F64(&'a [f64]),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npy_header() -> Result<()> {
+        let data = npy_bytes(&[2, 3], &[0f32; 6])?;
+        assert_eq!(&data[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([data[8], data[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(data.len(), 10 + header_len + 24);
+
+        let header = std::str::from_utf8(&data[10..10 + header_len])?;
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with(" \n"));
+
+        let header = std::str::from_utf8(&npy_bytes(&[4], &[0u64; 4])?[10..])?.to_string();
+        assert!(header.contains("'descr': '<u8'") && header.contains("'shape': (4,)"));
+
+        assert!(npy_bytes(&[2, 2], &[0f32; 3]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn npz_layout() -> Result<()> {
+        let mut dump = NpyDump::new();
+
+        let mut vectors = MotionVectors::new();
+        vectors.push((na::Point2::new(0.5, 0.5), na::Vector2::new(0.1, 0.0)));
+        vectors.push((na::Point2::new(0.2, 0.5), na::Vector2::new(0.0, 0.1)));
+        dump.add_vectors(0, Some(0.0), &vectors);
+        dump.add_vectors(1, None, &MotionVectors::new());
+        assert_eq!(dump.vector_offsets, [0, 2, 2]);
+
+        dump.add_field(1, None, &MotionField::new(3, 2))?;
+        assert!(dump.add_field(2, None, &MotionField::new(2, 3)).is_err());
+
+        let mut names = vec![];
+        dump.for_each_array(|name, shape, _| {
+            names.push((name.to_string(), shape.to_vec()));
+            Ok(())
+        })?;
+        assert!(names.contains(&("vectors_offset".into(), vec![3])));
+        assert!(names.contains(&("fields_motion".into(), vec![1, 2, 3, 2])));
+        assert!(!names.iter().any(|(n, _)| n.starts_with("trajectory")));
+
+        let npz = dump.write_npz(vec![])?;
+        assert_eq!(&npz[..4], &0x04034b50u32.to_le_bytes());
+
+        // End of central directory record lists all arrays.
+        let eocd = &npz[npz.len() - 22..];
+        assert_eq!(&eocd[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(
+            u16::from_le_bytes([eocd[10], eocd[11]]) as usize,
+            names.len()
+        );
+
+        Ok(())
+    }
+}
@@ -32,7 +32,7 @@ mod version {
 pub use version::RUSTC_VERSION;
 
 /// OFPS API version used to ensure compatibility.
-pub const API_VERSION: i32 = 1;
+pub const API_VERSION: i32 = 6;
 
 /// Plugin descriptor structure.
 ///
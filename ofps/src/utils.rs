@@ -2,9 +2,25 @@
 
 use anyhow::{anyhow, Result};
 use nalgebra as na;
-use std::io::Read;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::net::{TcpListener, TcpStream};
 
+/// Real scalar type of the core types.
+///
+/// Single precision is the default throughout the library, while double precision is available
+/// for estimators that need it, and for accumulating poses over long sequences.
+pub trait Real: na::RealField + Copy {}
+
+impl<T: na::RealField + Copy> Real for T {}
+
+/// Convert between real scalar types.
+///
+/// Conversion goes through `f64`, thus it is lossless, unless the target type is narrower.
+pub fn cast<T: Real, U: Real>(v: T) -> U {
+    na::convert(na::convert_unchecked::<T, f64>(v))
+}
+
 pub trait AsMutPtr {
     type Mut;
 
@@ -44,7 +60,7 @@ impl<T: AsMutPtr> AsMutPtr for *mut T {
 /// * `ab` - ground truth vector to be scaled against.
 /// * `bc` - vector going from ab to the end point. Its scale factor is computed.
 /// * `ac` - vector going directly from origin to the endpoint.
-pub fn triangulate_scale(ab: na::Vector3<f32>, bc: na::Vector3<f32>, ac: na::Vector3<f32>) -> f32 {
+pub fn triangulate_scale<T: Real>(ab: na::Vector3<T>, bc: na::Vector3<T>, ac: na::Vector3<T>) -> T {
     // P_bc = ab
     // Q_bc = P_bc + t_bc * bc
     //
@@ -85,11 +101,47 @@ pub fn triangulate_scale(ab: na::Vector3<f32>, bc: na::Vector3<f32>, ac: na::Vec
 
     let lu = lhs.lu();
 
-    lu.solve(&ab).map(|v| v.x).unwrap_or(1.0)
+    lu.solve(&ab).map(|v| v.x).unwrap_or_else(T::one)
+}
+
+/// Readable input stream that may support seeking.
+pub trait InputStream: Read + Send {
+    /// Get the stream as a seekable object, if it supports seeking.
+    fn as_seek(&mut self) -> Option<&mut dyn Seek> {
+        None
+    }
+
+    /// Check whether the stream supports seeking.
+    fn is_seekable(&self) -> bool {
+        false
+    }
+}
+
+impl InputStream for File {
+    fn as_seek(&mut self) -> Option<&mut dyn Seek> {
+        Some(self)
+    }
+
+    fn is_seekable(&self) -> bool {
+        true
+    }
+}
+
+impl InputStream for TcpStream {}
+
+/// Seeking on streams that do not support it fails with `ErrorKind::Unsupported`.
+impl Seek for dyn InputStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.as_seek()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "Stream is not seekable"))?
+            .seek(pos)
+    }
 }
 
 /// Open a file or an input stream.
-pub fn open_file(input: &str) -> Result<Box<dyn Read + Send>> {
+///
+/// Regular files are seekable, while network streams are not.
+pub fn open_file(input: &str) -> Result<Box<dyn InputStream>> {
     if input.starts_with("tcp://") {
         let input = input.strip_prefix("tcp://").expect("Cannot strip prefix");
         let (addr, port) = input
@@ -111,7 +163,7 @@ pub fn open_file(input: &str) -> Result<Box<dyn Read + Send>> {
 
         Ok(Box::new(stream))
     } else {
-        std::fs::File::open(input)
+        File::open(input)
             .map(|i| Box::new(i) as _)
             .map_err(Into::into)
     }
@@ -0,0 +1,193 @@
+//! # Homography decomposition
+//!
+//! Implementation of Faugeras' SVD based decomposition of a calibrated homography into camera
+//! motion and plane normal, as formulated in "An Invitation to 3-D Vision" by Ma et al.
+//!
+//! For points on a plane `n^T * X1 = d`, that move between cameras as `X2 = R * X1 + t`, the
+//! homography is `H = R + t * n^T / d`.
+
+use nalgebra as na;
+
+/// Single solution of homography decomposition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decomposition {
+    /// Rotation from the first camera to the second.
+    pub rotation: na::Rotation3<f64>,
+    /// Translation from the first camera to the second, divided by the distance to the plane.
+    pub translation: na::Vector3<f64>,
+    /// Unit normal of the plane in the first camera.
+    ///
+    /// The normal is zero, if there is no translation, since the plane is then unobservable.
+    pub normal: na::Vector3<f64>,
+}
+
+impl Decomposition {
+    /// Check whether a point on the plane is in front of both cameras.
+    ///
+    /// # Arguments
+    ///
+    /// * `f1` - ray of the point in the first camera.
+    /// * `f2` - ray of the point in the second camera.
+    pub fn is_visible(&self, f1: &na::Vector3<f64>, f2: &na::Vector3<f64>) -> bool {
+        if self.normal == na::Vector3::zeros() {
+            return true;
+        }
+
+        // Plane in the second camera is `n2^T * X2 = d * (1 + n2^T * t / d)`.
+        let n2 = self.rotation * self.normal;
+
+        self.normal.dot(f1) > 0.0 && (1.0 + n2.dot(&self.translation)) * n2.dot(f2) > 0.0
+    }
+
+    /// Get the plane normal in the second camera.
+    pub fn next_normal(&self) -> na::Vector3<f64> {
+        self.rotation * self.normal
+    }
+}
+
+/// Decompose a calibrated homography into camera motion and plane normal.
+///
+/// Returns all 4 candidate solutions, or 1, if there is no translation. Visibility of points
+/// narrows the candidates down to 2 solutions, see [`Decomposition::is_visible`].
+///
+/// The homography may be of arbitrary scale, but needs to be of the right sign, that is, rays of
+/// the points need to satisfy `f2^T * H * f1 > 0`.
+///
+/// # Arguments
+///
+/// * `h` - homography between rays of the cameras.
+pub fn decompose_homography(h: &na::Matrix3<f64>) -> Vec<Decomposition> {
+    let scale = h.singular_values()[1];
+
+    if scale <= 0.0 || !scale.is_finite() {
+        return vec![];
+    }
+
+    let h = h / scale;
+
+    let svd = (h.transpose() * h).svd(true, false);
+    let mut v = svd.u.unwrap();
+    let s = svd.singular_values;
+
+    if v.determinant() < 0.0 {
+        v = -v;
+    }
+
+    let (v1, v2, v3) = (v.column(0), v.column(1), v.column(2));
+
+    // Without translation, the homography is a pure rotation.
+    if s[0] - s[2] < 1e-9 {
+        return vec![Decomposition {
+            rotation: na::Rotation3::from_matrix(&h),
+            translation: na::Vector3::zeros(),
+            normal: na::Vector3::zeros(),
+        }];
+    }
+
+    let a = (1.0 - s[2]).max(0.0).sqrt();
+    let b = (s[0] - 1.0).max(0.0).sqrt();
+    let c = (s[0] - s[2]).sqrt();
+
+    let u1 = (v1 * a + v3 * b) / c;
+    let u2 = (v1 * a - v3 * b) / c;
+
+    [u1, u2]
+        .into_iter()
+        .flat_map(|u| {
+            let hv = h * v2;
+            let hu = h * u;
+
+            let basis = na::Matrix3::from_columns(&[v2.into_owned(), u, v2.cross(&u)]);
+            let image = na::Matrix3::from_columns(&[hv, hu, hv.cross(&hu)]);
+
+            let rotation = na::Rotation3::from_matrix_unchecked(image * basis.transpose());
+            let normal = v2.cross(&u);
+            let translation = (h - rotation.matrix()) * normal;
+
+            [
+                Decomposition {
+                    rotation,
+                    translation,
+                    normal,
+                },
+                Decomposition {
+                    rotation,
+                    translation: -translation,
+                    normal: -normal,
+                },
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truth() -> Decomposition {
+        Decomposition {
+            rotation: na::Rotation3::from_euler_angles(0.05, -0.02, 0.1),
+            translation: na::Vector3::new(0.1, 0.05, -0.2),
+            normal: na::Vector3::new(0.1, -0.2, 1.0).normalize(),
+        }
+    }
+
+    fn homography(d: &Decomposition) -> na::Matrix3<f64> {
+        d.rotation.matrix() + d.translation * d.normal.transpose()
+    }
+
+    fn rays(d: &Decomposition) -> Vec<(na::Vector3<f64>, na::Vector3<f64>)> {
+        // Points on the plane at unit distance.
+        [(0.1, 0.2), (-0.3, 0.1), (0.2, -0.3), (-0.2, -0.1)]
+            .into_iter()
+            .map(|(x, y)| na::Vector3::new(x, y, 1.0))
+            .map(|p| p / d.normal.dot(&p))
+            .map(|p| (p.normalize(), (d.rotation * p + d.translation).normalize()))
+            .collect()
+    }
+
+    fn same(a: &Decomposition, b: &Decomposition) -> bool {
+        (a.rotation.matrix() - b.rotation.matrix()).norm() < 1e-9
+            && (a.translation - b.translation).norm() < 1e-9
+            && (a.normal - b.normal).norm() < 1e-9
+    }
+
+    #[test]
+    fn decompose() {
+        let truth = truth();
+
+        // Scale does not matter.
+        let solutions = decompose_homography(&(homography(&truth) * 3.0));
+
+        assert_eq!(solutions.len(), 4);
+        assert!(solutions.iter().any(|s| same(s, &truth)));
+
+        for s in &solutions {
+            assert!((homography(s) - homography(&truth)).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn visibility() {
+        let truth = truth();
+        let rays = rays(&truth);
+
+        let visible = decompose_homography(&homography(&truth))
+            .into_iter()
+            .filter(|s| rays.iter().all(|(f1, f2)| s.is_visible(f1, f2)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(visible.len(), 2);
+        assert!(visible.iter().any(|s| same(s, &truth)));
+    }
+
+    #[test]
+    fn pure_rotation() {
+        let rotation = na::Rotation3::from_euler_angles(0.1, 0.2, -0.1);
+        let solutions = decompose_homography(&(rotation.matrix() * 0.5));
+
+        assert_eq!(solutions.len(), 1);
+        assert!((solutions[0].rotation.matrix() - rotation.matrix()).norm() < 1e-9);
+        assert_eq!(solutions[0].translation, na::Vector3::zeros());
+    }
+}
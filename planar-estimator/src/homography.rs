@@ -0,0 +1,386 @@
+//! # Homography estimation
+//!
+//! Normalised direct linear transform, robust estimation and Levenberg-Marquardt refinement of
+//! homographies between point correspondences.
+
+use nalgebra as na;
+use rand::seq::index::sample;
+
+/// Pair of corresponding points in the first and the second image.
+pub type Correspondence = (na::Point2<f64>, na::Point2<f64>);
+
+/// Compute the similarity transform that moves points' centroid to the origin and scales them to
+/// the average distance of `sqrt(2)`.
+fn normalisation(points: impl Iterator<Item = na::Point2<f64>> + Clone) -> na::Matrix3<f64> {
+    let (sum, count) = points
+        .clone()
+        .fold((na::Vector2::zeros(), 0), |(s, c), p| (s + p.coords, c + 1));
+    let centroid = sum / count.max(1) as f64;
+
+    let dist = points.map(|p| (p.coords - centroid).norm()).sum::<f64>() / count.max(1) as f64;
+    let scale = if dist > 0.0 {
+        std::f64::consts::SQRT_2 / dist
+    } else {
+        1.0
+    };
+
+    na::matrix![
+        scale, 0.0, -scale * centroid.x;
+        0.0, scale, -scale * centroid.y;
+        0.0, 0.0, 1.0
+    ]
+}
+
+/// Transform a point by a homography.
+pub fn transform(h: &na::Matrix3<f64>, p: &na::Point2<f64>) -> na::Point2<f64> {
+    let p = h * p.to_homogeneous();
+    na::Point2::new(p.x / p.z, p.y / p.z)
+}
+
+/// Compute the squared transfer error of a correspondence.
+///
+/// This is the squared distance between the second point and the first point transformed by
+/// the homography.
+pub fn transfer_error(h: &na::Matrix3<f64>, (p1, p2): &Correspondence) -> f64 {
+    (transform(h, p1) - p2).norm_squared()
+}
+
+/// Compute a homography using the normalised direct linear transform.
+///
+/// Returns a least squares solution if there are more than 4 correspondences. The result is
+/// normalised to unit norm.
+///
+/// # Arguments
+///
+/// * `points` - at least 4 point correspondences.
+pub fn find_homography(points: &[Correspondence]) -> Option<na::Matrix3<f64>> {
+    if points.len() < 4 {
+        return None;
+    }
+
+    let t1 = normalisation(points.iter().map(|(p, _)| *p));
+    let t2 = normalisation(points.iter().map(|(_, p)| *p));
+
+    // Accumulate the normal equations, so that the null space is computed even for minimal sets.
+    let mut ata = na::SMatrix::<f64, 9, 9>::zeros();
+
+    for (p1, p2) in points {
+        let p = t1 * p1.to_homogeneous();
+        let q = t2 * p2.to_homogeneous();
+
+        let rows = [
+            na::SVector::<f64, 9>::from_column_slice(&[
+                0.0,
+                0.0,
+                0.0,
+                -q.z * p.x,
+                -q.z * p.y,
+                -q.z * p.z,
+                q.y * p.x,
+                q.y * p.y,
+                q.y * p.z,
+            ]),
+            na::SVector::<f64, 9>::from_column_slice(&[
+                q.z * p.x,
+                q.z * p.y,
+                q.z * p.z,
+                0.0,
+                0.0,
+                0.0,
+                -q.x * p.x,
+                -q.x * p.y,
+                -q.x * p.z,
+            ]),
+        ];
+
+        for r in rows {
+            ata += r * r.transpose();
+        }
+    }
+
+    let v_t = ata.svd(false, true).v_t?;
+    let h = na::Matrix3::from_iterator(v_t.row(8).iter().copied()).transpose();
+    let h = t2.try_inverse()? * h * t1;
+
+    if h.iter().all(|v| v.is_finite()) && h.determinant().abs() > 1e-12 * h.norm().powi(3) {
+        Some(h.normalize())
+    } else {
+        None
+    }
+}
+
+/// Robust estimation method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Random sample consensus - maximise the number of points within the error threshold.
+    Ransac,
+    /// Least median of squares - minimise the median error, no threshold needed.
+    Lmeds,
+}
+
+/// Robust homography estimation parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct RobustParams {
+    /// Robust estimation method.
+    pub method: Method,
+    /// Maximum transfer error of inliers.
+    pub max_error: f64,
+    /// Desired probability of drawing at least one outlier-free sample.
+    pub confidence: f64,
+    /// Maximum number of samples drawn.
+    pub max_iters: usize,
+}
+
+/// Find the homography that agrees with the most correspondences.
+///
+/// Returns the homography and the mask of inliers. The homography is recomputed from all of the
+/// inliers, but not refined, see [`refine_homography`].
+///
+/// # Arguments
+///
+/// * `points` - point correspondences.
+/// * `params` - robust estimation parameters.
+pub fn find_homography_robust(
+    points: &[Correspondence],
+    params: &RobustParams,
+) -> Option<(na::Matrix3<f64>, Vec<bool>)> {
+    if points.len() < 4 {
+        return None;
+    }
+
+    let rng = &mut rand::thread_rng();
+    let threshold = params.max_error * params.max_error;
+    let log_confidence = (1.0 - params.confidence).ln();
+
+    let mut errors = vec![0.0; points.len()];
+    let mut best = None;
+    let mut best_score = f64::INFINITY;
+    let mut iters = params.max_iters;
+    let mut i = 0;
+
+    while i < iters {
+        i += 1;
+
+        let sample = sample(rng, points.len(), 4)
+            .into_iter()
+            .map(|i| points[i])
+            .collect::<Vec<_>>();
+
+        let h = match find_homography(&sample) {
+            Some(h) => h,
+            None => continue,
+        };
+
+        errors
+            .iter_mut()
+            .zip(points)
+            .for_each(|(e, p)| *e = transfer_error(&h, p));
+
+        let score = match params.method {
+            // Score with truncated errors, which ranks models with equal inlier count.
+            Method::Ransac => errors.iter().map(|e| e.min(threshold)).sum::<f64>(),
+            Method::Lmeds => median(&mut errors),
+        };
+
+        if score < best_score {
+            best_score = score;
+            best = Some(h);
+
+            if params.method == Method::Ransac {
+                // Stop early once an all-inlier sample has been drawn with desired confidence.
+                let inliers = errors.iter().filter(|e| **e < threshold).count();
+                let ratio = inliers as f64 / points.len() as f64;
+                let needed = log_confidence / (1.0 - ratio.powi(4)).ln();
+
+                if needed.is_finite() && needed >= 0.0 {
+                    iters = iters.min(needed.ceil() as usize);
+                }
+            }
+        }
+    }
+
+    let h = best?;
+
+    let threshold = match params.method {
+        Method::Ransac => threshold,
+        // Robust standard deviation estimate, as done by Rousseeuw, but never stricter than the
+        // error threshold.
+        Method::Lmeds => {
+            let sigma = 1.4826 * (1.0 + 5.0 / (points.len() - 3) as f64) * best_score.sqrt();
+            (2.5 * sigma).powi(2).max(threshold)
+        }
+    };
+
+    let is_inlier = |h, p| transfer_error(h, p) < threshold;
+
+    let inliers = points
+        .iter()
+        .filter(|p| is_inlier(&h, p))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let h = find_homography(&inliers).unwrap_or(h);
+    let mask = points.iter().map(|p| is_inlier(&h, p)).collect();
+
+    Some((h, mask))
+}
+
+/// Compute the median of values, reordering them in the process.
+fn median(values: &mut [f64]) -> f64 {
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1
+}
+
+/// Refine a homography by minimising transfer error.
+///
+/// Performs Levenberg-Marquardt optimisation over the first 8 entries of the homography, with
+/// the last one fixed to 1. The result is normalised to unit norm.
+///
+/// # Arguments
+///
+/// * `h` - initial homography.
+/// * `points` - inlier correspondences.
+/// * `iters` - maximum number of iterations.
+pub fn refine_homography(
+    h: na::Matrix3<f64>,
+    points: &[Correspondence],
+    iters: usize,
+) -> na::Matrix3<f64> {
+    if h[(2, 2)].abs() < 1e-9 * h.norm() {
+        return h;
+    }
+
+    let mut h = h / h[(2, 2)];
+
+    let cost = |h: &na::Matrix3<f64>| points.iter().map(|p| transfer_error(h, p)).sum::<f64>();
+
+    let mut current = cost(&h);
+    let mut lambda = 1e-3;
+
+    for _ in 0..iters {
+        let mut jtj = na::SMatrix::<f64, 8, 8>::zeros();
+        let mut jtr = na::SVector::<f64, 8>::zeros();
+
+        for (p1, p2) in points {
+            let p = h * p1.to_homogeneous();
+            let (u, v) = (p.x / p.z, p.y / p.z);
+            let (x, y, w) = (p1.x / p.z, p1.y / p.z, 1.0 / p.z);
+
+            let ju =
+                na::SVector::<f64, 8>::from_column_slice(&[x, y, w, 0.0, 0.0, 0.0, -u * x, -u * y]);
+            let jv =
+                na::SVector::<f64, 8>::from_column_slice(&[0.0, 0.0, 0.0, x, y, w, -v * x, -v * y]);
+
+            jtj += ju * ju.transpose() + jv * jv.transpose();
+            jtr += ju * (u - p2.x) + jv * (v - p2.y);
+        }
+
+        let improved = loop {
+            let damped = jtj + na::SMatrix::<f64, 8, 8>::from_diagonal(&jtj.diagonal()) * lambda;
+            let delta = match damped.cholesky() {
+                Some(c) => -c.solve(&jtr),
+                None => break false,
+            };
+
+            let mut h2 = h;
+            h2.iter_mut()
+                .zip([0, 3, 6, 1, 4, 7, 2, 5])
+                .for_each(|(h, i)| *h += delta[i]);
+            let new = cost(&h2);
+
+            if new < current {
+                let converged = current - new < current * 1e-10;
+                h = h2;
+                current = new;
+                lambda = (lambda * 0.1).max(1e-9);
+                break !converged;
+            }
+
+            lambda *= 10.0;
+
+            if lambda > 1e6 {
+                break false;
+            }
+        };
+
+        if !improved {
+            break;
+        }
+    }
+
+    h.normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truth() -> na::Matrix3<f64> {
+        na::matrix![
+            1.02, 0.03, 0.01;
+            -0.02, 0.98, -0.02;
+            0.05, -0.04, 1.0
+        ]
+    }
+
+    fn correspondences(h: &na::Matrix3<f64>) -> Vec<Correspondence> {
+        (0..10)
+            .flat_map(|x| (0..10).map(move |y| (x, y)))
+            .map(|(x, y)| na::Point2::new(x as f64 / 10.0 - 0.45, y as f64 / 10.0 - 0.45))
+            .map(|p| (p, transform(h, &p)))
+            .collect()
+    }
+
+    fn same(a: &na::Matrix3<f64>, b: &na::Matrix3<f64>) -> bool {
+        let (a, b) = (a.normalize(), b.normalize());
+        (a - b).norm() < 1e-6 || (a + b).norm() < 1e-6
+    }
+
+    #[test]
+    fn dlt() {
+        let h = truth();
+        let points = correspondences(&h);
+
+        assert!(same(&find_homography(&points).unwrap(), &h));
+        let corners = [points[0], points[9], points[90], points[99]];
+        assert!(same(&find_homography(&corners).unwrap(), &h));
+        assert!(find_homography(&points[..3]).is_none());
+    }
+
+    #[test]
+    fn robust() {
+        let h = truth();
+        let mut points = correspondences(&h);
+
+        for p in points.iter_mut().step_by(4) {
+            p.1 += na::Vector2::new(0.05, -0.1);
+        }
+
+        for method in [Method::Ransac, Method::Lmeds] {
+            let params = RobustParams {
+                method,
+                max_error: 0.001,
+                confidence: 0.999,
+                max_iters: 1000,
+            };
+
+            let (est, mask) = find_homography_robust(&points, &params).unwrap();
+
+            assert!(same(&est, &h), "{method:?}");
+            assert!(mask.iter().step_by(4).all(|v| !*v));
+            assert_eq!(mask.iter().filter(|v| **v).count(), 75);
+        }
+    }
+
+    #[test]
+    fn refine() {
+        let h = truth();
+        let points = correspondences(&h);
+
+        let initial = h + na::Matrix3::from_element(0.01);
+        assert!(!same(&initial, &h));
+
+        let refined = refine_homography(initial, &points, 20);
+        assert!(same(&refined, &h));
+    }
+}
@@ -0,0 +1,394 @@
+//! # Motion estimator built on native homography estimation.
+//!
+//! This estimator is a pure Rust alternative to the OpenCV based `homography` estimator. It finds
+//! the homography of the dominant plane in the scene, and decomposes it into camera motion.
+//!
+//! Homography decomposition is ambiguous - out of the candidate solutions, the ones placing
+//! points behind the cameras are rejected, and the rest are disambiguated by continuity of the
+//! plane normal between frames. On the first frame, the plane facing the camera most directly is
+//! assumed.
+
+use nalgebra as na;
+use ofps::frames::Frame;
+use ofps::prelude::v1::*;
+
+pub mod decomposition;
+pub mod homography;
+
+use decomposition::{decompose_homography, Decomposition};
+use homography::{find_homography_robust, refine_homography, Method, RobustParams};
+
+ofps::define_descriptor!(planar, Estimator, |_| Ok(Box::new(
+    PlanarEstimator::default()
+)));
+
+/// Native homography based camera estimator.
+///
+/// Translation is scaled to the `move_magnitude` hint. If no hint is given, translation is
+/// relative to the distance to the plane.
+pub struct PlanarEstimator {
+    desired_confidence: f32,
+    max_error: f32,
+    max_iters: usize,
+    refine_iters: usize,
+    use_ransac: bool,
+    /// Plane normal of the last estimate, in OpenGL camera space of the latest frame.
+    prev_normal: Option<na::Vector3<f64>>,
+}
+
+impl Properties for PlanarEstimator {
+    fn props_mut(&mut self) -> Vec<(&str, PropertyMut)> {
+        vec![
+            (
+                "Desired confidence",
+                PropertyMut::float(&mut self.desired_confidence, 0.0, 1.0),
+            ),
+            (
+                "Max error",
+                PropertyMut::float(&mut self.max_error, 0.00001, 0.1),
+            ),
+            (
+                "Max iters",
+                PropertyMut::usize(&mut self.max_iters, 1, 5000),
+            ),
+            (
+                "Refine iters",
+                PropertyMut::usize(&mut self.refine_iters, 0, 100),
+            ),
+            ("Use ransac", PropertyMut::bool(&mut self.use_ransac)),
+        ]
+    }
+}
+
+impl PlanarEstimator {
+    pub fn desired_confidence(self, desired_confidence: f32) -> Self {
+        Self {
+            desired_confidence,
+            ..self
+        }
+    }
+
+    pub fn max_error(self, max_error: f32) -> Self {
+        Self { max_error, ..self }
+    }
+
+    pub fn max_iters(self, max_iters: usize) -> Self {
+        Self { max_iters, ..self }
+    }
+
+    pub fn refine_iters(self, refine_iters: usize) -> Self {
+        Self {
+            refine_iters,
+            ..self
+        }
+    }
+
+    pub fn use_ransac(self, use_ransac: bool) -> Self {
+        Self { use_ransac, ..self }
+    }
+}
+
+impl Default for PlanarEstimator {
+    fn default() -> Self {
+        Self {
+            desired_confidence: 0.997,
+            max_error: 0.001,
+            max_iters: 2000,
+            refine_iters: 20,
+            use_ransac: true,
+            prev_normal: None,
+        }
+    }
+}
+
+impl PlanarEstimator {
+    /// Pick the decomposition that best explains the motion.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidates` - decompositions of the homography.
+    /// * `rays` - ray pairs of the inliers.
+    fn select(
+        &self,
+        candidates: Vec<Decomposition>,
+        rays: &[(na::Vector3<f64>, na::Vector3<f64>)],
+    ) -> Option<Decomposition> {
+        let visible =
+            |d: &Decomposition| rays.iter().filter(|(f1, f2)| d.is_visible(f1, f2)).count();
+
+        let max_visible = candidates.iter().map(visible).max()?;
+
+        // Compare against the last plane, or the viewing direction, if there is none.
+        let reference = self
+            .prev_normal
+            .unwrap_or_else(|| rays.iter().map(|(f1, _)| f1).sum());
+
+        candidates
+            .into_iter()
+            .filter(|d| visible(d) == max_visible)
+            .max_by(|a, b| {
+                a.normal
+                    .dot(&reference)
+                    .total_cmp(&b.normal.dot(&reference))
+            })
+    }
+}
+
+impl Estimator for PlanarEstimator {
+    fn estimate(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f32>, na::Vector3<f32>)> {
+        let (r, tr) = self.estimate_f64(motion_vectors, camera, move_magnitude)?;
+        Ok((r.cast(), tr.cast()))
+    }
+
+    fn estimate_f64(
+        &mut self,
+        motion_vectors: &MotionVectors,
+        camera: &dyn CameraModel,
+        move_magnitude: Option<f32>,
+    ) -> Result<(na::UnitQuaternion<f64>, na::Vector3<f64>)> {
+        let motion_vectors = camera.undistort_vectors(motion_vectors);
+
+        let inv_view = na::Matrix4::identity();
+        let ray = |p| {
+            camera
+                .unproject(p, inv_view)
+                .coords
+                .cast::<f64>()
+                .normalize()
+        };
+
+        // Only rays in front of the camera can be put on the image plane.
+        let rays = motion_vectors
+            .entries()
+            .map(|(pos, motion)| (ray(pos), ray(pos + motion)))
+            .filter(|(f1, f2)| f1.z < 0.0 && f2.z < 0.0)
+            .collect::<Vec<_>>();
+
+        // Rays are in OpenGL camera space, where the camera looks towards -z. The image plane is
+        // thus at z = -1, and flipping z maps between the two.
+        let flip = na::Matrix3::from_diagonal(&na::Vector3::new(1.0, 1.0, -1.0));
+        let to_image = |f: &na::Vector3<f64>| na::Point2::new(f.x / -f.z, f.y / -f.z);
+
+        let points = rays
+            .iter()
+            .map(|(f1, f2)| (to_image(f1), to_image(f2)))
+            .collect::<Vec<_>>();
+
+        let params = RobustParams {
+            method: if self.use_ransac {
+                Method::Ransac
+            } else {
+                Method::Lmeds
+            },
+            // Convert screen-space error to the image plane, as seen at the centre of the screen.
+            max_error: self.max_error as f64 / camera.intrinsics()[(1, 1)] as f64,
+            confidence: self.desired_confidence as f64,
+            max_iters: self.max_iters,
+        };
+
+        let (h, inliers) = find_homography_robust(&points, &params)
+            .ok_or_else(|| anyhow!("failed to compute homography"))?;
+
+        let (points, rays): (Vec<_>, Vec<_>) = points
+            .into_iter()
+            .zip(rays)
+            .zip(inliers)
+            .filter(|(_, inlier)| *inlier)
+            .map(|(v, _)| v)
+            .unzip();
+
+        let h = refine_homography(h, &points, self.refine_iters);
+        let h = flip * h * flip;
+
+        // Homography is only known up to scale, which may also be negative.
+        let positive = rays.iter().filter(|(f1, f2)| f2.dot(&(h * f1)) > 0.0);
+        let h = if positive.count() * 2 >= rays.len() {
+            h
+        } else {
+            -h
+        };
+
+        let decomposition = self
+            .select(decompose_homography(&h), &rays)
+            .ok_or_else(|| anyhow!("failed to decompose homography"))?;
+
+        if decomposition.normal != na::Vector3::zeros() {
+            self.prev_normal = Some(decomposition.next_normal());
+        }
+
+        let r = decomposition.rotation;
+        let t = decomposition.translation;
+
+        let t = match move_magnitude {
+            Some(m) if t.norm() > 0.0 => t.normalize() * m as f64,
+            _ => t,
+        };
+
+        // Points transform from the first camera to the second, while we need the motion of the
+        // camera itself.
+        let rot = na::UnitQuaternion::from_rotation_matrix(&r.inverse());
+        let tr = -(r.inverse() * t);
+
+        Ok((
+            Frame::OpenGl.convert_rotation(Frame::Body, rot),
+            Frame::OpenGl.convert_translation(Frame::Body, tr),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofps::frames;
+
+    /// Generate motion vectors of a camera moving in front of a plane.
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - camera to project points with.
+    /// * `normal` - normal of the plane, facing the camera.
+    /// * `from` - starting pose of the camera.
+    /// * `to` - final pose of the camera.
+    fn calc_field(
+        camera: &dyn CameraModel,
+        normal: na::Vector3<f32>,
+        from: (na::UnitQuaternion<f32>, na::Point3<f32>),
+        to: (na::UnitQuaternion<f32>, na::Point3<f32>),
+    ) -> MotionVectors {
+        let view = frames::view_matrix(from.1, from.0);
+        let inv_view = view.try_inverse().unwrap();
+        let next_view = frames::view_matrix(to.1, to.0);
+
+        // Plane at distance of 4 from the origin.
+        let normal = normal.normalize();
+
+        (1..20)
+            .flat_map(|x| (1..20).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let p = na::Point2::new(x as f32 / 20.0, y as f32 / 20.0);
+                let dir = camera.unproject(p, inv_view) - from.1;
+                let depth = (4.0 - normal.dot(&from.1.coords)) / normal.dot(&dir);
+                let next = camera.project(from.1 + dir * depth, next_view);
+                (p, next - p)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_motion() {
+        let camera = StandardCamera::new(16.0 / 9.0, 60.0);
+
+        // Without prior frames, the plane is assumed to face the camera.
+        let normal = na::Vector3::y();
+
+        let motions = [
+            (frames::from_roll_pitch_yaw(0.0, 0.0, 0.0), [0.0, 0.2, 0.0]),
+            (frames::from_roll_pitch_yaw(0.0, 0.0, 0.05), [0.1, 0.0, 0.0]),
+            (
+                frames::from_roll_pitch_yaw(0.02, -0.03, 0.01),
+                [0.1, 0.1, 0.05],
+            ),
+            (
+                frames::from_roll_pitch_yaw(-0.04, 0.02, 0.03),
+                [0.0, -0.1, 0.1],
+            ),
+        ];
+
+        for use_ransac in [true, false] {
+            for (rot, pos) in motions {
+                let pos = na::Point3::from(pos);
+                let start = (Default::default(), Default::default());
+                let field = calc_field(&camera, normal, start, (rot, pos));
+
+                let mut estimator = PlanarEstimator::default()
+                    .use_ransac(use_ransac)
+                    .max_iters(500);
+                let (r, tr) = estimator.estimate(&field, &camera, Some(2.0)).unwrap();
+
+                assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+                assert!(
+                    (tr - pos.coords.normalize() * 2.0).norm() < 0.05,
+                    "{tr} {pos}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn estimate_rotation() {
+        let camera = StandardCamera::new(1.0, 90.0);
+        let rot = frames::from_roll_pitch_yaw(0.01, 0.02, -0.03);
+        let start = (Default::default(), Default::default());
+        let field = calc_field(&camera, na::Vector3::y(), start, (rot, Default::default()));
+
+        let (r, tr) = PlanarEstimator::default()
+            .estimate(&field, &camera, None)
+            .unwrap();
+
+        assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+        assert!(tr.norm() < 1e-3, "{tr}");
+    }
+
+    #[test]
+    fn relative_translation() {
+        let camera = StandardCamera::new(1.0, 75.0);
+        let pos = na::Point3::new(0.1, 0.2, -0.1);
+        let start = (Default::default(), Default::default());
+        let field = calc_field(&camera, na::Vector3::y(), start, (Default::default(), pos));
+
+        let (_, tr) = PlanarEstimator::default()
+            .estimate(&field, &camera, None)
+            .unwrap();
+
+        // Plane is at distance of 4.
+        assert!((tr - pos.coords / 4.0).norm() < 1e-3, "{tr}");
+    }
+
+    #[test]
+    fn normal_continuity() {
+        let camera = StandardCamera::new(1.0, 75.0);
+
+        // Plane tilted away from the camera, which is not the first guess.
+        let normal = na::Vector3::new(0.6, 1.0, 0.5);
+
+        let poses = [
+            (na::UnitQuaternion::identity(), na::Point3::origin()),
+            (
+                frames::from_roll_pitch_yaw(0.0, 0.01, 0.02),
+                na::Point3::new(0.1, 0.1, 0.0),
+            ),
+            (
+                frames::from_roll_pitch_yaw(0.0, 0.02, 0.03),
+                na::Point3::new(0.2, 0.1, 0.1),
+            ),
+        ];
+
+        let mut estimator = PlanarEstimator::default();
+
+        // Give the estimator the true plane.
+        let prev_normal =
+            frames::Frame::Body.convert_translation(Frame::OpenGl, normal.normalize());
+        estimator.prev_normal = Some(prev_normal.cast());
+
+        for w in poses.windows(2) {
+            let field = calc_field(&camera, normal, w[0], w[1]);
+            let (r, tr) = estimator.estimate(&field, &camera, Some(1.0)).unwrap();
+
+            let rot = w[0].0.inverse() * w[1].0;
+            let pos = w[0].0.inverse() * (w[1].1 - w[0].1);
+
+            assert!(r.angle_to(&rot) < 0.002, "{r:?} {rot:?}");
+            assert!((tr - pos.normalize()).norm() < 0.05, "{tr} {pos}");
+
+            let expected = w[1].0.inverse() * normal.normalize();
+            let expected = Frame::Body.convert_translation(Frame::OpenGl, expected);
+            let normal = estimator.prev_normal.unwrap().cast::<f32>();
+            assert!((normal - expected).norm() < 0.01, "{normal} {expected}");
+        }
+    }
+}